@@ -1,11 +1,70 @@
 use serde::{Deserialize, Serialize};
 
+/// User Command for telling the server the version of the protocol the client speaks and the capabilities it supports.
+/// Sent before logging in, the server replies with its own version and capabilities, or rejects the client.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HelloCommand {
+    /// The version of the protocol the client speaks, see [crate::protocol::PROTOCOL_VERSION].
+    #[serde(rename = "v")]
+    pub version: u32,
+    /// The capabilities the client supports, see [crate::protocol::capability].
+    #[serde(rename = "cs", default)]
+    pub capabilities: Vec<String>,
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// User Command for creating a new account.
+/// The session is logged in with the new account once it is created.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegisterCommand {
+    /// The name of the account, which becomes the id of the user.
+    #[serde(rename = "u")]
+    pub username: String,
+    /// The password of the account.
+    #[serde(rename = "p")]
+    pub password: String,
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// User Command for logging in with an existing account.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoginCommand {
+    /// The name of the account.
+    #[serde(rename = "u")]
+    pub username: String,
+    /// The password of the account.
+    #[serde(rename = "p")]
+    pub password: String,
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// User Command for resuming a session the connection of which was lost, instead of logging in.
+/// The session keeps the rooms it had joined, and the events it has missed are sent after the reply.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResumeSessionCommand {
+    /// The resume token handed out by the server on the last login or resume of the session.
+    #[serde(rename = "t")]
+    pub token: String,
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
 /// User Command for joining a room.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct JoinRoomCommand {
     // The room to join.
     #[serde(rename = "r")]
     pub room: String,
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
 /// User Command for leaving a room.
@@ -14,6 +73,9 @@ pub struct LeaveRoomCommand {
     // The room to leave.
     #[serde(rename = "r")]
     pub room: String,
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
 /// User Command for sending a message.
@@ -25,6 +87,9 @@ pub struct SendMessageCommand {
     // The content of the message.
     #[serde(rename = "c")]
     pub content: String,
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
 /// User Command for retrieving chat history of a room.
@@ -33,25 +98,260 @@ pub struct GetHistoryCommand {
     /// The room to retrieve history from.
     #[serde(rename = "r")]
     pub room: String,
+    /// Cursor for pagination, only messages older than the message with this id are returned.
+    /// Starts from the most recent message when omitted.
+    #[serde(rename = "b", default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<u64>,
+    /// Maximum number of messages to return, the server applies its own default and upper bound.
+    #[serde(rename = "l", default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
+/// User Command for creating a new room, owned by the user.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CreateRoomCommand {
+    /// The slug of the new room.
+    #[serde(rename = "r")]
+    pub room: String,
+    /// The description of the new room.
+    #[serde(rename = "d")]
+    pub description: String,
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
 
-/// User Command for quitting the whole chat session.
+/// User Command for deleting a room the user owns, all of its participants are removed from it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeleteRoomCommand {
+    /// The slug of the room to delete.
+    #[serde(rename = "r")]
+    pub room: String,
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// User Command for sending a message to a single user, delivered to all of their sessions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SendDirectMessageCommand {
+    /// The id of the user to send the message to.
+    #[serde(rename = "u")]
+    pub user_id: String,
+    /// The content of the message.
+    #[serde(rename = "c")]
+    pub content: String,
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// User Command for removing a user from a room, only the owner and the moderators of the room can kick its members.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KickUserCommand {
+    /// The slug of the room.
+    #[serde(rename = "r")]
+    pub room: String,
+    /// The id of the user the command targets.
+    #[serde(rename = "u")]
+    pub user_id: String,
+    /// Seconds the user can not join the room again for, the user can join again right away if not set.
+    #[serde(rename = "d", default, skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<u64>,
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// User Command for removing a user from a room and refusing them to join it again.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub struct QuitCommand;
+pub struct BanUserCommand {
+    /// The slug of the room.
+    #[serde(rename = "r")]
+    pub room: String,
+    /// The id of the user the command targets.
+    #[serde(rename = "u")]
+    pub user_id: String,
+    /// Seconds the ban lasts for, the ban lasts until the user is unbanned if not set.
+    #[serde(rename = "d", default, skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<u64>,
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// User Command for lifting the ban of a user from a room.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnbanUserCommand {
+    /// The slug of the room.
+    #[serde(rename = "r")]
+    pub room: String,
+    /// The id of the user the command targets.
+    #[serde(rename = "u")]
+    pub user_id: String,
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// User Command for refusing the messages a user sends to a room, the user stays in the room.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MuteUserCommand {
+    /// The slug of the room.
+    #[serde(rename = "r")]
+    pub room: String,
+    /// The id of the user the command targets.
+    #[serde(rename = "u")]
+    pub user_id: String,
+    /// Seconds the mute lasts for, the mute lasts until the user is unmuted if not set.
+    #[serde(rename = "d", default, skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<u64>,
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// User Command for lifting the mute of a user in a room.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnmuteUserCommand {
+    /// The slug of the room.
+    #[serde(rename = "r")]
+    pub room: String,
+    /// The id of the user the command targets.
+    #[serde(rename = "u")]
+    pub user_id: String,
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// User Command for telling the participants of a room that the user has started typing a message.
+/// It is sent again every few seconds while the user keeps typing, the typing ends on its own otherwise.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TypingStartedCommand {
+    /// The slug of the room.
+    #[serde(rename = "r")]
+    pub room: String,
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// User Command for telling the participants of a room that the user has stopped typing without sending a message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TypingStoppedCommand {
+    /// The slug of the room.
+    #[serde(rename = "r")]
+    pub room: String,
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// User Command for changing the name other users see for the user.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SetNicknameCommand {
+    /// The new nickname, unique among all users.
+    #[serde(rename = "n")]
+    pub nickname: String,
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// User Command for checking that the connection is alive, the server replies with a pong.
+/// Used by clients to measure the round-trip latency.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PingCommand {
+    /// Opaque value chosen by the client, echoed back on the pong, e.g. the time the ping was sent at
+    #[serde(rename = "t")]
+    pub timestamp: u64,
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// User Command for answering a ping of the server, which it sends when the connection has been idle.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PongCommand {
+    /// The value of the ping this pong answers
+    #[serde(rename = "t")]
+    pub timestamp: u64,
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// User Command for quitting the whole chat session.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct QuitCommand {
+    /// Optional client chosen id, echoed back on the replies and errors caused by this command.
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
 
 /// A user command which can be sent to the server by a single user session.
 /// All commands are processed in the context of the chat server paired with an individual user session.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "_ct", rename_all = "snake_case")]
 pub enum UserCommand {
+    Hello(HelloCommand),
+    Register(RegisterCommand),
+    Login(LoginCommand),
+    ResumeSession(ResumeSessionCommand),
     JoinRoom(JoinRoomCommand),
     LeaveRoom(LeaveRoomCommand),
     SendMessage(SendMessageCommand),
     GetHistory(GetHistoryCommand),
+    SetNickname(SetNicknameCommand),
+    SendDirectMessage(SendDirectMessageCommand),
+    CreateRoom(CreateRoomCommand),
+    DeleteRoom(DeleteRoomCommand),
+    KickUser(KickUserCommand),
+    BanUser(BanUserCommand),
+    UnbanUser(UnbanUserCommand),
+    MuteUser(MuteUserCommand),
+    UnmuteUser(UnmuteUserCommand),
+    TypingStarted(TypingStartedCommand),
+    TypingStopped(TypingStoppedCommand),
+    Ping(PingCommand),
+    Pong(PongCommand),
     Quit(QuitCommand),
 }
 
+impl UserCommand {
+    /// The client chosen request id of the command, if any
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            UserCommand::Hello(cmd) => cmd.request_id.as_deref(),
+            UserCommand::Register(cmd) => cmd.request_id.as_deref(),
+            UserCommand::Login(cmd) => cmd.request_id.as_deref(),
+            UserCommand::ResumeSession(cmd) => cmd.request_id.as_deref(),
+            UserCommand::JoinRoom(cmd) => cmd.request_id.as_deref(),
+            UserCommand::LeaveRoom(cmd) => cmd.request_id.as_deref(),
+            UserCommand::SendMessage(cmd) => cmd.request_id.as_deref(),
+            UserCommand::GetHistory(cmd) => cmd.request_id.as_deref(),
+            UserCommand::SetNickname(cmd) => cmd.request_id.as_deref(),
+            UserCommand::SendDirectMessage(cmd) => cmd.request_id.as_deref(),
+            UserCommand::CreateRoom(cmd) => cmd.request_id.as_deref(),
+            UserCommand::DeleteRoom(cmd) => cmd.request_id.as_deref(),
+            UserCommand::KickUser(cmd) => cmd.request_id.as_deref(),
+            UserCommand::BanUser(cmd) => cmd.request_id.as_deref(),
+            UserCommand::UnbanUser(cmd) => cmd.request_id.as_deref(),
+            UserCommand::MuteUser(cmd) => cmd.request_id.as_deref(),
+            UserCommand::UnmuteUser(cmd) => cmd.request_id.as_deref(),
+            UserCommand::TypingStarted(cmd) => cmd.request_id.as_deref(),
+            UserCommand::TypingStopped(cmd) => cmd.request_id.as_deref(),
+            UserCommand::Ping(cmd) => cmd.request_id.as_deref(),
+            UserCommand::Pong(cmd) => cmd.request_id.as_deref(),
+            UserCommand::Quit(cmd) => cmd.request_id.as_deref(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -64,48 +364,296 @@ mod tests {
         assert_eq!(deserialized, *command);
     }
 
+    #[test]
+    fn test_register_command() {
+        let command = UserCommand::Register(RegisterCommand {
+            username: "alice".to_string(),
+            password: "secret".to_string(),
+            request_id: None,
+        });
+
+        assert_command_serialization(&command, r#"{"_ct":"register","u":"alice","p":"secret"}"#);
+    }
+
+    #[test]
+    fn test_login_command() {
+        let command = UserCommand::Login(LoginCommand {
+            username: "alice".to_string(),
+            password: "secret".to_string(),
+            request_id: Some("req-1".to_string()),
+        });
+
+        assert_command_serialization(
+            &command,
+            r#"{"_ct":"login","u":"alice","p":"secret","rid":"req-1"}"#,
+        );
+        assert_eq!(command.request_id(), Some("req-1"));
+    }
+
+    #[test]
+    fn test_resume_session_command() {
+        let command = UserCommand::ResumeSession(ResumeSessionCommand {
+            token: "token-1".to_string(),
+            request_id: Some("req-1".to_string()),
+        });
+
+        assert_command_serialization(
+            &command,
+            r#"{"_ct":"resume_session","t":"token-1","rid":"req-1"}"#,
+        );
+        assert_eq!(command.request_id(), Some("req-1"));
+    }
+
     #[test]
     fn test_join_command() {
         let command = UserCommand::JoinRoom(JoinRoomCommand {
             room: "test".to_string(),
+            request_id: None,
         });
 
         assert_command_serialization(&command, r#"{"_ct":"join_room","r":"test"}"#);
     }
 
+    #[test]
+    fn test_join_command_with_request_id() {
+        let command = UserCommand::JoinRoom(JoinRoomCommand {
+            room: "test".to_string(),
+            request_id: Some("req-1".to_string()),
+        });
+
+        assert_command_serialization(&command, r#"{"_ct":"join_room","r":"test","rid":"req-1"}"#);
+        assert_eq!(command.request_id(), Some("req-1"));
+    }
+
     #[test]
     fn test_leave_command() {
         let command = UserCommand::LeaveRoom(LeaveRoomCommand {
             room: "test".to_string(),
+            request_id: None,
         });
 
         assert_command_serialization(&command, r#"{"_ct":"leave_room","r":"test"}"#);
     }
 
+    #[test]
+    fn test_leave_command_with_request_id() {
+        let command = UserCommand::LeaveRoom(LeaveRoomCommand {
+            room: "test".to_string(),
+            request_id: Some("req-1".to_string()),
+        });
+
+        assert_command_serialization(&command, r#"{"_ct":"leave_room","r":"test","rid":"req-1"}"#);
+    }
+
     #[test]
     fn test_message_command() {
         let command = UserCommand::SendMessage(SendMessageCommand {
             room: "test".to_string(),
             content: "test".to_string(),
+            request_id: None,
         });
 
         assert_command_serialization(&command, r#"{"_ct":"send_message","r":"test","c":"test"}"#);
     }
 
+    #[test]
+    fn test_message_command_with_request_id() {
+        let command = UserCommand::SendMessage(SendMessageCommand {
+            room: "test".to_string(),
+            content: "test".to_string(),
+            request_id: Some("req-1".to_string()),
+        });
+
+        assert_command_serialization(
+            &command,
+            r#"{"_ct":"send_message","r":"test","c":"test","rid":"req-1"}"#,
+        );
+    }
+
+    #[test]
+    fn test_set_nickname_command() {
+        let command = UserCommand::SetNickname(SetNicknameCommand {
+            nickname: "alice".to_string(),
+            request_id: None,
+        });
+
+        assert_command_serialization(&command, r#"{"_ct":"set_nickname","n":"alice"}"#);
+    }
+
+    #[test]
+    fn test_send_direct_message_command() {
+        let command = UserCommand::SendDirectMessage(SendDirectMessageCommand {
+            user_id: "alice".to_string(),
+            content: "hello".to_string(),
+            request_id: None,
+        });
+
+        assert_command_serialization(
+            &command,
+            r#"{"_ct":"send_direct_message","u":"alice","c":"hello"}"#,
+        );
+    }
+
+    #[test]
+    fn test_create_room_command() {
+        let command = UserCommand::CreateRoom(CreateRoomCommand {
+            room: "test".to_string(),
+            description: "desc".to_string(),
+            request_id: None,
+        });
+
+        assert_command_serialization(&command, r#"{"_ct":"create_room","r":"test","d":"desc"}"#);
+    }
+
+    #[test]
+    fn test_delete_room_command() {
+        let command = UserCommand::DeleteRoom(DeleteRoomCommand {
+            room: "test".to_string(),
+            request_id: Some("req-1".to_string()),
+        });
+
+        assert_command_serialization(
+            &command,
+            r#"{"_ct":"delete_room","r":"test","rid":"req-1"}"#,
+        );
+    }
+
+    #[test]
+    fn test_moderation_commands() {
+        let command = UserCommand::KickUser(KickUserCommand {
+            room: "test".to_string(),
+            user_id: "bob".to_string(),
+            duration_secs: None,
+            request_id: None,
+        });
+        assert_command_serialization(&command, r#"{"_ct":"kick_user","r":"test","u":"bob"}"#);
+
+        let command = UserCommand::BanUser(BanUserCommand {
+            room: "test".to_string(),
+            user_id: "bob".to_string(),
+            duration_secs: Some(60),
+            request_id: Some("req-1".to_string()),
+        });
+        assert_command_serialization(
+            &command,
+            r#"{"_ct":"ban_user","r":"test","u":"bob","d":60,"rid":"req-1"}"#,
+        );
+
+        let command = UserCommand::UnbanUser(UnbanUserCommand {
+            room: "test".to_string(),
+            user_id: "bob".to_string(),
+            request_id: None,
+        });
+        assert_command_serialization(&command, r#"{"_ct":"unban_user","r":"test","u":"bob"}"#);
+
+        let command = UserCommand::MuteUser(MuteUserCommand {
+            room: "test".to_string(),
+            user_id: "bob".to_string(),
+            duration_secs: Some(300),
+            request_id: None,
+        });
+        assert_command_serialization(
+            &command,
+            r#"{"_ct":"mute_user","r":"test","u":"bob","d":300}"#,
+        );
+
+        let command = UserCommand::UnmuteUser(UnmuteUserCommand {
+            room: "test".to_string(),
+            user_id: "bob".to_string(),
+            request_id: None,
+        });
+        assert_command_serialization(&command, r#"{"_ct":"unmute_user","r":"test","u":"bob"}"#);
+    }
+
+    #[test]
+    fn test_typing_commands() {
+        let command = UserCommand::TypingStarted(TypingStartedCommand {
+            room: "test".to_string(),
+            request_id: None,
+        });
+        assert_command_serialization(&command, r#"{"_ct":"typing_started","r":"test"}"#);
+
+        let command = UserCommand::TypingStopped(TypingStoppedCommand {
+            room: "test".to_string(),
+            request_id: Some("req-1".to_string()),
+        });
+        assert_command_serialization(
+            &command,
+            r#"{"_ct":"typing_stopped","r":"test","rid":"req-1"}"#,
+        );
+    }
+
+    #[test]
+    fn test_hello_command() {
+        let command = UserCommand::Hello(HelloCommand {
+            version: 1,
+            capabilities: vec!["history".to_string()],
+            request_id: Some("req-1".to_string()),
+        });
+
+        assert_command_serialization(
+            &command,
+            r#"{"_ct":"hello","v":1,"cs":["history"],"rid":"req-1"}"#,
+        );
+        assert_eq!(command.request_id(), Some("req-1"));
+    }
+
+    #[test]
+    fn test_ping_and_pong_commands() {
+        let command = UserCommand::Ping(PingCommand {
+            timestamp: 1700000000000,
+            request_id: None,
+        });
+        assert_command_serialization(&command, r#"{"_ct":"ping","t":1700000000000}"#);
+
+        let command = UserCommand::Pong(PongCommand {
+            timestamp: 1700000000000,
+            request_id: None,
+        });
+        assert_command_serialization(&command, r#"{"_ct":"pong","t":1700000000000}"#);
+    }
+
     #[test]
     fn test_quit_command() {
-        let command = UserCommand::Quit(QuitCommand);
+        let command = UserCommand::Quit(QuitCommand::default());
 
         assert_command_serialization(&command, r#"{"_ct":"quit"}"#);
+        assert_eq!(command.request_id(), None);
+    }
+
+    #[test]
+    fn test_quit_command_with_request_id() {
+        let command = UserCommand::Quit(QuitCommand {
+            request_id: Some("req-1".to_string()),
+        });
+
+        assert_command_serialization(&command, r#"{"_ct":"quit","rid":"req-1"}"#);
     }
 
     #[test]
     fn test_get_history_command() {
         let command = UserCommand::GetHistory(GetHistoryCommand {
             room: "test".to_string(),
+            before: None,
+            limit: None,
+            request_id: None,
         });
 
         assert_command_serialization(&command, r#"{"_ct":"get_history","r":"test"}"#);
     }
 
+    #[test]
+    fn test_get_history_command_with_cursor() {
+        let command = UserCommand::GetHistory(GetHistoryCommand {
+            room: "test".to_string(),
+            before: Some(42),
+            limit: Some(20),
+            request_id: Some("req-1".to_string()),
+        });
+
+        assert_command_serialization(
+            &command,
+            r#"{"_ct":"get_history","r":"test","b":42,"l":20,"rid":"req-1"}"#,
+        );
+    }
 }
@@ -11,6 +11,17 @@ pub struct RoomDetail {
     pub description: String,
 }
 
+/// The identity of a user together with the name to display for them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserDetail {
+    /// The id of the user
+    #[serde(rename = "u")]
+    pub user_id: String,
+    /// The nickname of the user, or their id if they have not set one
+    #[serde(rename = "dn")]
+    pub display_name: String,
+}
+
 /// A user has successfully logged in
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct LoginSuccessfulReplyEvent {
@@ -20,9 +31,44 @@ pub struct LoginSuccessfulReplyEvent {
     /// The id of the user that has logged in
     #[serde(rename = "u")]
     pub user_id: String,
+    /// The name to display for the user that has logged in
+    #[serde(rename = "dn")]
+    pub display_name: String,
     /// The list of rooms the user can participate, unique and ordered
     #[serde(rename = "rs")]
     pub rooms: Vec<RoomDetail>,
+    /// The token to resume the session with after the connection is lost, if the server keeps sessions for it
+    #[serde(rename = "rt", default, skip_serializing_if = "Option::is_none")]
+    pub resume_token: Option<String>,
+    /// The request id of the command this event is a reply to, if the client has set one
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// A reply to the user when they have resumed a session, the events the session has missed follow it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionResumedReplyEvent {
+    /// The id of the resumed session
+    #[serde(rename = "s")]
+    pub session_id: String,
+    /// The id of the user of the session
+    #[serde(rename = "u")]
+    pub user_id: String,
+    /// The name to display for the user of the session
+    #[serde(rename = "dn")]
+    pub display_name: String,
+    /// The list of rooms the user can participate, unique and ordered
+    #[serde(rename = "rs")]
+    pub rooms: Vec<RoomDetail>,
+    /// The rooms the session is still participating in
+    #[serde(rename = "jrs")]
+    pub joined_rooms: Vec<String>,
+    /// The token to resume the session with the next time, the previous one can not be used again
+    #[serde(rename = "rt")]
+    pub resume_token: String,
+    /// The request id of the command this event is a reply to, if the client has set one
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
 /// Users new room participation status
@@ -42,6 +88,9 @@ pub struct RoomParticipationBroadcastEvent {
     /// The id of the user that has joined or left
     #[serde(rename = "u")]
     pub user_id: String,
+    /// The name to display for the user that has joined or left
+    #[serde(rename = "dn")]
+    pub display_name: String,
     /// The new status of the user in the room
     #[serde(rename = "s")]
     pub status: RoomParticipationStatus,
@@ -55,12 +104,21 @@ pub struct UserJoinedRoomReplyEvent {
     pub room: String,
     /// The users currently in the room, unique and ordered
     #[serde(rename = "us")]
-    pub users: Vec<String>,
+    pub users: Vec<UserDetail>,
+    /// The request id of the command this event is a reply to, if the client has set one
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
 /// A user has sent a message to a room
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct UserMessageBroadcastEvent {
+    /// The id of the message, unique and monotonically increasing within the room
+    #[serde(rename = "i")]
+    pub id: u64,
+    /// The time the server has recorded the message at, in milliseconds since the Unix epoch (UTC)
+    #[serde(rename = "t")]
+    pub timestamp: u64,
     /// The slug of the room the user has sent the message to
     #[serde(rename = "r")]
     pub room: String,
@@ -70,6 +128,14 @@ pub struct UserMessageBroadcastEvent {
     /// The content of the message
     #[serde(rename = "c")]
     pub content: String,
+    /// Set on a message which is only shown to its sender and not recorded in the history,
+    /// its id is the one of the latest recorded message, so it must not be used to dedupe or page the messages
+    #[serde(rename = "ur", default, skip_serializing_if = "is_false")]
+    pub unrecorded: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
 }
 
 /// A reply containing the recent message history of a room
@@ -81,19 +147,273 @@ pub struct RoomHistoryReplyEvent {
     /// List of messages (ordered oldest → newest)
     #[serde(rename = "ms")]
     pub messages: Vec<UserMessageBroadcastEvent>,
+    /// The cursor this page was requested with, absent for the most recent page
+    #[serde(rename = "b", default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<u64>,
+    /// The cursor to request the next (older) page with, absent when there are no older messages
+    #[serde(rename = "nc", default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<u64>,
+    /// The request id of the command this event is a reply to, if the client has set one
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
+/// Sent to a session in place of the room events it has missed because it could not keep up with the room
+/// The missed messages are re-sent from the room history, other missed events such as participation changes are not
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoomResyncEvent {
+    /// The room slug
+    #[serde(rename = "r")]
+    pub room: String,
+    /// Number of events the session has missed
+    #[serde(rename = "s")]
+    pub skipped: u64,
+    /// The missed messages which are still in the room history (ordered oldest → newest)
+    #[serde(rename = "ms")]
+    pub messages: Vec<UserMessageBroadcastEvent>,
+}
+
+/// A user has changed their nickname
+/// Broadcast to every room the user is in, and sent as a reply without a room to the session that changed it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NicknameChangedBroadcastEvent {
+    /// The slug of the room the event is broadcast to, `None` for the reply
+    #[serde(rename = "r", default, skip_serializing_if = "Option::is_none")]
+    pub room: Option<String>,
+    /// The id of the user that has changed their nickname
+    #[serde(rename = "u")]
+    pub user_id: String,
+    /// The new nickname of the user
+    #[serde(rename = "n")]
+    pub nickname: String,
+    /// The request id of the command this event is a reply to, if the client has set one
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// A message sent directly from one user to another
+/// Delivered to all sessions of the recipient and of the sender, so every session can show the conversation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DirectMessageEvent {
+    /// The id of the message, unique and monotonically increasing within the server
+    #[serde(rename = "i")]
+    pub id: u64,
+    /// Server time of the message in milliseconds since the Unix epoch
+    #[serde(rename = "t")]
+    pub timestamp: u64,
+    /// The id of the user that has sent the message
+    #[serde(rename = "u")]
+    pub user_id: String,
+    /// The name to display for the user that has sent the message
+    #[serde(rename = "dn")]
+    pub display_name: String,
+    /// The id of the user the message is sent to
+    #[serde(rename = "to")]
+    pub recipient: String,
+    /// The content of the message
+    #[serde(rename = "c")]
+    pub content: String,
+    /// The request id of the command this event is a reply to, only set on the copy sent to the session that sent it
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// A reply to the hello command of the client, with the version of the protocol the server speaks and the capabilities it supports
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HelloReplyEvent {
+    /// The version of the protocol the server speaks
+    #[serde(rename = "v")]
+    pub version: u32,
+    /// The capabilities the server supports
+    #[serde(rename = "cs", default)]
+    pub capabilities: Vec<String>,
+    /// The request id of the command this event is a reply to, if the client has set one
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// Sent by the server when the connection has been idle, the client is expected to answer with a pong command
+/// The connection is closed if the client stays silent for too long
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PingEvent {
+    /// Opaque value chosen by the server, to be echoed back on the pong command
+    #[serde(rename = "t")]
+    pub timestamp: u64,
+}
+
+/// A reply to a ping command of the client
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PongReplyEvent {
+    /// The value of the ping command this pong answers
+    #[serde(rename = "t")]
+    pub timestamp: u64,
+    /// The request id of the command this event is a reply to, if the client has set one
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// A new room has been created, broadcast to every logged in session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoomCreatedBroadcastEvent {
+    /// The detail of the new room
+    #[serde(rename = "r")]
+    pub room: RoomDetail,
+}
+
+/// A room has been deleted, broadcast to every logged in session
+/// Participants of the room are removed from it before they receive the event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoomDeletedBroadcastEvent {
+    /// The slug of the deleted room
+    #[serde(rename = "r")]
+    pub room: String,
+}
+
+/// What a moderator has done to a user in a room
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationAction {
+    Kicked,
+    Banned,
+    Unbanned,
+    Muted,
+    Unmuted,
+}
+
+/// A moderator of a room has kicked, banned, unbanned, muted or unmuted a user, broadcast to the participants of the room
+/// A kicked or banned user is removed from the room before they receive the event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserModeratedBroadcastEvent {
+    /// The slug of the room
+    #[serde(rename = "r")]
+    pub room: String,
+    /// The id of the user the action targets
+    #[serde(rename = "u")]
+    pub user_id: String,
+    /// The id of the owner or moderator of the room who took the action
+    #[serde(rename = "m")]
+    pub moderator_id: String,
+    /// What has been done to the user
+    #[serde(rename = "a")]
+    pub action: ModerationAction,
+    /// Server time the ban or the mute ends at, in milliseconds since the Unix epoch, if it is not permanent
+    /// For a kick, the time the user can join the room again at
+    #[serde(rename = "ea", default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+/// A user has started or stopped typing a message in a room, broadcast to the participants of the room
+/// The server ends the typing on its own after a few seconds without a new start. The typing of a user
+/// also ends with their next message to the room or when they leave it, without a stopped event.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserTypingBroadcastEvent {
+    /// The slug of the room
+    #[serde(rename = "r")]
+    pub room: String,
+    /// The id of the user who is typing
+    #[serde(rename = "u")]
+    pub user_id: String,
+    /// Whether the user has started or stopped typing
+    #[serde(rename = "ty")]
+    pub typing: bool,
+}
+
+/// Machine-readable reason of a rejected command
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandErrorCode {
+    /// The command could not be read or parsed by the server
+    InvalidCommand,
+    /// The room the command targets does not exist
+    RoomNotFound,
+    /// The user has already joined the room
+    AlreadyJoined,
+    /// The user has to join the room before the command can be processed
+    NotJoined,
+    /// The server has failed to process a valid command
+    Internal,
+    /// The session has to log in before the command can be processed
+    NotAuthenticated,
+    /// The session has already logged in
+    AlreadyAuthenticated,
+    /// The username or the password is wrong
+    InvalidCredentials,
+    /// An account with the username already exists
+    UsernameTaken,
+    /// Another user is already known by the nickname
+    NicknameTaken,
+    /// A room with the same name already exists
+    RoomAlreadyExists,
+    /// The user is not allowed to perform the command
+    PermissionDenied,
+    /// The user the command targets does not exist
+    UserNotFound,
+    /// The user the command targets has no active session
+    UserOffline,
+    /// The client has broken the protocol, e.g. sent a frame above the size limit, the connection is closed after it
+    ProtocolError,
+    /// The client speaks a version of the protocol the server does not support, the connection is closed after it
+    UnsupportedProtocolVersion,
+    /// The resume token is unknown, or the session it belongs to has expired
+    InvalidResumeToken,
+    /// The user is banned from the room
+    Banned,
+    /// The user is muted in the room
+    Muted,
+    /// The user has sent too many commands of the kind in a short time, the command can be retried later
+    RateLimited,
+    /// The message is empty or only contains whitespace
+    EmptyMessage,
+    /// The message is longer than the server allows
+    MessageTooLong,
+    /// The message contains control characters or terminal escape sequences, which the server does not accept
+    InvalidCharacters,
+    /// The message has been refused by a filter of the room, e.g. for containing a blocked word
+    MessageRejected,
+}
+
+/// A reply to the user when a command they have sent was rejected
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandErrorReplyEvent {
+    /// The slug of the room the rejected command targeted, if any
+    #[serde(rename = "r", default, skip_serializing_if = "Option::is_none")]
+    pub room: Option<String>,
+    /// The reason of the rejection
+    #[serde(rename = "ec")]
+    pub code: CommandErrorCode,
+    /// Human readable description of the rejection
+    #[serde(rename = "m")]
+    pub message: String,
+    /// Milliseconds to wait before retrying a rate limited command
+    #[serde(rename = "ra", default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_ms: Option<u64>,
+    /// The request id of the command this event is a reply to, if the client has set one
+    #[serde(rename = "rid", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "_et", rename_all = "snake_case")]
 /// Events that can be sent to the client
 /// Events maybe related to different users and rooms, the recipient is a single chat session
 pub enum Event {
+    Hello(HelloReplyEvent),
     LoginSuccessful(LoginSuccessfulReplyEvent),
+    SessionResumed(SessionResumedReplyEvent),
     RoomParticipation(RoomParticipationBroadcastEvent),
     UserJoinedRoom(UserJoinedRoomReplyEvent),
     UserMessage(UserMessageBroadcastEvent),
     RoomHistory(RoomHistoryReplyEvent),
+    RoomResync(RoomResyncEvent),
+    NicknameChanged(NicknameChangedBroadcastEvent),
+    DirectMessage(DirectMessageEvent),
+    RoomCreated(RoomCreatedBroadcastEvent),
+    RoomDeleted(RoomDeletedBroadcastEvent),
+    UserModerated(UserModeratedBroadcastEvent),
+    UserTyping(UserTypingBroadcastEvent),
+    Ping(PingEvent),
+    Pong(PongReplyEvent),
+    CommandError(CommandErrorReplyEvent),
 }
 
 #[cfg(test)]
@@ -113,15 +433,56 @@ mod tests {
         let event = Event::LoginSuccessful(LoginSuccessfulReplyEvent {
             session_id: "session-id-1".to_string(),
             user_id: "user-id-1".to_string(),
+            display_name: "alice".to_string(),
+            rooms: vec![RoomDetail {
+                name: "room-1".to_string(),
+                description: "some description".to_string(),
+            }],
+            resume_token: None,
+            request_id: None,
+        });
+
+        assert_event_serialization(
+            &event,
+            r#"{"_et":"login_successful","s":"session-id-1","u":"user-id-1","dn":"alice","rs":[{"n":"room-1","d":"some description"}]}"#,
+        );
+    }
+
+    #[test]
+    fn test_login_successful_event_with_resume_token() {
+        let event = Event::LoginSuccessful(LoginSuccessfulReplyEvent {
+            session_id: "session-id-1".to_string(),
+            user_id: "user-id-1".to_string(),
+            display_name: "alice".to_string(),
+            rooms: Vec::new(),
+            resume_token: Some("token-1".to_string()),
+            request_id: None,
+        });
+
+        assert_event_serialization(
+            &event,
+            r#"{"_et":"login_successful","s":"session-id-1","u":"user-id-1","dn":"alice","rs":[],"rt":"token-1"}"#,
+        );
+    }
+
+    #[test]
+    fn test_session_resumed_event() {
+        let event = Event::SessionResumed(SessionResumedReplyEvent {
+            session_id: "session-id-1".to_string(),
+            user_id: "user-id-1".to_string(),
+            display_name: "alice".to_string(),
             rooms: vec![RoomDetail {
                 name: "room-1".to_string(),
                 description: "some description".to_string(),
             }],
+            joined_rooms: vec!["room-1".to_string()],
+            resume_token: "token-2".to_string(),
+            request_id: Some("req-1".to_string()),
         });
 
         assert_event_serialization(
             &event,
-            r#"{"_et":"login_successful","s":"session-id-1","u":"user-id-1","rs":[{"n":"room-1","d":"some description"}]}"#,
+            r#"{"_et":"session_resumed","s":"session-id-1","u":"user-id-1","dn":"alice","rs":[{"n":"room-1","d":"some description"}],"jrs":["room-1"],"rt":"token-2","rid":"req-1"}"#,
         );
     }
 
@@ -130,12 +491,13 @@ mod tests {
         let event = Event::RoomParticipation(RoomParticipationBroadcastEvent {
             room: "test".to_string(),
             user_id: "test".to_string(),
+            display_name: "alice".to_string(),
             status: RoomParticipationStatus::Joined,
         });
 
         assert_event_serialization(
             &event,
-            r#"{"_et":"room_participation","r":"test","u":"test","s":"joined"}"#,
+            r#"{"_et":"room_participation","r":"test","u":"test","dn":"alice","s":"joined"}"#,
         );
     }
 
@@ -144,12 +506,13 @@ mod tests {
         let event = Event::RoomParticipation(RoomParticipationBroadcastEvent {
             room: "test".to_string(),
             user_id: "test".to_string(),
+            display_name: "alice".to_string(),
             status: RoomParticipationStatus::Left,
         });
 
         assert_event_serialization(
             &event,
-            r#"{"_et":"room_participation","r":"test","u":"test","s":"left"}"#,
+            r#"{"_et":"room_participation","r":"test","u":"test","dn":"alice","s":"left"}"#,
         );
     }
 
@@ -157,26 +520,67 @@ mod tests {
     fn test_user_joined_room_event() {
         let event = Event::UserJoinedRoom(UserJoinedRoomReplyEvent {
             room: "test".to_string(),
-            users: vec!["test".to_string()],
+            users: vec![UserDetail {
+                user_id: "test".to_string(),
+                display_name: "alice".to_string(),
+            }],
+            request_id: None,
+        });
+
+        assert_event_serialization(
+            &event,
+            r#"{"_et":"user_joined_room","r":"test","us":[{"u":"test","dn":"alice"}]}"#,
+        );
+    }
+
+    #[test]
+    fn test_user_joined_room_event_with_request_id() {
+        let event = Event::UserJoinedRoom(UserJoinedRoomReplyEvent {
+            room: "test".to_string(),
+            users: vec![UserDetail {
+                user_id: "test".to_string(),
+                display_name: "alice".to_string(),
+            }],
+            request_id: Some("req-1".to_string()),
         });
 
         assert_event_serialization(
             &event,
-            r#"{"_et":"user_joined_room","r":"test","us":["test"]}"#,
+            r#"{"_et":"user_joined_room","r":"test","us":[{"u":"test","dn":"alice"}],"rid":"req-1"}"#,
         );
     }
 
     #[test]
     fn test_user_message_event() {
         let event = Event::UserMessage(UserMessageBroadcastEvent {
+            id: 1,
+            timestamp: 1700000000000,
             room: "test".to_string(),
             user_id: "test".to_string(),
             content: "test".to_string(),
+            unrecorded: false,
         });
 
         assert_event_serialization(
             &event,
-            r#"{"_et":"user_message","r":"test","u":"test","c":"test"}"#,
+            r#"{"_et":"user_message","i":1,"t":1700000000000,"r":"test","u":"test","c":"test"}"#,
+        );
+    }
+
+    #[test]
+    fn test_unrecorded_user_message_event() {
+        let event = Event::UserMessage(UserMessageBroadcastEvent {
+            id: 1,
+            timestamp: 1700000000000,
+            room: "test".to_string(),
+            user_id: "test".to_string(),
+            content: "test".to_string(),
+            unrecorded: true,
+        });
+
+        assert_event_serialization(
+            &event,
+            r#"{"_et":"user_message","i":1,"t":1700000000000,"r":"test","u":"test","c":"test","ur":true}"#,
         );
     }
 
@@ -185,6 +589,9 @@ mod tests {
         let event = Event::RoomHistory(RoomHistoryReplyEvent {
             room: "test".to_string(),
             messages: vec![],
+            before: None,
+            next_cursor: None,
+            request_id: None,
         });
 
         assert_event_serialization(
@@ -193,4 +600,238 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_room_history_page_event() {
+        let event = Event::RoomHistory(RoomHistoryReplyEvent {
+            room: "test".to_string(),
+            messages: vec![UserMessageBroadcastEvent {
+                id: 19,
+                timestamp: 1700000000000,
+                room: "test".to_string(),
+                user_id: "test".to_string(),
+                content: "test".to_string(),
+                unrecorded: false,
+            }],
+            before: Some(20),
+            next_cursor: Some(19),
+            request_id: Some("req-1".to_string()),
+        });
+
+        assert_event_serialization(
+            &event,
+            r#"{"_et":"room_history","r":"test","ms":[{"i":19,"t":1700000000000,"r":"test","u":"test","c":"test"}],"b":20,"nc":19,"rid":"req-1"}"#,
+        );
+    }
+
+    #[test]
+    fn test_nickname_changed_event() {
+        let event = Event::NicknameChanged(NicknameChangedBroadcastEvent {
+            room: Some("test".to_string()),
+            user_id: "test".to_string(),
+            nickname: "alice".to_string(),
+            request_id: None,
+        });
+
+        assert_event_serialization(
+            &event,
+            r#"{"_et":"nickname_changed","r":"test","u":"test","n":"alice"}"#,
+        );
+    }
+
+    #[test]
+    fn test_nickname_changed_reply_event() {
+        let event = Event::NicknameChanged(NicknameChangedBroadcastEvent {
+            room: None,
+            user_id: "test".to_string(),
+            nickname: "alice".to_string(),
+            request_id: Some("req-1".to_string()),
+        });
+
+        assert_event_serialization(
+            &event,
+            r#"{"_et":"nickname_changed","u":"test","n":"alice","rid":"req-1"}"#,
+        );
+    }
+
+    #[test]
+    fn test_hello_event() {
+        let event = Event::Hello(HelloReplyEvent {
+            version: 1,
+            capabilities: vec!["history".to_string(), "heartbeat".to_string()],
+            request_id: None,
+        });
+
+        assert_event_serialization(
+            &event,
+            r#"{"_et":"hello","v":1,"cs":["history","heartbeat"]}"#,
+        );
+    }
+
+    #[test]
+    fn test_ping_and_pong_events() {
+        let event = Event::Ping(PingEvent {
+            timestamp: 1700000000000,
+        });
+        assert_event_serialization(&event, r#"{"_et":"ping","t":1700000000000}"#);
+
+        let event = Event::Pong(PongReplyEvent {
+            timestamp: 1700000000000,
+            request_id: Some("req-1".to_string()),
+        });
+        assert_event_serialization(&event, r#"{"_et":"pong","t":1700000000000,"rid":"req-1"}"#);
+    }
+
+    #[test]
+    fn test_room_resync_event() {
+        let event = Event::RoomResync(RoomResyncEvent {
+            room: "test".to_string(),
+            skipped: 3,
+            messages: vec![UserMessageBroadcastEvent {
+                id: 7,
+                timestamp: 1700000000000,
+                room: "test".to_string(),
+                user_id: "alice".to_string(),
+                content: "hello".to_string(),
+                unrecorded: false,
+            }],
+        });
+
+        assert_event_serialization(
+            &event,
+            r#"{"_et":"room_resync","r":"test","s":3,"ms":[{"i":7,"t":1700000000000,"r":"test","u":"alice","c":"hello"}]}"#,
+        );
+    }
+
+    #[test]
+    fn test_direct_message_event() {
+        let event = Event::DirectMessage(DirectMessageEvent {
+            id: 1,
+            timestamp: 1700000000000,
+            user_id: "alice".to_string(),
+            display_name: "Alice".to_string(),
+            recipient: "bob".to_string(),
+            content: "hello".to_string(),
+            request_id: None,
+        });
+
+        assert_event_serialization(
+            &event,
+            r#"{"_et":"direct_message","i":1,"t":1700000000000,"u":"alice","dn":"Alice","to":"bob","c":"hello"}"#,
+        );
+    }
+
+    #[test]
+    fn test_room_created_event() {
+        let event = Event::RoomCreated(RoomCreatedBroadcastEvent {
+            room: RoomDetail {
+                name: "test".to_string(),
+                description: "desc".to_string(),
+            },
+        });
+
+        assert_event_serialization(
+            &event,
+            r#"{"_et":"room_created","r":{"n":"test","d":"desc"}}"#,
+        );
+    }
+
+    #[test]
+    fn test_room_deleted_event() {
+        let event = Event::RoomDeleted(RoomDeletedBroadcastEvent {
+            room: "test".to_string(),
+        });
+
+        assert_event_serialization(&event, r#"{"_et":"room_deleted","r":"test"}"#);
+    }
+
+    #[test]
+    fn test_user_moderated_event() {
+        let event = Event::UserModerated(UserModeratedBroadcastEvent {
+            room: "test".to_string(),
+            user_id: "bob".to_string(),
+            moderator_id: "alice".to_string(),
+            action: ModerationAction::Banned,
+            expires_at: Some(1_700_000_060_000),
+        });
+
+        assert_event_serialization(
+            &event,
+            r#"{"_et":"user_moderated","r":"test","u":"bob","m":"alice","a":"banned","ea":1700000060000}"#,
+        );
+
+        let event = Event::UserModerated(UserModeratedBroadcastEvent {
+            room: "test".to_string(),
+            user_id: "bob".to_string(),
+            moderator_id: "alice".to_string(),
+            action: ModerationAction::Kicked,
+            expires_at: None,
+        });
+
+        assert_event_serialization(
+            &event,
+            r#"{"_et":"user_moderated","r":"test","u":"bob","m":"alice","a":"kicked"}"#,
+        );
+    }
+
+    #[test]
+    fn test_user_typing_event() {
+        let event = Event::UserTyping(UserTypingBroadcastEvent {
+            room: "test".to_string(),
+            user_id: "alice".to_string(),
+            typing: true,
+        });
+
+        assert_event_serialization(
+            &event,
+            r#"{"_et":"user_typing","r":"test","u":"alice","ty":true}"#,
+        );
+    }
+
+    #[test]
+    fn test_command_error_event() {
+        let event = Event::CommandError(CommandErrorReplyEvent {
+            room: Some("test".to_string()),
+            code: CommandErrorCode::NotJoined,
+            message: "test".to_string(),
+            retry_after_ms: None,
+            request_id: Some("req-1".to_string()),
+        });
+
+        assert_event_serialization(
+            &event,
+            r#"{"_et":"command_error","r":"test","ec":"not_joined","m":"test","rid":"req-1"}"#,
+        );
+    }
+
+    #[test]
+    fn test_rate_limited_command_error_event() {
+        let event = Event::CommandError(CommandErrorReplyEvent {
+            room: None,
+            code: CommandErrorCode::RateLimited,
+            message: "rate limited, retry after 500 ms".to_string(),
+            retry_after_ms: Some(500),
+            request_id: None,
+        });
+
+        assert_event_serialization(
+            &event,
+            r#"{"_et":"command_error","ec":"rate_limited","m":"rate limited, retry after 500 ms","ra":500}"#,
+        );
+    }
+
+    #[test]
+    fn test_command_error_event_without_room() {
+        let event = Event::CommandError(CommandErrorReplyEvent {
+            room: None,
+            code: CommandErrorCode::InvalidCommand,
+            message: "test".to_string(),
+            retry_after_ms: None,
+            request_id: None,
+        });
+
+        assert_event_serialization(
+            &event,
+            r#"{"_et":"command_error","ec":"invalid_command","m":"test"}"#,
+        );
+    }
 }
@@ -0,0 +1,123 @@
+use std::fmt;
+
+/// Version of the protocol spoken by this version of the crate
+/// It is increased on every change that an older peer can not understand, e.g. a new command or event
+pub const PROTOCOL_VERSION: u32 = 4;
+/// Oldest version of the protocol this version of the crate can still talk to
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Optional features of the protocol a peer may or may not support
+pub mod capability {
+    /// Direct messages between users, see [crate::command::SendDirectMessageCommand]
+    pub const DIRECT_MESSAGES: &str = "direct_messages";
+    /// Creating and deleting rooms at runtime, see [crate::command::CreateRoomCommand]
+    pub const ROOM_MANAGEMENT: &str = "room_management";
+    /// Paging through the message history of a room, see [crate::command::GetHistoryCommand]
+    pub const HISTORY: &str = "history";
+    /// Re-sending the missed messages of a session that falls behind, see [crate::event::RoomResyncEvent]
+    pub const RESYNC: &str = "resync";
+    /// Pings in both directions, see [crate::command::PingCommand] and [crate::event::PingEvent]
+    pub const HEARTBEAT: &str = "heartbeat";
+    /// Resuming a lost session with a token, see [crate::command::ResumeSessionCommand]
+    pub const RESUME: &str = "resume";
+    /// Room roles, kicking, banning and muting users, see [crate::command::KickUserCommand]
+    pub const MODERATION: &str = "moderation";
+    /// Typing indicators of the users in a room, see [crate::command::TypingStartedCommand]
+    pub const TYPING: &str = "typing";
+}
+
+/// Capabilities supported by this version of the crate
+pub const CAPABILITIES: &[&str] = &[
+    capability::DIRECT_MESSAGES,
+    capability::ROOM_MANAGEMENT,
+    capability::HISTORY,
+    capability::RESYNC,
+    capability::HEARTBEAT,
+    capability::RESUME,
+    capability::MODERATION,
+    capability::TYPING,
+];
+
+/// [NegotiatedProtocol] is what both peers of a connection have agreed on after the hello
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NegotiatedProtocol {
+    /// The version spoken on the connection, the older one of both peers
+    pub version: u32,
+    /// The capabilities supported by both peers
+    pub capabilities: Vec<String>,
+}
+
+/// [IncompatibleProtocolError] is the error of a peer speaking a version of the protocol that is not supported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncompatibleProtocolError {
+    pub version: u32,
+}
+
+impl NegotiatedProtocol {
+    /// Agree on the protocol with a peer, given our capabilities and the version and the capabilities the peer has announced
+    /// Capabilities only one of the peers supports are left out
+    pub fn negotiate(
+        capabilities: &[&str],
+        peer_version: u32,
+        peer_capabilities: &[String],
+    ) -> Result<NegotiatedProtocol, IncompatibleProtocolError> {
+        if peer_version < MIN_PROTOCOL_VERSION {
+            return Err(IncompatibleProtocolError {
+                version: peer_version,
+            });
+        }
+
+        Ok(NegotiatedProtocol {
+            version: peer_version.min(PROTOCOL_VERSION),
+            capabilities: capabilities
+                .iter()
+                .filter(|capability| peer_capabilities.iter().any(|c| c == *capability))
+                .map(|capability| String::from(*capability))
+                .collect(),
+        })
+    }
+
+    /// Whether both peers support the given capability
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
+
+impl fmt::Display for IncompatibleProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "protocol version {} is not supported, versions {} to {} are",
+            self.version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+        )
+    }
+}
+
+impl std::error::Error for IncompatibleProtocolError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate() {
+        let negotiated = NegotiatedProtocol::negotiate(
+            CAPABILITIES,
+            PROTOCOL_VERSION + 1,
+            &[capability::HISTORY.into(), "from_the_future".into()],
+        )
+        .unwrap();
+
+        assert_eq!(negotiated.version, PROTOCOL_VERSION);
+        assert_eq!(negotiated.capabilities, vec![capability::HISTORY]);
+        assert!(negotiated.supports(capability::HISTORY));
+        assert!(!negotiated.supports(capability::DIRECT_MESSAGES));
+
+        assert_eq!(
+            NegotiatedProtocol::negotiate(CAPABILITIES, MIN_PROTOCOL_VERSION - 1, &[]),
+            Err(IncompatibleProtocolError {
+                version: MIN_PROTOCOL_VERSION - 1
+            })
+        );
+    }
+}
@@ -1,13 +1,19 @@
 use anyhow::Context;
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    net::{tcp::OwnedWriteHalf, TcpStream},
+    io::{self, AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
 };
-use tokio_stream::{wrappers::LinesStream, StreamExt};
+use tokio_stream::StreamExt;
 
-use crate::{command, event};
+use crate::{
+    command, event,
+    protocol::{NegotiatedProtocol, PROTOCOL_VERSION},
+};
 
-use super::common::{BoxedStream, NEW_LINE};
+use super::{
+    codec::Codec,
+    common::{BoxedStream, BoxedWriter, DEFAULT_MAX_FRAME_SIZE},
+};
 
 /// [EventStream] is a stream of [event::Event]s sent by the server
 ///
@@ -17,17 +23,32 @@ use super::common::{BoxedStream, NEW_LINE};
 /// without the risk of missing events.
 pub type EventStream = BoxedStream<anyhow::Result<event::Event>>;
 
-/// [CommandWriter] is a wrapper around a [TcpStream] which writes [command::UserCommand]s to the server
+/// [CommandWriter] is a wrapper around the write half of a stream which writes [command::UserCommand]s to the server
 pub struct CommandWriter {
-    writer: OwnedWriteHalf,
+    writer: BoxedWriter,
+    codec: Codec,
+    negotiated: Option<NegotiatedProtocol>,
 }
 
 impl CommandWriter {
-    pub fn new(writer: OwnedWriteHalf) -> Self {
-        Self { writer }
+    pub fn new<W: AsyncWrite + Send + 'static>(writer: W) -> Self {
+        Self::with_codec(writer, Codec::default())
+    }
+
+    pub fn with_codec<W: AsyncWrite + Send + 'static>(writer: W, codec: Codec) -> Self {
+        Self {
+            writer: Box::pin(writer),
+            codec,
+            negotiated: None,
+        }
+    }
+
+    /// The protocol agreed on with the server, `None` until [hello] has succeeded
+    pub fn negotiated(&self) -> Option<&NegotiatedProtocol> {
+        self.negotiated.as_ref()
     }
 
-    /// Send a [command::UserCommand] to the backing [TcpStream]
+    /// Send a [command::UserCommand] to the backing stream
     ///
     /// # Cancel Safety
     ///
@@ -37,33 +58,151 @@ impl CommandWriter {
     /// partially written, but future calls to `write` will start over
     /// from the beginning of the buffer. Causing undefined behaviour.
     pub async fn write(&mut self, command: &command::UserCommand) -> anyhow::Result<()> {
-        let mut serialized_bytes = serde_json::to_vec(command)?;
-        serialized_bytes.extend_from_slice(NEW_LINE);
+        let frame = self.codec.encode(command)?;
+
+        self.writer.write_all(frame.as_slice()).await?;
+
+        Ok(())
+    }
 
-        self.writer.write_all(serialized_bytes.as_slice()).await?;
+    /// Close the write half of the stream, which lets a TLS server tell a closed connection from a truncated one
+    pub async fn shutdown(&mut self) -> anyhow::Result<()> {
+        self.writer.shutdown().await?;
 
         Ok(())
     }
 }
 
 /// Splits a TCP stream into a stream of events and a command writer.
+/// The default [Codec] is used, and frames sent by the server are limited to [DEFAULT_MAX_FRAME_SIZE].
 ///
 /// # Arguments
 ///
 /// - `stream` - A [TcpStream] to split
 pub fn split_tcp_stream(stream: TcpStream) -> (EventStream, CommandWriter) {
+    split_tcp_stream_with_codec(stream, Codec::default(), DEFAULT_MAX_FRAME_SIZE)
+}
+
+/// Splits a TCP stream into a stream of events and a command writer using the given codec.
+/// A frame larger than the given size fails with a [super::FrameTooLargeError] without being read further.
+///
+/// # Arguments
+///
+/// - `stream` - A [TcpStream] to split
+/// - `codec` - The [Codec] both ends of the connection use
+/// - `max_frame_size` - The maximum size of a frame sent by the server, in bytes
+pub fn split_tcp_stream_with_codec(
+    stream: TcpStream,
+    codec: Codec,
+    max_frame_size: usize,
+) -> (EventStream, CommandWriter) {
     let (reader, writer) = stream.into_split();
 
+    split_halves(reader, writer, codec, max_frame_size)
+}
+
+/// Splits any stream, e.g. a Unix socket or a TLS stream, into a stream of events and a command writer.
+/// The default [Codec] is used, and frames sent by the server are limited to [DEFAULT_MAX_FRAME_SIZE].
+///
+/// # Arguments
+///
+/// - `stream` - A stream to split
+pub fn split_stream<S>(stream: S) -> (EventStream, CommandWriter)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    split_stream_with_codec(stream, Codec::default(), DEFAULT_MAX_FRAME_SIZE)
+}
+
+/// Splits any stream, e.g. a Unix socket or a TLS stream, into a stream of events and a command writer using the given codec.
+/// A frame larger than the given size fails with a [super::FrameTooLargeError] without being read further.
+///
+/// # Arguments
+///
+/// - `stream` - A stream to split
+/// - `codec` - The [Codec] both ends of the connection use
+/// - `max_frame_size` - The maximum size of a frame sent by the server, in bytes
+pub fn split_stream_with_codec<S>(
+    stream: S,
+    codec: Codec,
+    max_frame_size: usize,
+) -> (EventStream, CommandWriter)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (reader, writer) = io::split(stream);
+
+    split_halves(reader, writer, codec, max_frame_size)
+}
+
+fn split_halves<R, W>(
+    reader: R,
+    writer: W,
+    codec: Codec,
+    max_frame_size: usize,
+) -> (EventStream, CommandWriter)
+where
+    R: AsyncRead + Send + 'static,
+    W: AsyncWrite + Send + 'static,
+{
     (
         Box::pin(
-            LinesStream::new(BufReader::new(reader).lines()).map(|line| {
-                line.context("could not read line from the server")
-                    .and_then(|line| {
-                        serde_json::from_str::<event::Event>(&line)
+            codec
+                .read_frames(reader, max_frame_size, "could not read from the server")
+                .map(move |frame| {
+                    frame.and_then(|frame| {
+                        codec
+                            .decode::<event::Event>(&frame)
                             .context("failed to deserialize event from the server")
                     })
-            }),
+                }),
         ),
-        CommandWriter::new(writer),
+        CommandWriter::with_codec(writer, codec),
     )
 }
+
+/// Sends a hello with the given capabilities to the server and waits for its reply, which has to be done before logging in.
+/// The negotiated protocol is kept by the command writer as well.
+/// Fails if the server rejects the client, or the server speaks a version of the protocol the client does not support.
+///
+/// # Arguments
+///
+/// - `event_stream` - The [EventStream] of the connection, nothing else is expected on it until the reply
+/// - `command_writer` - The [CommandWriter] of the connection
+/// - `capabilities` - The capabilities the client supports, see [crate::protocol::capability]
+pub async fn hello(
+    event_stream: &mut EventStream,
+    command_writer: &mut CommandWriter,
+    capabilities: &[&str],
+) -> anyhow::Result<NegotiatedProtocol> {
+    command_writer
+        .write(&command::UserCommand::Hello(command::HelloCommand {
+            version: PROTOCOL_VERSION,
+            capabilities: capabilities.iter().map(|c| String::from(*c)).collect(),
+            request_id: None,
+        }))
+        .await?;
+
+    loop {
+        match event_stream.next().await {
+            Some(Ok(event::Event::Hello(reply))) => {
+                let negotiated = NegotiatedProtocol::negotiate(
+                    capabilities,
+                    reply.version,
+                    &reply.capabilities,
+                )?;
+                command_writer.negotiated = Some(negotiated.clone());
+
+                return Ok(negotiated);
+            }
+            // the server may ping the connection before it gets to the hello
+            Some(Ok(event::Event::Ping(_))) => continue,
+            Some(Ok(event::Event::CommandError(err))) => {
+                anyhow::bail!("the server has rejected the hello: {}", err.message)
+            }
+            Some(Ok(event)) => anyhow::bail!("unexpected event instead of a hello: {:?}", event),
+            Some(Err(err)) => return Err(err),
+            None => anyhow::bail!("the server has closed the connection"),
+        }
+    }
+}
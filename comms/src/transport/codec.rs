@@ -0,0 +1,431 @@
+use std::{fmt, str::FromStr};
+
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::io::AsyncRead;
+use tokio_stream::StreamExt;
+use tokio_util::codec::{FramedRead, LinesCodec, LinesCodecError};
+#[cfg(feature = "msgpack")]
+use tokio_util::codec::{LengthDelimitedCodec, LengthDelimitedCodecError};
+
+use super::common::{BoxedStream, FrameTooLargeError, NEW_LINE};
+
+/// [Codec] is the encoding of the commands and events on the wire, both ends of a connection have to use the same one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+    /// Newline delimited JSON, which can be read and written by hand with tools like netcat
+    #[default]
+    JsonLines,
+    /// MessagePack frames, each prefixed with its length as a big endian `u32`
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+}
+
+impl Codec {
+    /// Serializes an item into a frame, along with its delimiter or length prefix
+    pub fn encode<T: Serialize>(&self, item: &T) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Codec::JsonLines => {
+                let mut frame = serde_json::to_vec(item)?;
+                frame.extend_from_slice(NEW_LINE);
+
+                Ok(frame)
+            }
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => {
+                // the field names are kept, the commands and events are tagged by a field and can not be read without them
+                let payload = rmp_serde::to_vec_named(item)?;
+                let length = u32::try_from(payload.len())?;
+
+                let mut frame = Vec::with_capacity(4 + payload.len());
+                frame.extend_from_slice(&length.to_be_bytes());
+                frame.extend_from_slice(&payload);
+
+                Ok(frame)
+            }
+        }
+    }
+
+    /// Deserializes an item from a frame, without its delimiter or length prefix
+    pub fn decode<T: DeserializeOwned>(&self, frame: &[u8]) -> anyhow::Result<T> {
+        match self {
+            Codec::JsonLines => Ok(serde_json::from_slice(frame)?),
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => Ok(rmp_serde::from_slice(frame)?),
+        }
+    }
+
+    /// Reads the frames of a stream, failing with a [FrameTooLargeError] for a frame above the limit
+    pub(super) fn read_frames<R>(
+        &self,
+        reader: R,
+        max_frame_size: usize,
+        context: &'static str,
+    ) -> BoxedStream<anyhow::Result<Vec<u8>>>
+    where
+        R: AsyncRead + Send + 'static,
+    {
+        match self {
+            Codec::JsonLines => Box::pin(
+                FramedRead::new(reader, LinesCodec::new_with_max_length(max_frame_size)).map(
+                    move |frame| match frame {
+                        Ok(frame) => Ok(frame.into_bytes()),
+                        Err(LinesCodecError::MaxLineLengthExceeded) => {
+                            Err(anyhow::Error::new(FrameTooLargeError { max_frame_size }))
+                        }
+                        Err(LinesCodecError::Io(err)) => {
+                            Err(anyhow::Error::new(err).context(context))
+                        }
+                    },
+                ),
+            ),
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => {
+                let codec = LengthDelimitedCodec::builder()
+                    .max_frame_length(max_frame_size)
+                    .new_codec();
+
+                Box::pin(FramedRead::new(reader, codec).map(move |frame| {
+                    match frame {
+                        Ok(frame) => Ok(frame.to_vec()),
+                        // the length prefix is read first, so the frame is refused before any of it is buffered
+                        Err(err)
+                            if err
+                                .get_ref()
+                                .is_some_and(|err| err.is::<LengthDelimitedCodecError>()) =>
+                        {
+                            Err(anyhow::Error::new(FrameTooLargeError { max_frame_size }))
+                        }
+                        Err(err) => Err(anyhow::Error::new(err).context(context)),
+                    }
+                }))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Codec::JsonLines => write!(f, "json"),
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => write!(f, "msgpack"),
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = anyhow::Error;
+
+    /// Parses the name of a codec, `json` or `msgpack`, the latter is only known with the `msgpack` feature
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Codec::JsonLines),
+            #[cfg(feature = "msgpack")]
+            "msgpack" => Ok(Codec::MessagePack),
+            _ => anyhow::bail!("unknown codec '{}'", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        command::{self, UserCommand},
+        event::{self, Event},
+    };
+
+    use super::*;
+
+    /// Every codec the crate has been built with
+    fn codecs() -> Vec<Codec> {
+        vec![
+            Codec::JsonLines,
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack,
+        ]
+    }
+
+    /// Encodes the items into a single stream, and asserts that the same items are read back from it
+    async fn assert_round_trip<T>(items: Vec<T>)
+    where
+        T: Serialize + DeserializeOwned + PartialEq + fmt::Debug,
+    {
+        for codec in codecs() {
+            let mut bytes = Vec::new();
+            for item in items.iter() {
+                bytes.extend(codec.encode(item).unwrap());
+            }
+
+            let decoded = codec
+                .read_frames(std::io::Cursor::new(bytes), 1024, "could not read")
+                .map(|frame| codec.decode::<T>(&frame.unwrap()).unwrap())
+                .collect::<Vec<_>>()
+                .await;
+
+            assert_eq!(decoded, items, "round trip with the {} codec", codec);
+        }
+    }
+
+    // every variant of the commands, new ones have to be added here as well
+    #[tokio::test]
+    async fn test_command_round_trip() {
+        let request_id = Some(String::from("request-1"));
+
+        assert_round_trip(vec![
+            UserCommand::Hello(command::HelloCommand {
+                version: 1,
+                capabilities: vec!["history".into(), "heartbeat".into()],
+                request_id: None,
+            }),
+            UserCommand::Register(command::RegisterCommand {
+                username: "user-1".into(),
+                password: "password-1".into(),
+                request_id: None,
+            }),
+            UserCommand::Login(command::LoginCommand {
+                username: "user-1".into(),
+                password: "password-1".into(),
+                request_id: request_id.clone(),
+            }),
+            UserCommand::ResumeSession(command::ResumeSessionCommand {
+                token: "token-1".into(),
+                request_id: request_id.clone(),
+            }),
+            UserCommand::JoinRoom(command::JoinRoomCommand {
+                room: "room-1".into(),
+                request_id: None,
+            }),
+            UserCommand::LeaveRoom(command::LeaveRoomCommand {
+                room: "room-1".into(),
+                request_id: request_id.clone(),
+            }),
+            UserCommand::SendMessage(command::SendMessageCommand {
+                room: "room-1".into(),
+                content: "content with \"quotes\" and\nnew lines".into(),
+                request_id: None,
+            }),
+            UserCommand::GetHistory(command::GetHistoryCommand {
+                room: "room-1".into(),
+                before: Some(42),
+                limit: Some(10),
+                request_id: None,
+            }),
+            UserCommand::GetHistory(command::GetHistoryCommand {
+                room: "room-1".into(),
+                before: None,
+                limit: None,
+                request_id: request_id.clone(),
+            }),
+            UserCommand::SetNickname(command::SetNicknameCommand {
+                nickname: "nick".into(),
+                request_id: None,
+            }),
+            UserCommand::SendDirectMessage(command::SendDirectMessageCommand {
+                user_id: "user-2".into(),
+                content: "content-1".into(),
+                request_id: request_id.clone(),
+            }),
+            UserCommand::CreateRoom(command::CreateRoomCommand {
+                room: "room-2".into(),
+                description: "description".into(),
+                request_id: None,
+            }),
+            UserCommand::DeleteRoom(command::DeleteRoomCommand {
+                room: "room-2".into(),
+                request_id: None,
+            }),
+            UserCommand::KickUser(command::KickUserCommand {
+                room: "room-1".into(),
+                user_id: "user-2".into(),
+                duration_secs: Some(60),
+                request_id: None,
+            }),
+            UserCommand::BanUser(command::BanUserCommand {
+                room: "room-1".into(),
+                user_id: "user-2".into(),
+                duration_secs: None,
+                request_id: request_id.clone(),
+            }),
+            UserCommand::UnbanUser(command::UnbanUserCommand {
+                room: "room-1".into(),
+                user_id: "user-2".into(),
+                request_id: None,
+            }),
+            UserCommand::MuteUser(command::MuteUserCommand {
+                room: "room-1".into(),
+                user_id: "user-2".into(),
+                duration_secs: Some(u64::MAX),
+                request_id: None,
+            }),
+            UserCommand::UnmuteUser(command::UnmuteUserCommand {
+                room: "room-1".into(),
+                user_id: "user-2".into(),
+                request_id: request_id.clone(),
+            }),
+            UserCommand::TypingStarted(command::TypingStartedCommand {
+                room: "room-1".into(),
+                request_id: None,
+            }),
+            UserCommand::TypingStopped(command::TypingStoppedCommand {
+                room: "room-1".into(),
+                request_id: request_id.clone(),
+            }),
+            UserCommand::Ping(command::PingCommand {
+                timestamp: u64::MAX,
+                request_id: None,
+            }),
+            UserCommand::Pong(command::PongCommand {
+                timestamp: 0,
+                request_id: request_id.clone(),
+            }),
+            UserCommand::Quit(command::QuitCommand { request_id: None }),
+        ])
+        .await;
+    }
+
+    // every variant of the events, new ones have to be added here as well
+    #[tokio::test]
+    async fn test_event_round_trip() {
+        let request_id = Some(String::from("request-1"));
+        let message = event::UserMessageBroadcastEvent {
+            id: 7,
+            timestamp: 1_700_000_000_000,
+            room: "room-1".into(),
+            user_id: "user-1".into(),
+            content: "content-1".into(),
+            unrecorded: false,
+        };
+
+        assert_round_trip(vec![
+            Event::Hello(event::HelloReplyEvent {
+                version: 1,
+                capabilities: Vec::new(),
+                request_id: request_id.clone(),
+            }),
+            Event::LoginSuccessful(event::LoginSuccessfulReplyEvent {
+                session_id: "session-1".into(),
+                user_id: "user-1".into(),
+                display_name: "User 1".into(),
+                rooms: vec![event::RoomDetail {
+                    name: "room-1".into(),
+                    description: "description".into(),
+                }],
+                resume_token: Some("token-1".into()),
+                request_id: request_id.clone(),
+            }),
+            Event::SessionResumed(event::SessionResumedReplyEvent {
+                session_id: "session-1".into(),
+                user_id: "user-1".into(),
+                display_name: "User 1".into(),
+                rooms: Vec::new(),
+                joined_rooms: vec!["room-1".into()],
+                resume_token: "token-2".into(),
+                request_id: request_id.clone(),
+            }),
+            Event::RoomParticipation(event::RoomParticipationBroadcastEvent {
+                room: "room-1".into(),
+                user_id: "user-1".into(),
+                display_name: "User 1".into(),
+                status: event::RoomParticipationStatus::Joined,
+            }),
+            Event::UserJoinedRoom(event::UserJoinedRoomReplyEvent {
+                room: "room-1".into(),
+                users: vec![event::UserDetail {
+                    user_id: "user-1".into(),
+                    display_name: "User 1".into(),
+                }],
+                request_id: None,
+            }),
+            Event::UserMessage(message.clone()),
+            Event::RoomHistory(event::RoomHistoryReplyEvent {
+                room: "room-1".into(),
+                messages: vec![message.clone()],
+                before: Some(8),
+                next_cursor: None,
+                request_id: request_id.clone(),
+            }),
+            Event::RoomResync(event::RoomResyncEvent {
+                room: "room-1".into(),
+                skipped: 3,
+                messages: vec![message],
+            }),
+            Event::NicknameChanged(event::NicknameChangedBroadcastEvent {
+                room: None,
+                user_id: "user-1".into(),
+                nickname: "nick".into(),
+                request_id: None,
+            }),
+            Event::DirectMessage(event::DirectMessageEvent {
+                id: 1,
+                timestamp: 1_700_000_000_000,
+                user_id: "user-1".into(),
+                display_name: "User 1".into(),
+                recipient: "user-2".into(),
+                content: "content-1".into(),
+                request_id: request_id.clone(),
+            }),
+            Event::RoomCreated(event::RoomCreatedBroadcastEvent {
+                room: event::RoomDetail {
+                    name: "room-2".into(),
+                    description: "description".into(),
+                },
+            }),
+            Event::RoomDeleted(event::RoomDeletedBroadcastEvent {
+                room: "room-2".into(),
+            }),
+            Event::UserModerated(event::UserModeratedBroadcastEvent {
+                room: "room-1".into(),
+                user_id: "user-2".into(),
+                moderator_id: "user-1".into(),
+                action: event::ModerationAction::Muted,
+                expires_at: Some(1_700_000_000_000),
+            }),
+            Event::UserTyping(event::UserTypingBroadcastEvent {
+                room: "room-1".into(),
+                user_id: "user-1".into(),
+                typing: false,
+            }),
+            Event::Ping(event::PingEvent { timestamp: 1 }),
+            Event::Pong(event::PongReplyEvent {
+                timestamp: u64::MAX,
+                request_id: None,
+            }),
+            Event::CommandError(event::CommandErrorReplyEvent {
+                room: Some("room-1".into()),
+                code: event::CommandErrorCode::NotJoined,
+                message: "you have to join the room first".into(),
+                retry_after_ms: Some(250),
+                request_id,
+            }),
+        ])
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_frames_above_the_limit_are_refused() {
+        for codec in codecs() {
+            let frame = codec
+                .encode(&UserCommand::Quit(command::QuitCommand {
+                    request_id: Some("r".repeat(100)),
+                }))
+                .unwrap();
+
+            let err = codec
+                .read_frames(std::io::Cursor::new(frame), 50, "could not read")
+                .next()
+                .await
+                .unwrap()
+                .unwrap_err();
+
+            assert!(err.is::<FrameTooLargeError>(), "{} codec", codec);
+        }
+    }
+
+    #[test]
+    fn test_codec_names() {
+        for codec in codecs() {
+            assert_eq!(codec.to_string().parse::<Codec>().unwrap(), codec);
+        }
+        assert!("xml".parse::<Codec>().is_err());
+    }
+}
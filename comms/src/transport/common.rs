@@ -1,7 +1,33 @@
-use std::pin::Pin;
+use std::{fmt, pin::Pin};
 
+use tokio::io::AsyncWrite;
 use tokio_stream::Stream;
 
 pub const NEW_LINE: &[u8; 2] = b"\r\n";
 
+/// Maximum size of a single frame, e.g. a serialized command or event, in bytes unless configured otherwise
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
+
 pub type BoxedStream<Item> = Pin<Box<dyn Stream<Item = Item> + Send>>;
+pub type BoxedWriter = Pin<Box<dyn AsyncWrite + Send>>;
+
+/// [FrameTooLargeError] is the error of a frame which is larger than the maximum frame size
+///
+/// The frame is not buffered beyond the limit. The peer can not be trusted to follow the protocol anymore,
+/// hence the connection should be closed after this error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameTooLargeError {
+    pub max_frame_size: usize,
+}
+
+impl fmt::Display for FrameTooLargeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "frame exceeds the maximum size of {} bytes",
+            self.max_frame_size
+        )
+    }
+}
+
+impl std::error::Error for FrameTooLargeError {}
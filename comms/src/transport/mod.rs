@@ -2,7 +2,17 @@
 #[cfg(feature = "client")]
 pub mod client;
 #[cfg(any(feature = "client", feature = "server"))]
+mod codec;
+#[cfg(any(feature = "client", feature = "server"))]
 mod common;
+#[cfg(any(feature = "client", feature = "server"))]
+pub use codec::Codec;
+#[cfg(any(feature = "client", feature = "server"))]
+pub use common::{FrameTooLargeError, DEFAULT_MAX_FRAME_SIZE};
 /// Transport over TCP implementation for a server to interact with a single client TCP Stream
 #[cfg(feature = "server")]
 pub mod server;
+/// TLS for the transports, the TLS streams are split like any other stream
+/// Requires the 'tls' feature to be enabled
+#[cfg(feature = "tls")]
+pub mod tls;
@@ -1,13 +1,19 @@
 use anyhow::Context;
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    net::{tcp::OwnedWriteHalf, TcpStream},
+    io::{self, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
 };
-use tokio_stream::{wrappers::LinesStream, StreamExt};
+use tokio_stream::StreamExt;
 
-use crate::{command, event};
+use crate::{
+    command, event,
+    protocol::{NegotiatedProtocol, CAPABILITIES, PROTOCOL_VERSION},
+};
 
-use super::common::{BoxedStream, NEW_LINE};
+use super::{
+    codec::Codec,
+    common::{BoxedStream, BoxedWriter, DEFAULT_MAX_FRAME_SIZE},
+};
 
 /// [CommandStream] is a stream of [command::UserCommand]s sent by the client
 ///
@@ -17,17 +23,73 @@ use super::common::{BoxedStream, NEW_LINE};
 /// without the risk of missing commands.
 pub type CommandStream = BoxedStream<anyhow::Result<command::UserCommand>>;
 
-/// [EventWriter] is a wrapper around a [TcpStream] which writes [event::Event]s to the client
+/// [EventWriter] is a wrapper around the write half of a stream which writes [event::Event]s to the client
 pub struct EventWriter {
-    writer: OwnedWriteHalf,
+    writer: BoxedWriter,
+    codec: Codec,
+    negotiated: Option<NegotiatedProtocol>,
 }
 
 impl EventWriter {
-    pub fn new(writer: OwnedWriteHalf) -> Self {
-        Self { writer }
+    pub fn new<W: AsyncWrite + Send + 'static>(writer: W) -> Self {
+        Self::with_codec(writer, Codec::default())
+    }
+
+    pub fn with_codec<W: AsyncWrite + Send + 'static>(writer: W, codec: Codec) -> Self {
+        Self {
+            writer: Box::pin(writer),
+            codec,
+            negotiated: None,
+        }
+    }
+
+    /// The codec the events are written with
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// The protocol agreed on with the client, `None` until the client has sent a hello
+    pub fn negotiated(&self) -> Option<&NegotiatedProtocol> {
+        self.negotiated.as_ref()
+    }
+
+    /// Reply to the hello of the client with the version and the capabilities of the server,
+    /// or with an [event::CommandErrorCode::UnsupportedProtocolVersion] error if the client speaks an unsupported version
+    ///
+    /// Returns the negotiated protocol, which is kept by the writer as well,
+    /// or `None` if the client has been rejected and should be disconnected
+    pub async fn reply_hello(
+        &mut self,
+        hello: command::HelloCommand,
+    ) -> anyhow::Result<Option<NegotiatedProtocol>> {
+        match NegotiatedProtocol::negotiate(CAPABILITIES, hello.version, &hello.capabilities) {
+            Ok(negotiated) => {
+                self.write(&event::Event::Hello(event::HelloReplyEvent {
+                    version: PROTOCOL_VERSION,
+                    capabilities: CAPABILITIES.iter().map(|c| String::from(*c)).collect(),
+                    request_id: hello.request_id,
+                }))
+                .await?;
+                self.negotiated = Some(negotiated.clone());
+
+                Ok(Some(negotiated))
+            }
+            Err(err) => {
+                self.write(&event::Event::CommandError(event::CommandErrorReplyEvent {
+                    room: None,
+                    code: event::CommandErrorCode::UnsupportedProtocolVersion,
+                    message: err.to_string(),
+                    retry_after_ms: None,
+                    request_id: hello.request_id,
+                }))
+                .await?;
+
+                Ok(None)
+            }
+        }
     }
 
-    /// Send a [event::Event] to the backing [TcpStream]
+    /// Send a [event::Event] to the backing stream
     ///
     /// # Cancel Safety
     ///
@@ -37,33 +99,132 @@ impl EventWriter {
     /// partially written, but future calls to `write` will start over
     /// from the beginning of the buffer. Causing undefined behaviour.
     pub async fn write(&mut self, event: &event::Event) -> anyhow::Result<()> {
-        let mut serialized_bytes = serde_json::to_vec(event)?;
-        serialized_bytes.extend_from_slice(NEW_LINE);
+        let frame = self.codec.encode(event)?;
+
+        self.writer.write_all(frame.as_slice()).await?;
+
+        Ok(())
+    }
 
-        self.writer.write_all(serialized_bytes.as_slice()).await?;
+    /// Close the write half of the stream, which lets a TLS client tell a closed connection from a truncated one
+    pub async fn shutdown(&mut self) -> anyhow::Result<()> {
+        self.writer.shutdown().await?;
 
         Ok(())
     }
 }
 
 /// Splits a TCP stream into a stream of commands and an event writer.
+/// The default [Codec] is used, and frames sent by the client are limited to [DEFAULT_MAX_FRAME_SIZE].
 ///
 /// # Arguments
 ///
 /// - `stream` - A [TcpStream] to split
 pub fn split_tcp_stream(stream: TcpStream) -> (CommandStream, EventWriter) {
+    split_tcp_stream_with_codec(stream, Codec::default(), DEFAULT_MAX_FRAME_SIZE)
+}
+
+/// Splits a TCP stream into a stream of commands and an event writer using the given codec.
+/// A frame larger than the given size fails with a [super::FrameTooLargeError] without being read further.
+///
+/// # Arguments
+///
+/// - `stream` - A [TcpStream] to split
+/// - `codec` - The [Codec] both ends of the connection use
+/// - `max_frame_size` - The maximum size of a frame sent by the client, in bytes
+pub fn split_tcp_stream_with_codec(
+    stream: TcpStream,
+    codec: Codec,
+    max_frame_size: usize,
+) -> (CommandStream, EventWriter) {
     let (reader, writer) = stream.into_split();
 
+    split_halves(reader, writer, codec, max_frame_size)
+}
+
+/// Splits any stream, e.g. a Unix socket or a TLS stream, into a stream of commands and an event writer.
+/// The default [Codec] is used, and frames sent by the client are limited to [DEFAULT_MAX_FRAME_SIZE].
+///
+/// # Arguments
+///
+/// - `stream` - A stream to split
+pub fn split_stream<S>(stream: S) -> (CommandStream, EventWriter)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    split_stream_with_codec(stream, Codec::default(), DEFAULT_MAX_FRAME_SIZE)
+}
+
+/// Splits any stream, e.g. a Unix socket or a TLS stream, into a stream of commands and an event writer using the given codec.
+/// A frame larger than the given size fails with a [super::FrameTooLargeError] without being read further.
+///
+/// # Arguments
+///
+/// - `stream` - A stream to split
+/// - `codec` - The [Codec] both ends of the connection use
+/// - `max_frame_size` - The maximum size of a frame sent by the client, in bytes
+pub fn split_stream_with_codec<S>(
+    stream: S,
+    codec: Codec,
+    max_frame_size: usize,
+) -> (CommandStream, EventWriter)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (reader, writer) = io::split(stream);
+
+    split_halves(reader, writer, codec, max_frame_size)
+}
+
+fn split_halves<R, W>(
+    reader: R,
+    writer: W,
+    codec: Codec,
+    max_frame_size: usize,
+) -> (CommandStream, EventWriter)
+where
+    R: AsyncRead + Send + 'static,
+    W: AsyncWrite + Send + 'static,
+{
     (
         Box::pin(
-            LinesStream::new(BufReader::new(reader).lines()).map(|line| {
-                line.context("could not read line from the client")
-                    .and_then(|line| {
-                        serde_json::from_str::<command::UserCommand>(&line)
+            codec
+                .read_frames(reader, max_frame_size, "could not read from the client")
+                .map(move |frame| {
+                    frame.and_then(|frame| {
+                        codec
+                            .decode::<command::UserCommand>(&frame)
                             .context("failed to deserialize command from client")
                     })
-            }),
+                }),
         ),
-        EventWriter::new(writer),
+        EventWriter::with_codec(writer, codec),
     )
 }
+
+/// Splits any stream into a stream of commands and an event writer, using the [Codec] the client has picked.
+/// The codec is told apart by the first byte the client sends, which is not consumed:
+/// a JSON line starts with `{`, while a length prefix starts with a zero byte for any frame below 16 MiB.
+///
+/// # Arguments
+///
+/// - `stream` - A stream the client has not sent anything over yet
+/// - `max_frame_size` - The maximum size of a frame sent by the client, in bytes
+pub async fn split_stream_detecting_codec<S>(
+    stream: S,
+    max_frame_size: usize,
+) -> io::Result<(CommandStream, EventWriter)>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (reader, writer) = io::split(stream);
+    let mut reader = BufReader::new(reader);
+
+    let codec = match reader.fill_buf().await?.first() {
+        #[cfg(feature = "msgpack")]
+        Some(0) => Codec::MessagePack,
+        _ => Codec::JsonLines,
+    };
+
+    Ok(split_halves(reader, writer, codec, max_frame_size))
+}
@@ -0,0 +1,273 @@
+use std::{fmt, fs::File, io::BufReader, path::Path, str::FromStr, sync::Arc};
+
+use anyhow::Context;
+use tokio_rustls::rustls::{
+    self,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{ring::default_provider, CryptoProvider},
+    pki_types::{CertificateDer, PrivateKeyDer, UnixTime},
+    DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+
+pub use tokio_rustls::{client, rustls::pki_types::ServerName, server, TlsAcceptor, TlsConnector};
+
+/// [ServerTrust] is how a client decides whether to trust the certificate of the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerTrust {
+    /// The certificate has to be issued by one of the well known public CAs
+    PublicCas,
+    /// The certificate has to be issued by one of the CAs in the PEM file
+    CaFile(std::path::PathBuf),
+    /// The certificate has to be the one with the fingerprint, e.g. a self-signed one.
+    /// Its name and validity period are not checked.
+    Pinned(Fingerprint),
+}
+
+impl FromStr for ServerTrust {
+    type Err = anyhow::Error;
+
+    /// Parses the trust as given by a user, an empty string for the public CAs,
+    /// a `sha256:` fingerprint to pin a certificate, or the path of a CA file otherwise
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            Ok(ServerTrust::PublicCas)
+        } else if s.starts_with("sha256:") {
+            Ok(ServerTrust::Pinned(s.parse()?))
+        } else {
+            Ok(ServerTrust::CaFile(s.into()))
+        }
+    }
+}
+
+/// [Fingerprint] is the SHA-256 digest of a certificate, written as `sha256:` followed by the hex digits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    pub fn of(certificate: &CertificateDer<'_>) -> Self {
+        let digest = ring::digest::digest(&ring::digest::SHA256, certificate.as_ref());
+
+        let mut fingerprint = [0; 32];
+        fingerprint.copy_from_slice(digest.as_ref());
+
+        Fingerprint(fingerprint)
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sha256:")?;
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = anyhow::Error;
+
+    /// Parses a fingerprint, the hex digits may be in any case and separated by colons
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s
+            .strip_prefix("sha256:")
+            .context("a fingerprint has to start with 'sha256:'")?
+            .replace(':', "");
+        anyhow::ensure!(
+            digits.len() == 64 && digits.is_ascii(),
+            "a fingerprint has to have 64 hex digits"
+        );
+
+        let mut fingerprint = [0; 32];
+        for (i, byte) in fingerprint.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16)
+                .context("a fingerprint has to have 64 hex digits")?;
+        }
+
+        Ok(Fingerprint(fingerprint))
+    }
+}
+
+/// Reads all certificates of a PEM file
+pub fn load_certificates(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let file = File::open(path)
+        .with_context(|| format!("could not open the certificate file '{}'", path.display()))?;
+    let certificates = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("invalid certificate file '{}'", path.display()))?;
+    anyhow::ensure!(
+        !certificates.is_empty(),
+        "no certificate in '{}'",
+        path.display()
+    );
+
+    Ok(certificates)
+}
+
+/// Reads the first private key of a PEM file
+fn load_private_key(path: &Path) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let file = File::open(path)
+        .with_context(|| format!("could not open the key file '{}'", path.display()))?;
+
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("invalid key file '{}'", path.display()))?
+        .with_context(|| format!("no private key in '{}'", path.display()))
+}
+
+/// Creates the acceptor of the TLS connections of a server, given the PEM files of its certificate chain and its private key
+pub fn acceptor(certificate_file: &Path, key_file: &Path) -> anyhow::Result<TlsAcceptor> {
+    let config = rustls::ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(
+            load_certificates(certificate_file)?,
+            load_private_key(key_file)?,
+        )
+        .context("the certificate does not match the private key")?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Creates the connector of a client, which trusts the certificates of the servers as configured
+pub fn connector(trust: &ServerTrust) -> anyhow::Result<TlsConnector> {
+    let provider = Arc::new(default_provider());
+    let builder = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let config = match trust {
+        ServerTrust::PublicCas => builder
+            .with_root_certificates(RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            })
+            .with_no_client_auth(),
+        ServerTrust::CaFile(path) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in load_certificates(path)? {
+                roots
+                    .add(certificate)
+                    .with_context(|| format!("invalid CA certificate in '{}'", path.display()))?;
+            }
+
+            builder.with_root_certificates(roots).with_no_client_auth()
+        }
+        ServerTrust::Pinned(fingerprint) => builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedCertificateVerifier {
+                fingerprint: *fingerprint,
+                provider,
+            }))
+            .with_no_client_auth(),
+    };
+
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// Trusts the server certificate with the pinned fingerprint only, the handshake signatures are still verified
+#[derive(Debug)]
+struct PinnedCertificateVerifier {
+    fingerprint: Fingerprint,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertificateVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if Fingerprint::of(end_entity) == self.fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint() {
+        let fingerprint = Fingerprint::of(&CertificateDer::from(b"certificate".to_vec()));
+        let formatted = fingerprint.to_string();
+        assert!(formatted.starts_with("sha256:"));
+        assert_eq!(formatted.parse::<Fingerprint>().unwrap(), fingerprint);
+
+        // the colon separated upper case form printed by openssl is accepted as well
+        let openssl_form = format!(
+            "sha256:{}",
+            formatted["sha256:".len()..]
+                .to_uppercase()
+                .as_bytes()
+                .chunks(2)
+                .map(|pair| std::str::from_utf8(pair).unwrap())
+                .collect::<Vec<_>>()
+                .join(":")
+        );
+        assert_eq!(openssl_form.parse::<Fingerprint>().unwrap(), fingerprint);
+
+        assert!("sha1:00".parse::<Fingerprint>().is_err());
+        assert!("sha256:00".parse::<Fingerprint>().is_err());
+        assert!(format!("sha256:{}", "zz".repeat(32))
+            .parse::<Fingerprint>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_server_trust() {
+        let fingerprint = Fingerprint::of(&CertificateDer::from(b"certificate".to_vec()));
+
+        assert_eq!(" ".parse::<ServerTrust>().unwrap(), ServerTrust::PublicCas);
+        assert_eq!(
+            fingerprint.to_string().parse::<ServerTrust>().unwrap(),
+            ServerTrust::Pinned(fingerprint)
+        );
+        assert_eq!(
+            "certs/ca.pem".parse::<ServerTrust>().unwrap(),
+            ServerTrust::CaFile("certs/ca.pem".into())
+        );
+        assert!("sha256:00".parse::<ServerTrust>().is_err());
+    }
+}
@@ -0,0 +1,80 @@
+use comms::{
+    command::{self, UserCommand},
+    event::{self, Event},
+    protocol::CAPABILITIES,
+    transport::{self, Codec, DEFAULT_MAX_FRAME_SIZE},
+};
+use tokio::io::{duplex, DuplexStream};
+use tokio_stream::StreamExt;
+
+#[tokio::test]
+async fn assert_transport_over_duplex_stream() {
+    for codec in codecs() {
+        let (client_stream, server_stream) = duplex(1024);
+
+        let (server_result, client_result) = tokio::join!(
+            execute_server(server_stream),
+            execute_client(client_stream, codec)
+        );
+
+        assert_eq!(server_result.unwrap(), codec);
+        client_result.unwrap();
+    }
+}
+
+fn codecs() -> Vec<Codec> {
+    vec![
+        Codec::JsonLines,
+        #[cfg(feature = "msgpack")]
+        Codec::MessagePack,
+    ]
+}
+
+/// Replies to the hello, echoes a message back as an event, and returns the codec the client has picked
+async fn execute_server(stream: DuplexStream) -> anyhow::Result<Codec> {
+    let (mut command_stream, mut event_writer) =
+        transport::server::split_stream_detecting_codec(stream, DEFAULT_MAX_FRAME_SIZE).await?;
+
+    let Some(Ok(UserCommand::Hello(hello))) = command_stream.next().await else {
+        anyhow::bail!("expected a hello");
+    };
+    event_writer.reply_hello(hello).await?;
+
+    let Some(Ok(UserCommand::SendMessage(message))) = command_stream.next().await else {
+        anyhow::bail!("expected a message");
+    };
+    event_writer
+        .write(&Event::UserMessage(event::UserMessageBroadcastEvent {
+            id: 1,
+            timestamp: 0,
+            room: message.room,
+            user_id: "user-1".into(),
+            content: message.content,
+            unrecorded: false,
+        }))
+        .await?;
+
+    Ok(event_writer.codec())
+}
+
+async fn execute_client(stream: DuplexStream, codec: Codec) -> anyhow::Result<()> {
+    let (mut event_stream, mut command_writer) =
+        transport::client::split_stream_with_codec(stream, codec, DEFAULT_MAX_FRAME_SIZE);
+
+    transport::client::hello(&mut event_stream, &mut command_writer, CAPABILITIES).await?;
+
+    command_writer
+        .write(&UserCommand::SendMessage(command::SendMessageCommand {
+            room: "room-1".into(),
+            content: "content-1".into(),
+            request_id: None,
+        }))
+        .await?;
+
+    let Some(Ok(Event::UserMessage(message))) = event_stream.next().await else {
+        anyhow::bail!("expected the message to be echoed back");
+    };
+    assert_eq!(message.content, "content-1");
+
+    Ok(())
+}
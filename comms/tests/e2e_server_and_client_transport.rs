@@ -1,18 +1,56 @@
 use comms::{
     command::{self, UserCommand},
     event::{self, Event},
-    transport,
+    transport::{
+        self,
+        client::{CommandWriter, EventStream},
+        server::{CommandStream, EventWriter},
+    },
 };
 use tokio::net::{TcpListener, TcpStream};
 use tokio_stream::StreamExt;
 
 const PORT: usize = 8081;
+#[cfg(feature = "tls")]
+const TLS_CA_PORT: usize = 8084;
+#[cfg(feature = "tls")]
+const TLS_PINNED_PORT: usize = 8085;
 
 #[tokio::test]
 async fn assert_server_client_transport() {
-    let (server_collected_commands, client_collected_events) =
-        tokio::join!(execute_server(), execute_client());
+    assert_collected(tokio::join!(execute_server(), execute_client()));
+}
+
+#[cfg(feature = "tls")]
+#[tokio::test]
+async fn assert_server_client_transport_over_tls_with_custom_ca() {
+    let certificates = tls_support::generate_certificates("custom-ca");
+    let trust = tls_support::ServerTrust::CaFile(certificates.ca_file.clone());
+
+    assert_collected(tokio::join!(
+        tls_support::execute_server(TLS_CA_PORT, &certificates),
+        tls_support::execute_client(TLS_CA_PORT, &trust),
+    ));
+}
+
+#[cfg(feature = "tls")]
+#[tokio::test]
+async fn assert_server_client_transport_over_tls_with_pinned_certificate() {
+    let certificates = tls_support::generate_certificates("pinned");
+    let trust = tls_support::ServerTrust::Pinned(certificates.fingerprint);
+
+    assert_collected(tokio::join!(
+        tls_support::execute_server(TLS_PINNED_PORT, &certificates),
+        tls_support::execute_client(TLS_PINNED_PORT, &trust),
+    ));
+}
 
+fn assert_collected(
+    (server_collected_commands, client_collected_events): (
+        anyhow::Result<Vec<UserCommand>>,
+        anyhow::Result<Vec<Event>>,
+    ),
+) {
     assert!(server_collected_commands.is_ok());
     assert!(client_collected_events.is_ok());
 
@@ -21,10 +59,12 @@ async fn assert_server_client_transport() {
         vec![
             UserCommand::JoinRoom(command::JoinRoomCommand {
                 room: "room-1".into(),
+                request_id: None,
             }),
             UserCommand::SendMessage(command::SendMessageCommand {
                 room: "room-1".into(),
                 content: "content-1".into(),
+                request_id: Some("request-1".into()),
             }),
         ]
     );
@@ -33,8 +73,11 @@ async fn assert_server_client_transport() {
         client_collected_events.unwrap(),
         vec![Event::LoginSuccessful(event::LoginSuccessfulReplyEvent {
             user_id: "user-id-1".into(),
+            display_name: "user-id-1".into(),
             session_id: "session-id-1".into(),
             rooms: Vec::default(),
+            resume_token: None,
+            request_id: None,
         }),]
     );
 }
@@ -52,7 +95,12 @@ async fn execute_server() -> anyhow::Result<Vec<UserCommand>> {
     };
 
     // break the client connection into higher level API for ease of use
-    let (mut command_stream, mut event_writer) = transport::server::split_tcp_stream(tcp_stream);
+    serve_client(transport::server::split_tcp_stream(tcp_stream)).await
+}
+
+async fn serve_client(
+    (mut command_stream, mut event_writer): (CommandStream, EventWriter),
+) -> anyhow::Result<Vec<UserCommand>> {
     // store commands received from the client
     let mut collected_commands = Vec::new();
 
@@ -60,8 +108,11 @@ async fn execute_server() -> anyhow::Result<Vec<UserCommand>> {
     event_writer
         .write(&Event::LoginSuccessful(event::LoginSuccessfulReplyEvent {
             user_id: "user-id-1".into(),
+            display_name: "user-id-1".into(),
             session_id: "session-id-1".into(),
             rooms: Vec::default(),
+            resume_token: None,
+            request_id: None,
         }))
         .await?;
 
@@ -87,7 +138,12 @@ async fn execute_client() -> anyhow::Result<Vec<Event>> {
     };
 
     // break the server connection into higher level API for ease of use
-    let (mut event_stream, mut command_writer) = transport::client::split_tcp_stream(tcp_stream);
+    talk_to_server(transport::client::split_tcp_stream(tcp_stream)).await
+}
+
+async fn talk_to_server(
+    (mut event_stream, mut command_writer): (EventStream, CommandWriter),
+) -> anyhow::Result<Vec<Event>> {
     // store events received from the server
     let mut collected_events = Vec::new();
 
@@ -106,6 +162,7 @@ async fn execute_client() -> anyhow::Result<Vec<Event>> {
     command_writer
         .write(&UserCommand::JoinRoom(command::JoinRoomCommand {
             room: "room-1".into(),
+            request_id: None,
         }))
         .await?;
 
@@ -113,8 +170,87 @@ async fn execute_client() -> anyhow::Result<Vec<Event>> {
         .write(&UserCommand::SendMessage(command::SendMessageCommand {
             room: "room-1".into(),
             content: "content-1".into(),
+            request_id: Some("request-1".into()),
         }))
         .await?;
 
+    // close the connection so that the server stops listening for commands
+    command_writer.shutdown().await?;
+
     Ok(collected_events)
 }
+
+/// The same server and client over TLS, with certificates generated for the test
+#[cfg(feature = "tls")]
+mod tls_support {
+    use std::path::PathBuf;
+
+    use comms::transport::{self, tls};
+    use tokio::net::{TcpListener, TcpStream};
+
+    pub use comms::transport::tls::{Fingerprint, ServerTrust};
+
+    pub struct Certificates {
+        pub ca_file: PathBuf,
+        pub cert_file: PathBuf,
+        pub key_file: PathBuf,
+        pub fingerprint: Fingerprint,
+    }
+
+    /// Generates a CA and a certificate for localhost issued by it, written as PEM files to the temp dir
+    pub fn generate_certificates(name: &str) -> Certificates {
+        let mut ca_params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let key = rcgen::KeyPair::generate().unwrap();
+        let certificate = rcgen::CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .signed_by(&key, &ca, &ca_key)
+            .unwrap();
+
+        let dir =
+            std::env::temp_dir().join(format!("comms-e2e-tls-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let certificates = Certificates {
+            ca_file: dir.join("ca.pem"),
+            cert_file: dir.join("cert.pem"),
+            key_file: dir.join("key.pem"),
+            fingerprint: Fingerprint::of(certificate.der()),
+        };
+        std::fs::write(&certificates.ca_file, ca.pem()).unwrap();
+        std::fs::write(&certificates.cert_file, certificate.pem()).unwrap();
+        std::fs::write(&certificates.key_file, key.serialize_pem()).unwrap();
+
+        certificates
+    }
+
+    pub async fn execute_server(
+        port: usize,
+        certificates: &Certificates,
+    ) -> anyhow::Result<Vec<comms::command::UserCommand>> {
+        let acceptor = tls::acceptor(&certificates.cert_file, &certificates.key_file)?;
+        let listener = TcpListener::bind(format!("0.0.0.0:{}", port))
+            .await
+            .expect("could not bind to the port");
+
+        let (tcp_stream, _addr) = listener.accept().await?;
+        let tls_stream = acceptor.accept(tcp_stream).await?;
+
+        super::serve_client(transport::server::split_stream(tls_stream)).await
+    }
+
+    pub async fn execute_client(
+        port: usize,
+        trust: &ServerTrust,
+    ) -> anyhow::Result<Vec<comms::event::Event>> {
+        let connector = tls::connector(trust)?;
+        let tcp_stream = TcpStream::connect(format!("localhost:{}", port)).await?;
+        let tls_stream = connector
+            .connect(tls::ServerName::try_from("localhost")?, tcp_stream)
+            .await?;
+
+        super::talk_to_server(transport::client::split_stream(tls_stream)).await
+    }
+}
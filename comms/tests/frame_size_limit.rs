@@ -0,0 +1,110 @@
+use comms::{
+    command::{self, UserCommand},
+    event::{self, Event},
+    transport::{self, Codec, FrameTooLargeError},
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_stream::StreamExt;
+
+const PORT: usize = 8082;
+const MAX_FRAME_SIZE: usize = 1024;
+
+#[tokio::test]
+async fn assert_oversized_frames_are_refused() {
+    let (server_result, client_result) = tokio::join!(execute_server(), execute_client());
+
+    server_result.unwrap();
+    client_result.unwrap();
+}
+
+async fn execute_server() -> anyhow::Result<()> {
+    let listener = TcpListener::bind(format!("0.0.0.0:{}", PORT))
+        .await
+        .expect("could not bind to the port");
+    let (tcp_stream, _addr) = listener.accept().await?;
+
+    let (mut command_stream, mut event_writer) = transport::server::split_tcp_stream_with_codec(
+        tcp_stream,
+        Codec::default(),
+        MAX_FRAME_SIZE,
+    );
+
+    // a command within the limit is read as usual
+    let command = command_stream.next().await.expect("connection closed")?;
+    assert_eq!(
+        command,
+        UserCommand::JoinRoom(command::JoinRoomCommand {
+            room: "room-1".into(),
+            request_id: None,
+        })
+    );
+
+    // a frame above the limit is refused with a distinct error, even before its end is received
+    let err = command_stream
+        .next()
+        .await
+        .expect("connection closed")
+        .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<FrameTooLargeError>(),
+        Some(&FrameTooLargeError {
+            max_frame_size: MAX_FRAME_SIZE
+        })
+    );
+
+    // an event within the limit of the client is read as usual
+    event_writer
+        .write(&Event::RoomDeleted(event::RoomDeletedBroadcastEvent {
+            room: "room-1".into(),
+        }))
+        .await?;
+
+    // the limit holds for the events read by the client as well
+    event_writer
+        .write(&Event::RoomDeleted(event::RoomDeletedBroadcastEvent {
+            room: "r".repeat(MAX_FRAME_SIZE),
+        }))
+        .await?;
+
+    Ok(())
+}
+
+async fn execute_client() -> anyhow::Result<()> {
+    let tcp_stream = TcpStream::connect(format!("localhost:{}", PORT)).await?;
+    let (mut event_stream, mut command_writer) = transport::client::split_tcp_stream_with_codec(
+        tcp_stream,
+        Codec::default(),
+        MAX_FRAME_SIZE,
+    );
+
+    command_writer
+        .write(&UserCommand::JoinRoom(command::JoinRoomCommand {
+            room: "room-1".into(),
+            request_id: None,
+        }))
+        .await?;
+    command_writer
+        .write(&UserCommand::SendMessage(command::SendMessageCommand {
+            room: "room-1".into(),
+            content: "c".repeat(MAX_FRAME_SIZE),
+            request_id: None,
+        }))
+        .await?;
+
+    let event = event_stream.next().await.expect("connection closed")?;
+    assert_eq!(
+        event,
+        Event::RoomDeleted(event::RoomDeletedBroadcastEvent {
+            room: "room-1".into(),
+        })
+    );
+
+    let err = event_stream
+        .next()
+        .await
+        .expect("connection closed")
+        .unwrap_err();
+    assert!(err.is::<FrameTooLargeError>());
+
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use comms::{
+    command::{self, UserCommand},
+    event::{CommandErrorCode, Event},
+    protocol::{capability, CAPABILITIES, PROTOCOL_VERSION},
+    transport,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_stream::StreamExt;
+
+const PORT: usize = 8083;
+
+#[tokio::test]
+async fn assert_protocol_handshake() {
+    let listener = TcpListener::bind(format!("0.0.0.0:{}", PORT))
+        .await
+        .expect("could not bind to the port");
+
+    let (server_result, client_result) = tokio::join!(execute_server(listener), execute_client());
+
+    server_result.unwrap();
+    client_result.unwrap();
+}
+
+async fn execute_server(listener: TcpListener) -> anyhow::Result<()> {
+    // the first client speaks the same version, with fewer capabilities than the server
+    let (tcp_stream, _addr) = listener.accept().await?;
+    let (mut command_stream, mut event_writer) = transport::server::split_tcp_stream(tcp_stream);
+    assert!(event_writer.negotiated().is_none());
+
+    let Some(Ok(UserCommand::Hello(hello))) = command_stream.next().await else {
+        anyhow::bail!("expected a hello");
+    };
+    let negotiated = event_writer.reply_hello(hello).await?.unwrap();
+    assert_eq!(negotiated.version, PROTOCOL_VERSION);
+    assert_eq!(negotiated.capabilities, vec![capability::HISTORY]);
+    assert_eq!(event_writer.negotiated(), Some(&negotiated));
+
+    // the second client speaks a version the server does not support
+    let (tcp_stream, _addr) = listener.accept().await?;
+    let (mut command_stream, mut event_writer) = transport::server::split_tcp_stream(tcp_stream);
+
+    let Some(Ok(UserCommand::Hello(hello))) = command_stream.next().await else {
+        anyhow::bail!("expected a hello");
+    };
+    assert!(event_writer.reply_hello(hello).await?.is_none());
+    assert!(event_writer.negotiated().is_none());
+
+    Ok(())
+}
+
+async fn execute_client() -> anyhow::Result<()> {
+    let tcp_stream = TcpStream::connect(format!("localhost:{}", PORT)).await?;
+    let (mut event_stream, mut command_writer) = transport::client::split_tcp_stream(tcp_stream);
+
+    let negotiated = transport::client::hello(
+        &mut event_stream,
+        &mut command_writer,
+        &[capability::HISTORY, "from_the_future"],
+    )
+    .await?;
+    assert_eq!(negotiated.version, PROTOCOL_VERSION);
+    assert_eq!(negotiated.capabilities, vec![capability::HISTORY]);
+    assert_eq!(command_writer.negotiated(), Some(&negotiated));
+
+    let tcp_stream = TcpStream::connect(format!("localhost:{}", PORT)).await?;
+    let (mut event_stream, mut command_writer) = transport::client::split_tcp_stream(tcp_stream);
+
+    command_writer
+        .write(&UserCommand::Hello(command::HelloCommand {
+            version: 0,
+            capabilities: CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+            request_id: Some("request-1".into()),
+        }))
+        .await?;
+
+    let Some(Ok(Event::CommandError(err))) = event_stream.next().await else {
+        anyhow::bail!("expected the hello to be rejected");
+    };
+    assert_eq!(err.code, CommandErrorCode::UnsupportedProtocolVersion);
+    assert_eq!(err.request_id.as_deref(), Some("request-1"));
+
+    Ok(())
+}
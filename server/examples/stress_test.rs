@@ -1,7 +1,7 @@
 use std::time::Duration;
 
 use comms::{
-    command::{JoinRoomCommand, UserCommand},
+    command::{JoinRoomCommand, RegisterCommand, UserCommand},
     event::Event,
     transport,
 };
@@ -11,12 +11,12 @@ use serde::{Deserialize, Serialize};
 use tokio::{net::TcpStream, task::JoinSet};
 use tokio_stream::StreamExt;
 
-/// Stress Test for the Chat Server
-///
-/// Generates synthetic load with users who joins and sends messages to random roms.
-/// The number of users, number of rooms joined per user and chatting of users can be configured.
-///
-/// !IMPORTANT! Be sure to check and configure your socket limits, before you run the tests
+// Stress Test for the Chat Server
+//
+// Generates synthetic load with users who joins and sends messages to random roms.
+// The number of users, number of rooms joined per user and chatting of users can be configured.
+//
+// !IMPORTANT! Be sure to check and configure your socket limits, before you run the tests
 
 const SERVER_ADDR: &str = "localhost:8080";
 const CHAT_ROOMS_METADATA: &str = include_str!("../resources/chat_rooms_metadata.json");
@@ -73,7 +73,7 @@ async fn spawn_single_user(rooms_to_join: Vec<String>) -> anyhow::Result<()> {
 
     match result.as_ref() {
         Ok(_) => println!("exited without problems"),
-        Err(err) => println!("some error occurred = {}", err.to_string()),
+        Err(err) => println!("some error occurred = {}", err),
     }
 
     result
@@ -83,6 +83,15 @@ async fn spawn_single_user_raw(rooms_to_join: Vec<String>) -> anyhow::Result<()>
     let tcp_stream = TcpStream::connect(SERVER_ADDR).await?;
     let (mut event_stream, mut command_writer) = transport::client::split_tcp_stream(tcp_stream);
 
+    // every simulated user registers a fresh account
+    command_writer
+        .write(&UserCommand::Register(RegisterCommand {
+            username: nanoid!(),
+            password: nanoid!(),
+            request_id: None,
+        }))
+        .await?;
+
     let _login_event = match event_stream.next().await {
         Some(Ok(Event::LoginSuccessful(login_event))) => login_event,
         _ => return Err(anyhow::anyhow!("server did not send login successful")),
@@ -92,6 +101,7 @@ async fn spawn_single_user_raw(rooms_to_join: Vec<String>) -> anyhow::Result<()>
         command_writer
             .write(&UserCommand::JoinRoom(JoinRoomCommand {
                 room: String::from(room_name),
+                request_id: None,
             }))
             .await?;
     }
@@ -113,8 +123,9 @@ async fn spawn_single_user_raw(rooms_to_join: Vec<String>) -> anyhow::Result<()>
                 let _ = command_writer
                     .write(&UserCommand::SendMessage(
                         comms::command::SendMessageCommand {
-                            room: String::from(room_name),
+                            room: room_name,
                             content: nanoid!(),
+                            request_id: None,
                         },
                     ))
                     .await;
@@ -124,7 +135,7 @@ async fn spawn_single_user_raw(rooms_to_join: Vec<String>) -> anyhow::Result<()>
         }
     });
 
-    while let Some(_) = event_stream.next().await {}
+    while event_stream.next().await.is_some() {}
 
     join_handle.abort();
     Ok(())
@@ -164,5 +175,5 @@ async fn main() {
         }
     }
 
-    while let Some(_) = join_set.join_next().await {}
+    while join_set.join_next().await.is_some() {}
 }
@@ -0,0 +1,468 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use anyhow::Context;
+use argon2::password_hash::{rand_core::OsRng, SaltString};
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use comms::event::CommandErrorCode;
+use serde::{Deserialize, Serialize};
+
+use crate::error::CommandError;
+
+const MIN_USERNAME_LENGTH: usize = 3;
+const MAX_USERNAME_LENGTH: usize = 32;
+const MIN_PASSWORD_LENGTH: usize = 8;
+/// Passwords are hashed on every login, so their length is bounded to keep hashing cheap
+const MAX_PASSWORD_LENGTH: usize = 128;
+const MAX_NICKNAME_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A registered account as it is written to the accounts file
+struct Account {
+    username: String,
+    /// Salted argon2 hash of the password in the PHC string format
+    password_hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nickname: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Accounts {
+    by_username: HashMap<String, Account>,
+    /// Usernames by the lowercase nicknames of their accounts, nicknames are unique regardless of case
+    by_nickname: HashMap<String, String>,
+}
+
+impl Accounts {
+    fn insert(&mut self, account: Account) {
+        if let Some(previous) = self.by_username.get(&account.username) {
+            if let Some(nickname) = previous.nickname.as_ref() {
+                self.by_nickname.remove(&nickname.to_lowercase());
+            }
+        }
+        if let Some(nickname) = account.nickname.as_ref() {
+            self.by_nickname
+                .insert(nickname.to_lowercase(), account.username.clone());
+        }
+
+        self.by_username.insert(account.username.clone(), account);
+    }
+
+    /// Whether the name is the username or the nickname of an account other than the given one
+    fn is_name_taken(&self, name: &str, except_username: Option<&str>) -> bool {
+        let is_other = |username: &str| Some(username) != except_username;
+
+        self.by_username
+            .keys()
+            .any(|username| username.eq_ignore_ascii_case(name) && is_other(username))
+            || self
+                .by_nickname
+                .get(&name.to_lowercase())
+                .is_some_and(|username| is_other(username))
+    }
+}
+
+#[derive(Debug)]
+/// [AccountStore] keeps the registered accounts of the users and authenticates them
+///
+/// Passwords are never stored, only their salted argon2 hashes. Accounts are appended to a
+/// newline delimited JSON file when the store is backed by one, so they survive restarts.
+/// An account is appended again whenever it changes, the latest line of an account wins.
+pub struct AccountStore {
+    path: Option<PathBuf>,
+    argon2: Argon2<'static>,
+    accounts: Mutex<Accounts>,
+    /// Held while an account is changed, so that the file is written in the same order as the accounts are
+    writer: tokio::sync::Mutex<()>,
+    /// Hash the password of an unknown user is checked against, so that their login takes as long as any other
+    dummy_hash: Arc<OnceLock<String>>,
+}
+
+impl AccountStore {
+    /// Creates a store which only keeps the accounts in memory
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        AccountStore {
+            path: None,
+            argon2: Argon2::default(),
+            accounts: Mutex::new(Accounts::default()),
+            writer: tokio::sync::Mutex::new(()),
+            dummy_hash: Arc::new(OnceLock::new()),
+        }
+    }
+
+    /// Opens a store backed by the given file, reading the accounts registered so far
+    /// The file and its parent directory are created if they do not exist
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut accounts = Accounts::default();
+
+        if path.exists() {
+            let file = File::open(&path)
+                .with_context(|| format!("could not open accounts file {:?}", path))?;
+
+            for line in BufReader::new(file).lines() {
+                let line = line.context("could not read accounts file")?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                let account: Account =
+                    serde_json::from_str(&line).context("could not parse account")?;
+                accounts.insert(account);
+            }
+        } else if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("could not create accounts directory {:?}", dir))?;
+        }
+
+        Ok(AccountStore {
+            path: Some(path),
+            argon2: Argon2::default(),
+            accounts: Mutex::new(accounts),
+            writer: tokio::sync::Mutex::new(()),
+            dummy_hash: Arc::new(OnceLock::new()),
+        })
+    }
+
+    /// Creates an account with the given credentials
+    pub async fn register(&self, username: &str, password: &str) -> Result<(), CommandError> {
+        validate_username(username)?;
+        validate_password(password)?;
+
+        if self.accounts.lock().unwrap().is_name_taken(username, None) {
+            return Err(username_taken(username));
+        }
+
+        let argon2 = self.argon2.clone();
+        let password = String::from(password);
+        // hashing is deliberately slow, so it is kept off the async runtime
+        let password_hash = tokio::task::spawn_blocking(move || {
+            let salt = SaltString::generate(&mut OsRng);
+
+            argon2
+                .hash_password(password.as_bytes(), &salt)
+                .map(|hash| hash.to_string())
+        })
+        .await
+        .map_err(|err| internal_error(anyhow::anyhow!(err)))?
+        .map_err(|err| internal_error(anyhow::anyhow!(err)))?;
+
+        let _writer = self.writer.lock().await;
+        // another session may have taken the username while the password was being hashed
+        if self.accounts.lock().unwrap().is_name_taken(username, None) {
+            return Err(username_taken(username));
+        }
+
+        let account = Account {
+            username: String::from(username),
+            password_hash,
+            nickname: None,
+        };
+        self.persist(&account).await.map_err(internal_error)?;
+        self.accounts.lock().unwrap().insert(account);
+
+        Ok(())
+    }
+
+    /// Checks the given credentials against the registered accounts
+    /// The password of an unknown user is checked all the same, so that the reply does not tell whether the user exists
+    pub async fn authenticate(&self, username: &str, password: &str) -> Result<(), CommandError> {
+        let password_hash = self
+            .accounts
+            .lock()
+            .unwrap()
+            .by_username
+            .get(username)
+            .map(|account| account.password_hash.clone());
+        let is_known = password_hash.is_some();
+
+        let argon2 = self.argon2.clone();
+        let dummy_hash = self.dummy_hash.clone();
+        let password = String::from(password);
+        let is_valid = tokio::task::spawn_blocking(move || {
+            let password_hash = match password_hash {
+                Some(password_hash) => password_hash,
+                None => dummy_hash
+                    .get_or_init(|| {
+                        argon2
+                            .hash_password(b"", &SaltString::generate(&mut OsRng))
+                            .map(|hash| hash.to_string())
+                            .unwrap_or_default()
+                    })
+                    .clone(),
+            };
+
+            PasswordHash::new(&password_hash)
+                .map(|hash| argon2.verify_password(password.as_bytes(), &hash).is_ok())
+        })
+        .await
+        .map_err(|err| internal_error(anyhow::anyhow!(err)))?
+        .map_err(|err| internal_error(anyhow::anyhow!(err)))?;
+
+        if is_known && is_valid {
+            Ok(())
+        } else {
+            Err(invalid_credentials())
+        }
+    }
+
+    /// Sets the nickname of the user, which has to differ from the names of all other users
+    pub async fn set_nickname(&self, username: &str, nickname: &str) -> Result<(), CommandError> {
+        validate_nickname(nickname)?;
+
+        let _writer = self.writer.lock().await;
+        let account = {
+            let accounts = self.accounts.lock().unwrap();
+            if accounts.is_name_taken(nickname, Some(username)) {
+                return Err(CommandError::new(
+                    CommandErrorCode::NicknameTaken,
+                    format!("nickname '{}' is already taken", nickname),
+                ));
+            }
+
+            let Some(account) = accounts.by_username.get(username) else {
+                return Err(internal_error(anyhow::anyhow!(
+                    "account '{}' does not exist",
+                    username
+                )));
+            };
+
+            Account {
+                nickname: Some(String::from(nickname)),
+                ..account.clone()
+            }
+        };
+        self.persist(&account).await.map_err(internal_error)?;
+        self.accounts.lock().unwrap().insert(account);
+
+        Ok(())
+    }
+
+    /// Whether an account with the given username has been registered
+    pub fn exists(&self, username: &str) -> bool {
+        self.accounts
+            .lock()
+            .unwrap()
+            .by_username
+            .contains_key(username)
+    }
+
+    /// The name to display for the user, their nickname if they have set one or their username otherwise
+    pub fn display_name(&self, username: &str) -> String {
+        self.accounts
+            .lock()
+            .unwrap()
+            .by_username
+            .get(username)
+            .and_then(|account| account.nickname.clone())
+            .unwrap_or_else(|| String::from(username))
+    }
+
+    /// Appends the account to the file, the file is written off the async runtime
+    async fn persist(&self, account: &Account) -> anyhow::Result<()> {
+        let Some(path) = self.path.clone() else {
+            return Ok(());
+        };
+
+        let mut line = serde_json::to_string(account).context("could not serialize account")?;
+        line.push('\n');
+
+        tokio::task::spawn_blocking(move || {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("could not open accounts file {:?}", path))?;
+            file.write_all(line.as_bytes())
+                .context("could not write to accounts file")
+        })
+        .await?
+    }
+}
+
+fn validate_username(username: &str) -> Result<(), CommandError> {
+    let is_valid_length = (MIN_USERNAME_LENGTH..=MAX_USERNAME_LENGTH).contains(&username.len());
+    let is_valid_charset = username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    if is_valid_length && is_valid_charset {
+        Ok(())
+    } else {
+        Err(CommandError::new(
+            CommandErrorCode::InvalidCommand,
+            format!(
+                "username must be {} to {} characters of letters, digits, '_' or '-'",
+                MIN_USERNAME_LENGTH, MAX_USERNAME_LENGTH
+            ),
+        ))
+    }
+}
+
+fn validate_password(password: &str) -> Result<(), CommandError> {
+    if (MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&password.chars().count()) {
+        Ok(())
+    } else {
+        Err(CommandError::new(
+            CommandErrorCode::InvalidCommand,
+            format!(
+                "password must be {} to {} characters long",
+                MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH
+            ),
+        ))
+    }
+}
+
+fn validate_nickname(nickname: &str) -> Result<(), CommandError> {
+    let length = nickname.chars().count();
+    let is_valid = length > 0
+        && length <= MAX_NICKNAME_LENGTH
+        && nickname.trim() == nickname
+        && !nickname.chars().any(char::is_control);
+
+    if is_valid {
+        Ok(())
+    } else {
+        Err(CommandError::new(
+            CommandErrorCode::InvalidCommand,
+            format!(
+                "nickname must be 1 to {} characters without leading or trailing whitespace",
+                MAX_NICKNAME_LENGTH
+            ),
+        ))
+    }
+}
+
+fn username_taken(username: &str) -> CommandError {
+    CommandError::new(
+        CommandErrorCode::UsernameTaken,
+        format!("username '{}' is already taken", username),
+    )
+}
+
+fn invalid_credentials() -> CommandError {
+    CommandError::new(
+        CommandErrorCode::InvalidCredentials,
+        "invalid username or password",
+    )
+}
+
+fn internal_error(err: anyhow::Error) -> CommandError {
+    println!("account store failure: {:#}", err);
+
+    CommandError::new(CommandErrorCode::Internal, "could not process the account")
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::{Algorithm, Params, Version};
+    use nanoid::nanoid;
+
+    use super::*;
+
+    /// Removes the accounts file once the test is done
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new() -> Self {
+            TempFile(std::env::temp_dir().join(format!("chat-accounts-{}.jsonl", nanoid!())))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// The default argon2 parameters are too slow for unoptimized test builds
+    fn fast(store: AccountStore) -> AccountStore {
+        AccountStore {
+            argon2: Argon2::new(
+                Algorithm::Argon2id,
+                Version::V0x13,
+                Params::new(Params::MIN_M_COST, 1, 1, None).unwrap(),
+            ),
+            ..store
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_and_authenticate() {
+        let store = fast(AccountStore::in_memory());
+
+        store.register("alice", "password-1").await.unwrap();
+
+        assert!(store.authenticate("alice", "password-1").await.is_ok());
+        assert!(store.authenticate("alice", "password-2").await.is_err());
+        assert!(store.authenticate("bob", "password-1").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_username_taken() {
+        let store = fast(AccountStore::in_memory());
+
+        store.register("alice", "password-1").await.unwrap();
+
+        assert!(store.register("alice", "password-2").await.is_err());
+        assert!(store.authenticate("alice", "password-1").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_credentials_are_refused() {
+        let store = fast(AccountStore::in_memory());
+
+        assert!(store.register("al", "password-1").await.is_err());
+        assert!(store.register("alice bob", "password-1").await.is_err());
+        assert!(store.register("alice", "short").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_nicknames_are_unique() {
+        let store = fast(AccountStore::in_memory());
+        store.register("alice", "password-1").await.unwrap();
+        store.register("bob", "password-1").await.unwrap();
+
+        assert_eq!(store.display_name("alice"), "alice");
+
+        store.set_nickname("alice", "Wonder").await.unwrap();
+        assert_eq!(store.display_name("alice"), "Wonder");
+
+        // neither the nickname nor the username of another user can be taken
+        assert!(store.set_nickname("bob", "wonder").await.is_err());
+        assert!(store.set_nickname("bob", "Alice").await.is_err());
+        assert!(store.register("wonder", "password-1").await.is_err());
+
+        // the old nickname is released once it is changed
+        store.set_nickname("alice", "alice").await.unwrap();
+        store.set_nickname("bob", "Wonder").await.unwrap();
+        assert_eq!(store.display_name("bob"), "Wonder");
+    }
+
+    #[tokio::test]
+    async fn test_accounts_survive_restart() {
+        let file = TempFile::new();
+
+        let store = fast(AccountStore::open(&file.0).unwrap());
+        store.register("alice", "password-1").await.unwrap();
+        drop(store);
+
+        let store = fast(AccountStore::open(&file.0).unwrap());
+        assert!(store.authenticate("alice", "password-1").await.is_ok());
+        assert!(store.register("alice", "password-2").await.is_err());
+
+        store.set_nickname("alice", "Wonder").await.unwrap();
+        drop(store);
+
+        let store = fast(AccountStore::open(&file.0).unwrap());
+        assert_eq!(store.display_name("alice"), "Wonder");
+        assert!(store.authenticate("alice", "password-1").await.is_ok());
+
+        let contents = fs::read_to_string(&file.0).unwrap();
+        assert!(!contents.contains("password-1"));
+    }
+}
@@ -0,0 +1,904 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+    time::Duration,
+};
+
+use anyhow::Context;
+use clap::{Parser, ValueEnum};
+use comms::transport::DEFAULT_MAX_FRAME_SIZE;
+use serde::Deserialize;
+
+use crate::room_manager::{
+    BlocklistAction, ChatRoomMetadata, DEFAULT_BROADCAST_CHANNEL_CAPACITY,
+    DEFAULT_HISTORY_RETENTION, DEFAULT_ROOM_CHANNEL_CAPACITY,
+};
+
+/// Rooms the server starts with, unless a rooms file is configured
+const DEFAULT_CHAT_ROOMS_METADATA: &str = include_str!("../resources/chat_rooms_metadata.json");
+/// Number of events buffered for a session before they are written to the user
+pub const DEFAULT_SESSION_CHANNEL_CAPACITY: usize = 100;
+/// Maximum length of a message in characters
+pub const DEFAULT_MAX_MESSAGE_LENGTH: usize = 2000;
+/// Upper bound of the durations in seconds, larger ones would overflow the deadlines they are added to
+const MAX_DURATION_SECS: u64 = 24 * 60 * 60;
+/// Upper bound of the channel capacities, the room and broadcast channels allocate their whole buffer upfront
+const MAX_CHANNEL_CAPACITY: usize = 100_000;
+
+/// Command line arguments of the server
+/// Every argument overrides the value of the same setting in the config file
+#[derive(Debug, Parser)]
+#[command(about = "Chat server with rooms, message history and direct messages")]
+pub struct Cli {
+    /// Path of the TOML config file, defaults are used for the settings which are not in the file
+    #[arg(short, long, env = "CHAT_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Address to listen on
+    #[arg(long)]
+    pub bind_address: Option<IpAddr>,
+    /// Port to listen on
+    #[arg(short, long)]
+    pub port: Option<u16>,
+    /// Port to listen on for WebSocket clients, on the same address
+    #[arg(long)]
+    pub websocket_port: Option<u16>,
+    /// Do not listen on TCP, only on the Unix socket
+    #[arg(long)]
+    pub no_tcp: bool,
+    /// Path of a Unix socket to listen on, next to TCP
+    #[arg(long)]
+    pub unix_socket: Option<PathBuf>,
+    /// PEM file with the certificate chain of the server, TCP connections use TLS when it is set
+    #[arg(long, env = "CHAT_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+    /// PEM file with the private key of the server certificate
+    #[arg(long, env = "CHAT_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+    /// JSON file with the rooms to create at startup, the built-in rooms are used otherwise
+    #[arg(long)]
+    pub rooms_file: Option<PathBuf>,
+    /// User id owning the startup rooms which do not name an owner in the rooms file
+    #[arg(long)]
+    pub room_owner: Option<String>,
+    /// File the registered accounts are persisted to
+    #[arg(long, env = "CHAT_ACCOUNTS_FILE")]
+    pub accounts_file: Option<PathBuf>,
+    /// Store to record the message history in
+    #[arg(long, env = "CHAT_HISTORY_STORE")]
+    pub history_store: Option<HistoryStoreKind>,
+    /// Directory of the on disk history store
+    #[arg(long, env = "CHAT_HISTORY_DIR")]
+    pub history_dir: Option<PathBuf>,
+    /// Number of messages to keep per room
+    #[arg(long, env = "CHAT_HISTORY_RETENTION")]
+    pub history_retention: Option<usize>,
+    /// Number of events buffered for each participant of a room
+    #[arg(long)]
+    pub room_channel_capacity: Option<usize>,
+    /// Number of events buffered for each session for the events sent to all sessions
+    #[arg(long)]
+    pub broadcast_channel_capacity: Option<usize>,
+    /// Number of events buffered for a session before they are written to the user
+    #[arg(long)]
+    pub session_channel_capacity: Option<usize>,
+    /// Seconds a connection can be idle before the server pings the client
+    #[arg(long)]
+    pub heartbeat_interval_secs: Option<u64>,
+    /// Seconds a connection can be idle before the server closes it
+    #[arg(long)]
+    pub idle_timeout_secs: Option<u64>,
+    /// Seconds a session is kept after its connection is lost, so that the user can resume it, 0 disables resuming
+    #[arg(long)]
+    pub resume_grace_period_secs: Option<u64>,
+    /// Maximum number of open connections
+    #[arg(long)]
+    pub max_connections: Option<usize>,
+    /// Maximum number of open connections from a single IP address
+    #[arg(long)]
+    pub max_connections_per_ip: Option<usize>,
+    /// Maximum size of a command sent by a client in bytes, the client is disconnected above it
+    #[arg(long)]
+    pub max_frame_size: Option<usize>,
+    /// Maximum length of a message in characters
+    #[arg(long)]
+    pub max_message_length: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryStoreKind {
+    /// Keeps the history in memory, it is lost when the server restarts
+    Memory,
+    /// Keeps the history in a segmented log on disk
+    Log,
+}
+
+/// What happens to the room and direct messages containing control characters or terminal escape sequences
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ControlCharacters {
+    /// The escape sequences and control characters are removed, line breaks and tabs become spaces
+    Strip,
+    /// The message is refused
+    Reject,
+}
+
+/// [Config] holds the settings of the server, read from the config file and the command line arguments
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    /// Whether to listen on TCP, on the bind address and the port
+    pub tcp: bool,
+    /// Port to listen on for WebSocket clients, on the bind address
+    pub websocket_port: Option<u16>,
+    pub unix_socket: Option<PathBuf>,
+    pub rooms_file: Option<PathBuf>,
+    /// User id owning the startup rooms which do not name an owner, without it nobody can delete or moderate them
+    pub room_owner: Option<String>,
+    pub accounts_file: PathBuf,
+    pub history: HistoryConfig,
+    pub channels: ChannelsConfig,
+    pub heartbeat: HeartbeatConfig,
+    pub resume: ResumeConfig,
+    pub limits: LimitsConfig,
+    pub rate_limits: RateLimitsConfig,
+    pub messages: MessagesConfig,
+    pub message_hooks: MessageHooksConfig,
+    pub tls: TlsConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    pub store: HistoryStoreKind,
+    pub dir: PathBuf,
+    pub retention: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChannelsConfig {
+    pub room_capacity: usize,
+    pub broadcast_capacity: usize,
+    pub session_capacity: usize,
+}
+
+/// Detection of dead connections, a connection is idle while the client sends nothing
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeartbeatConfig {
+    pub interval_secs: u64,
+    pub idle_timeout_secs: u64,
+}
+
+/// Resuming the sessions that lose their connection, with the resume token handed out on login
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResumeConfig {
+    /// Seconds a session is kept in its rooms after its connection is lost, resuming is disabled if it is 0
+    pub grace_period_secs: u64,
+}
+
+/// Limits of the open connections, there is no limit for the connection counts that are not set
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub max_connections: Option<usize>,
+    pub max_connections_per_ip: Option<usize>,
+    /// Maximum size of a single command in bytes
+    pub max_frame_size: usize,
+}
+
+/// Validation of the content of the room and direct messages
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MessagesConfig {
+    /// Maximum length of a message in characters, counted after the control characters are stripped
+    pub max_length: usize,
+    pub control_characters: ControlCharacters,
+}
+
+/// The built-in message hooks of the rooms, see [crate::room_manager::MessageHook]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MessageHooksConfig {
+    /// Words blocked in every room, compared case insensitively
+    pub blocked_words: Vec<String>,
+    /// What happens to the messages containing a blocked word, of every room
+    pub blocked_words_action: BlocklistAction,
+    /// Maximum number of links in a message of every room, there is no limit if it is not set
+    pub max_links: Option<usize>,
+    /// Hooks of single rooms by room name, which run after the ones of every room
+    pub rooms: HashMap<String, RoomMessageHooksConfig>,
+}
+
+/// The built-in message hooks of a single room
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoomMessageHooksConfig {
+    pub blocked_words: Vec<String>,
+    pub max_links: Option<usize>,
+}
+
+/// Budget of a kind of commands of a session, `burst` commands can be sent at once,
+/// and the budget is refilled with `per_sec` commands every second
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateBudget {
+    pub burst: u32,
+    pub per_sec: f64,
+}
+
+/// Rate limits of the commands of every session, only set in the config file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitsConfig {
+    /// Room and direct messages
+    pub messages: RateBudget,
+    /// Joining rooms
+    pub joins: RateBudget,
+    /// Typing notifications, the ones above the budget are dropped without an error
+    pub typing: RateBudget,
+    /// Registering, logging in and resuming a session, each attempt hashes a password or checks a token
+    pub auth: RateBudget,
+    /// Every other command, except quitting and the heartbeat
+    pub commands: RateBudget,
+    /// Number of rate limited commands a session can send within the violation window before it is disconnected
+    pub max_violations: u32,
+    pub violation_window_secs: u64,
+    /// Budgets of single rooms by room name, replacing the global ones for the messages sent to and the joins of the room
+    pub rooms: HashMap<String, RoomRateLimitsConfig>,
+}
+
+/// Budgets of a single room, the global budget is used for the ones which are not set
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoomRateLimitsConfig {
+    pub messages: Option<RateBudget>,
+    pub joins: Option<RateBudget>,
+}
+
+/// TLS of the TCP connections, the connections are in plaintext unless both files are set
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert_file: Option<PathBuf>,
+    pub key_file: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 8080,
+            tcp: true,
+            websocket_port: None,
+            unix_socket: None,
+            rooms_file: None,
+            room_owner: None,
+            accounts_file: PathBuf::from("data/accounts.jsonl"),
+            history: HistoryConfig::default(),
+            channels: ChannelsConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
+            resume: ResumeConfig::default(),
+            limits: LimitsConfig::default(),
+            rate_limits: RateLimitsConfig::default(),
+            messages: MessagesConfig::default(),
+            message_hooks: MessageHooksConfig::default(),
+            tls: TlsConfig::default(),
+        }
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            store: HistoryStoreKind::Memory,
+            dir: PathBuf::from("data/history"),
+            retention: DEFAULT_HISTORY_RETENTION,
+        }
+    }
+}
+
+impl Default for ChannelsConfig {
+    fn default() -> Self {
+        ChannelsConfig {
+            room_capacity: DEFAULT_ROOM_CHANNEL_CAPACITY,
+            broadcast_capacity: DEFAULT_BROADCAST_CHANNEL_CAPACITY,
+            session_capacity: DEFAULT_SESSION_CHANNEL_CAPACITY,
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_connections: None,
+            max_connections_per_ip: None,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+}
+
+impl Default for MessagesConfig {
+    fn default() -> Self {
+        MessagesConfig {
+            max_length: DEFAULT_MAX_MESSAGE_LENGTH,
+            control_characters: ControlCharacters::Strip,
+        }
+    }
+}
+
+impl Default for MessageHooksConfig {
+    fn default() -> Self {
+        MessageHooksConfig {
+            blocked_words: Vec::new(),
+            blocked_words_action: BlocklistAction::Reject,
+            max_links: None,
+            rooms: HashMap::new(),
+        }
+    }
+}
+
+impl Default for RateLimitsConfig {
+    fn default() -> Self {
+        RateLimitsConfig {
+            messages: RateBudget {
+                burst: 10,
+                per_sec: 2.0,
+            },
+            joins: RateBudget {
+                burst: 10,
+                per_sec: 0.5,
+            },
+            typing: RateBudget {
+                burst: 5,
+                per_sec: 1.0,
+            },
+            auth: RateBudget {
+                burst: 5,
+                per_sec: 0.2,
+            },
+            commands: RateBudget {
+                burst: 20,
+                per_sec: 5.0,
+            },
+            max_violations: 20,
+            violation_window_secs: 60,
+            rooms: HashMap::new(),
+        }
+    }
+}
+
+impl RateLimitsConfig {
+    pub fn violation_window(&self) -> Duration {
+        Duration::from_secs(self.violation_window_secs)
+    }
+
+    /// Every budget with the name of its setting
+    fn budgets(&self) -> Vec<(String, RateBudget)> {
+        let mut budgets = vec![
+            (String::from("rate_limits.messages"), self.messages),
+            (String::from("rate_limits.joins"), self.joins),
+            (String::from("rate_limits.typing"), self.typing),
+            (String::from("rate_limits.auth"), self.auth),
+            (String::from("rate_limits.commands"), self.commands),
+        ];
+        for (room, room_config) in self.rooms.iter() {
+            if let Some(messages) = room_config.messages {
+                budgets.push((format!("rate_limits.rooms.{}.messages", room), messages));
+            }
+            if let Some(joins) = room_config.joins {
+                budgets.push((format!("rate_limits.rooms.{}.joins", room), joins));
+            }
+        }
+
+        budgets
+    }
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        HeartbeatConfig {
+            interval_secs: 15,
+            idle_timeout_secs: 60,
+        }
+    }
+}
+
+impl Default for ResumeConfig {
+    fn default() -> Self {
+        ResumeConfig {
+            grace_period_secs: 30,
+        }
+    }
+}
+
+impl ResumeConfig {
+    pub fn grace_period(&self) -> Duration {
+        Duration::from_secs(self.grace_period_secs)
+    }
+}
+
+impl HeartbeatConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_secs)
+    }
+}
+
+impl Config {
+    /// Read the config file given in the arguments, if any, and override its settings with the arguments
+    /// Fails with a description of the problem if the file can not be read or the settings are invalid
+    pub fn load(cli: Cli) -> anyhow::Result<Config> {
+        let config = match cli.config.as_ref() {
+            Some(path) => {
+                let content = std::fs::read_to_string(path).with_context(|| {
+                    format!("could not read the config file '{}'", path.display())
+                })?;
+
+                toml::from_str(&content)
+                    .with_context(|| format!("invalid config file '{}'", path.display()))?
+            }
+            None => Config::default(),
+        };
+
+        let config = config.with_overrides(cli);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn with_overrides(mut self, cli: Cli) -> Config {
+        self.bind_address = cli.bind_address.unwrap_or(self.bind_address);
+        self.port = cli.port.unwrap_or(self.port);
+        self.tcp = self.tcp && !cli.no_tcp;
+        self.websocket_port = cli.websocket_port.or(self.websocket_port);
+        self.unix_socket = cli.unix_socket.or(self.unix_socket);
+        self.rooms_file = cli.rooms_file.or(self.rooms_file);
+        self.room_owner = cli.room_owner.or(self.room_owner);
+        self.accounts_file = cli.accounts_file.unwrap_or(self.accounts_file);
+        self.history.store = cli.history_store.unwrap_or(self.history.store);
+        self.history.dir = cli.history_dir.unwrap_or(self.history.dir);
+        self.history.retention = cli.history_retention.unwrap_or(self.history.retention);
+        self.channels.room_capacity = cli
+            .room_channel_capacity
+            .unwrap_or(self.channels.room_capacity);
+        self.channels.broadcast_capacity = cli
+            .broadcast_channel_capacity
+            .unwrap_or(self.channels.broadcast_capacity);
+        self.channels.session_capacity = cli
+            .session_channel_capacity
+            .unwrap_or(self.channels.session_capacity);
+        self.heartbeat.interval_secs = cli
+            .heartbeat_interval_secs
+            .unwrap_or(self.heartbeat.interval_secs);
+        self.heartbeat.idle_timeout_secs = cli
+            .idle_timeout_secs
+            .unwrap_or(self.heartbeat.idle_timeout_secs);
+        self.resume.grace_period_secs = cli
+            .resume_grace_period_secs
+            .unwrap_or(self.resume.grace_period_secs);
+        self.limits.max_connections = cli.max_connections.or(self.limits.max_connections);
+        self.limits.max_connections_per_ip = cli
+            .max_connections_per_ip
+            .or(self.limits.max_connections_per_ip);
+        self.limits.max_frame_size = cli.max_frame_size.unwrap_or(self.limits.max_frame_size);
+        self.messages.max_length = cli.max_message_length.unwrap_or(self.messages.max_length);
+        self.tls.cert_file = cli.tls_cert.or(self.tls.cert_file);
+        self.tls.key_file = cli.tls_key.or(self.tls.key_file);
+
+        self
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        let positive_settings = [
+            ("history.retention", Some(self.history.retention)),
+            ("channels.room_capacity", Some(self.channels.room_capacity)),
+            (
+                "channels.broadcast_capacity",
+                Some(self.channels.broadcast_capacity),
+            ),
+            (
+                "channels.session_capacity",
+                Some(self.channels.session_capacity),
+            ),
+            (
+                "heartbeat.interval_secs",
+                Some(self.heartbeat.interval_secs as usize),
+            ),
+            ("limits.max_connections", self.limits.max_connections),
+            (
+                "limits.max_connections_per_ip",
+                self.limits.max_connections_per_ip,
+            ),
+            ("limits.max_frame_size", Some(self.limits.max_frame_size)),
+            ("messages.max_length", Some(self.messages.max_length)),
+            (
+                "rate_limits.max_violations",
+                Some(self.rate_limits.max_violations as usize),
+            ),
+            (
+                "rate_limits.violation_window_secs",
+                Some(self.rate_limits.violation_window_secs as usize),
+            ),
+        ];
+
+        for (name, value) in positive_settings {
+            if value == Some(0) {
+                anyhow::bail!("{} must be greater than 0", name);
+            }
+        }
+
+        let durations = [
+            ("heartbeat.interval_secs", self.heartbeat.interval_secs),
+            (
+                "heartbeat.idle_timeout_secs",
+                self.heartbeat.idle_timeout_secs,
+            ),
+            ("resume.grace_period_secs", self.resume.grace_period_secs),
+            (
+                "rate_limits.violation_window_secs",
+                self.rate_limits.violation_window_secs,
+            ),
+        ];
+        for (name, secs) in durations {
+            if secs > MAX_DURATION_SECS {
+                anyhow::bail!("{} must be at most {}", name, MAX_DURATION_SECS);
+            }
+        }
+
+        let capacities = [
+            ("channels.room_capacity", self.channels.room_capacity),
+            (
+                "channels.broadcast_capacity",
+                self.channels.broadcast_capacity,
+            ),
+            ("channels.session_capacity", self.channels.session_capacity),
+        ];
+        for (name, capacity) in capacities {
+            if capacity > MAX_CHANNEL_CAPACITY {
+                anyhow::bail!("{} must be at most {}", name, MAX_CHANNEL_CAPACITY);
+            }
+        }
+
+        // the time to wait for the next command is 1 / per_sec, it is bounded like the other durations
+        for (name, budget) in self.rate_limits.budgets() {
+            if budget.burst == 0
+                || !budget.per_sec.is_finite()
+                || budget.per_sec * (MAX_DURATION_SECS as f64) < 1.0
+            {
+                anyhow::bail!(
+                    "{} must have a burst greater than 0 and a per_sec of at least 1 / {}",
+                    name,
+                    MAX_DURATION_SECS
+                );
+            }
+        }
+
+        if !self.tcp && self.unix_socket.is_none() {
+            anyhow::bail!("unix_socket must be set when tcp is disabled");
+        }
+
+        if self.tcp && self.websocket_port == Some(self.port) {
+            anyhow::bail!("websocket_port must be different from port");
+        }
+
+        if self.tls.cert_file.is_some() != self.tls.key_file.is_some() {
+            anyhow::bail!("tls.cert_file and tls.key_file must be set together");
+        }
+
+        // the client needs to be pinged at least once before the connection is considered dead
+        if self.heartbeat.idle_timeout_secs <= self.heartbeat.interval_secs {
+            anyhow::bail!(
+                "heartbeat.idle_timeout_secs must be greater than heartbeat.interval_secs"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// The rooms to create at startup, read from the rooms file if one is configured
+    /// The rooms which do not name an owner are owned by the configured room owner
+    pub fn chat_rooms(&self) -> anyhow::Result<Vec<ChatRoomMetadata>> {
+        let mut chat_rooms: Vec<ChatRoomMetadata> = match self.rooms_file.as_ref() {
+            Some(path) => {
+                let content = std::fs::read_to_string(path).with_context(|| {
+                    format!("could not read the rooms file '{}'", path.display())
+                })?;
+
+                serde_json::from_str(&content)
+                    .with_context(|| format!("invalid rooms file '{}'", path.display()))?
+            }
+            None => serde_json::from_str(DEFAULT_CHAT_ROOMS_METADATA)
+                .context("could not parse the built-in rooms")?,
+        };
+
+        let mut names = HashSet::new();
+        if let Some(duplicate) = chat_rooms.iter().find(|room| !names.insert(&room.name)) {
+            anyhow::bail!("room '{}' is listed more than once", duplicate.name);
+        }
+
+        for chat_room in chat_rooms.iter_mut() {
+            if chat_room.owner.is_none() {
+                chat_room.owner = self.room_owner.clone();
+            }
+        }
+
+        Ok(chat_rooms)
+    }
+
+    /// The per-room settings of rooms which are not in the given rooms, sorted by name
+    /// They only apply once a room with that name is created at runtime, so they are likely typos
+    pub fn settings_of_unknown_rooms(&self, chat_rooms: &[ChatRoomMetadata]) -> Vec<String> {
+        let is_unknown =
+            |room: &&String| !chat_rooms.iter().any(|metadata| &metadata.name == *room);
+        let mut settings = self
+            .message_hooks
+            .rooms
+            .keys()
+            .filter(is_unknown)
+            .map(|room| format!("message_hooks.rooms.{}", room))
+            .chain(
+                self.rate_limits
+                    .rooms
+                    .keys()
+                    .filter(is_unknown)
+                    .map(|room| format!("rate_limits.rooms.{}", room)),
+            )
+            .collect::<Vec<_>>();
+        settings.sort();
+
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(config: &str, args: &[&str]) -> anyhow::Result<Config> {
+        let config = toml::from_str::<Config>(config)?;
+        let cli = Cli::try_parse_from(std::iter::once("server").chain(args.iter().copied()))?;
+
+        let config = config.with_overrides(cli);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = load("", &[]).unwrap();
+
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.history.store, HistoryStoreKind::Memory);
+        assert_eq!(config.history.retention, DEFAULT_HISTORY_RETENTION);
+        assert_eq!(
+            config.channels.session_capacity,
+            DEFAULT_SESSION_CHANNEL_CAPACITY
+        );
+        assert!(config.limits.max_connections.is_none());
+        assert_eq!(config.limits.max_frame_size, DEFAULT_MAX_FRAME_SIZE);
+        assert!(config.rate_limits.rooms.is_empty());
+        assert_eq!(config.messages.max_length, DEFAULT_MAX_MESSAGE_LENGTH);
+        assert_eq!(config.messages.control_characters, ControlCharacters::Strip);
+        assert!(!config.chat_rooms().unwrap().is_empty());
+        assert!(config.room_owner.is_none());
+    }
+
+    #[test]
+    fn test_room_owner() {
+        // without a room owner nobody owns the built-in rooms, so nobody can delete or moderate them
+        let chat_rooms = load("", &[]).unwrap().chat_rooms().unwrap();
+        assert!(chat_rooms.iter().all(|room| room.owner.is_none()));
+
+        let config = load("room_owner = \"admin\"", &[]).unwrap();
+        let chat_rooms = config.chat_rooms().unwrap();
+        assert!(chat_rooms
+            .iter()
+            .all(|room| room.owner.as_deref() == Some("admin")));
+
+        let config = load("room_owner = \"admin\"", &["--room-owner", "root"]).unwrap();
+        assert_eq!(config.room_owner.as_deref(), Some("root"));
+    }
+
+    #[test]
+    fn test_rate_limits() {
+        let config = load(
+            r#"
+            [rate_limits]
+            messages = { burst = 5, per_sec = 1.0 }
+            max_violations = 3
+
+            [rate_limits.rooms.general]
+            messages = { burst = 1, per_sec = 0.1 }
+            "#,
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.rate_limits.messages,
+            RateBudget {
+                burst: 5,
+                per_sec: 1.0
+            }
+        );
+        assert_eq!(config.rate_limits.joins, RateLimitsConfig::default().joins);
+        assert_eq!(config.rate_limits.max_violations, 3);
+        let general = &config.rate_limits.rooms["general"];
+        assert_eq!(general.messages.map(|budget| budget.burst), Some(1));
+        assert!(general.joins.is_none());
+
+        let err = load(
+            "[rate_limits.rooms.general]\njoins = { burst = 0, per_sec = 1.0 }",
+            &[],
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "rate_limits.rooms.general.joins must have a burst greater than 0 and a per_sec of at least 1 / 86400"
+        );
+        assert!(load(
+            "[rate_limits]\ncommands = { burst = 1, per_sec = 0.0 }",
+            &[]
+        )
+        .is_err());
+        assert!(load("[rate_limits]\nmessages = { burst = 1 }", &[]).is_err());
+        assert!(load("[rate_limits]\nmax_violations = 0", &[]).is_err());
+    }
+
+    #[test]
+    fn test_message_hooks() {
+        let config = load(
+            r#"
+            [message_hooks]
+            blocked_words = ["heck"]
+            blocked_words_action = "mask"
+
+            [message_hooks.rooms.general]
+            max_links = 0
+            "#,
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(config.message_hooks.blocked_words, vec!["heck"]);
+        assert_eq!(
+            config.message_hooks.blocked_words_action,
+            BlocklistAction::Mask
+        );
+        assert!(config.message_hooks.max_links.is_none());
+        let general = &config.message_hooks.rooms["general"];
+        assert!(general.blocked_words.is_empty());
+        assert_eq!(general.max_links, Some(0));
+
+        assert!(load("[message_hooks]\nblocked_words_action = \"hide\"", &[]).is_err());
+    }
+
+    #[test]
+    fn test_example_config() {
+        let config = load(include_str!("../config.example.toml"), &[]).unwrap();
+
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.history.retention, DEFAULT_HISTORY_RETENTION);
+    }
+
+    #[test]
+    fn test_arguments_override_the_file() {
+        let config = load(
+            r#"
+            bind_address = "127.0.0.1"
+            port = 9000
+
+            [history]
+            store = "log"
+            retention = 10
+
+            [limits]
+            max_connections = 50
+
+            [messages]
+            max_length = 100
+            control_characters = "reject"
+            "#,
+            &[
+                "--port",
+                "9001",
+                "--history-retention",
+                "20",
+                "--max-message-length",
+                "500",
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(config.bind_address, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(config.port, 9001);
+        assert_eq!(config.history.store, HistoryStoreKind::Log);
+        assert_eq!(config.history.retention, 20);
+        assert_eq!(config.limits.max_connections, Some(50));
+        assert_eq!(config.messages.max_length, 500);
+        assert_eq!(
+            config.messages.control_characters,
+            ControlCharacters::Reject
+        );
+    }
+
+    #[test]
+    fn test_invalid_config_is_rejected() {
+        assert!(load("prot = 8080", &[]).is_err());
+        assert!(load("port = \"eighty\"", &[]).is_err());
+        assert!(load("[history]\nstore = \"disk\"", &[]).is_err());
+
+        let err = load("[channels]\nroom_capacity = 0", &[]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "channels.room_capacity must be greater than 0"
+        );
+        assert!(load("", &["--max-connections", "0"]).is_err());
+        assert!(load("", &["--max-frame-size", "0"]).is_err());
+        assert!(load("", &["--max-message-length", "0"]).is_err());
+        assert!(load("[messages]\ncontrol_characters = \"escape\"", &[]).is_err());
+        assert!(load("", &["--heartbeat-interval-secs", "60"]).is_err());
+        assert!(load("", &["--no-tcp"]).is_err());
+        assert!(load("", &["--no-tcp", "--unix-socket", "chat.sock"]).is_ok());
+        assert!(load("", &["--websocket-port", "8080"]).is_err());
+        assert!(load("", &["--websocket-port", "8081"]).is_ok());
+        assert!(load("", &["--resume-grace-period-secs", "0"]).is_ok());
+        assert!(load("[tls]\ncert_file = \"cert.pem\"", &[]).is_err());
+        assert!(load("", &["--tls-key", "key.pem"]).is_err());
+        assert!(load("", &["--tls-cert", "cert.pem", "--tls-key", "key.pem"]).is_ok());
+    }
+
+    #[test]
+    fn test_too_large_values_are_rejected() {
+        let err = load("", &["--idle-timeout-secs", "18446744073709551615"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "heartbeat.idle_timeout_secs must be at most 86400"
+        );
+        assert!(load("", &["--idle-timeout-secs", "86400"]).is_ok());
+        assert!(load("", &["--resume-grace-period-secs", "86401"]).is_err());
+        assert!(load("[rate_limits]\nviolation_window_secs = 100000", &[]).is_err());
+        assert!(load("", &["--room-channel-capacity", "100001"]).is_err());
+        assert!(load("", &["--broadcast-channel-capacity", "1000000000000"]).is_err());
+        assert!(load("", &["--session-channel-capacity", "100000"]).is_ok());
+        assert!(load(
+            "[rate_limits]\nmessages = { burst = 5, per_sec = 1e-300 }",
+            &[]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_settings_of_unknown_rooms() {
+        let config = load(
+            r#"
+            [message_hooks.rooms.general]
+            max_links = 0
+
+            [message_hooks.rooms.genral]
+            max_links = 0
+
+            [rate_limits.rooms.random]
+            joins = { burst = 1, per_sec = 0.1 }
+            "#,
+            &[],
+        )
+        .unwrap();
+        let chat_rooms = config.chat_rooms().unwrap();
+
+        assert_eq!(
+            config.settings_of_unknown_rooms(&chat_rooms),
+            vec!["message_hooks.rooms.genral", "rate_limits.rooms.random"]
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use crate::config::LimitsConfig;
+
+#[derive(Debug, Default)]
+struct OpenConnections {
+    total: usize,
+    by_ip: HashMap<IpAddr, usize>,
+}
+
+/// [ConnectionLimiter] keeps count of the open connections to refuse the ones above the configured limits
+#[derive(Debug)]
+pub struct ConnectionLimiter {
+    limits: LimitsConfig,
+    open_connections: Mutex<OpenConnections>,
+}
+
+/// [ConnectionPermit] is held for as long as a connection is open, the connection is counted until it is dropped
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    limiter: Arc<ConnectionLimiter>,
+    ip: Option<IpAddr>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(limits: LimitsConfig) -> Arc<Self> {
+        Arc::new(ConnectionLimiter {
+            limits,
+            open_connections: Mutex::new(OpenConnections::default()),
+        })
+    }
+
+    /// Count a new connection from the given address, returns `None` if it would exceed any of the limits
+    /// Connections without an address, e.g. over a Unix socket, only count towards the total
+    pub fn try_acquire(self: &Arc<Self>, ip: Option<IpAddr>) -> Option<ConnectionPermit> {
+        let mut open_connections = self.open_connections.lock().unwrap();
+        let open_by_ip = ip.map(|ip| open_connections.by_ip.get(&ip).copied().unwrap_or(0));
+
+        let exceeds = |limit: Option<usize>, open: usize| limit.is_some_and(|limit| open >= limit);
+        if exceeds(self.limits.max_connections, open_connections.total)
+            || open_by_ip.is_some_and(|open| exceeds(self.limits.max_connections_per_ip, open))
+        {
+            return None;
+        }
+
+        open_connections.total += 1;
+        if let (Some(ip), Some(open_by_ip)) = (ip, open_by_ip) {
+            open_connections.by_ip.insert(ip, open_by_ip + 1);
+        }
+
+        Some(ConnectionPermit {
+            limiter: self.clone(),
+            ip,
+        })
+    }
+
+    fn release(&self, ip: Option<IpAddr>) {
+        let mut open_connections = self.open_connections.lock().unwrap();
+        open_connections.total -= 1;
+
+        let Some(ip) = ip else {
+            return;
+        };
+        if let Some(open_by_ip) = open_connections.by_ip.get_mut(&ip) {
+            *open_by_ip -= 1;
+
+            if *open_by_ip == 0 {
+                open_connections.by_ip.remove(&ip);
+            }
+        }
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.limiter.release(self.ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn test_connection_limits() {
+        let limiter = ConnectionLimiter::new(LimitsConfig {
+            max_connections: Some(3),
+            max_connections_per_ip: Some(2),
+            ..LimitsConfig::default()
+        });
+        let first_ip = Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        let second_ip = Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+
+        let first = limiter.try_acquire(first_ip).unwrap();
+        let _second = limiter.try_acquire(first_ip).unwrap();
+        assert!(limiter.try_acquire(first_ip).is_none());
+
+        let _third = limiter.try_acquire(second_ip).unwrap();
+        assert!(limiter.try_acquire(second_ip).is_none());
+
+        // connections without an address are only limited by the total
+        assert!(limiter.try_acquire(None).is_none());
+
+        // closing a connection makes room for a new one
+        drop(first);
+        assert!(limiter.try_acquire(first_ip).is_some());
+    }
+}
@@ -0,0 +1,97 @@
+use std::{fmt, time::Duration};
+
+use comms::event::{self, CommandErrorCode};
+
+#[derive(Debug, Clone)]
+/// [CommandError] is the reason why a user command was rejected by the server
+///
+/// Unlike other errors, it does not end the user session but is sent back to the user as an event
+pub struct CommandError {
+    code: CommandErrorCode,
+    room: Option<String>,
+    message: String,
+    retry_after_ms: Option<u64>,
+    request_id: Option<String>,
+}
+
+impl CommandError {
+    pub fn new(code: CommandErrorCode, message: impl Into<String>) -> Self {
+        CommandError {
+            code,
+            room: None,
+            message: message.into(),
+            retry_after_ms: None,
+            request_id: None,
+        }
+    }
+
+    /// Echo the request id of the rejected command back to the user
+    pub fn with_request_id(self, request_id: Option<String>) -> Self {
+        CommandError { request_id, ..self }
+    }
+
+    /// Tell the user which room the rejected command targeted
+    pub fn with_room(self, room: &str) -> Self {
+        CommandError {
+            room: Some(String::from(room)),
+            ..self
+        }
+    }
+
+    /// Creates an error for a command which targeted the given room
+    pub fn in_room(code: CommandErrorCode, room: &str, message: impl Into<String>) -> Self {
+        CommandError {
+            room: Some(String::from(room)),
+            ..CommandError::new(code, message)
+        }
+    }
+
+    pub fn room_not_found(room: &str) -> Self {
+        CommandError::in_room(
+            CommandErrorCode::RoomNotFound,
+            room,
+            format!("room '{}' not found", room),
+        )
+    }
+
+    /// Creates an error for a command above the rate limit of the session, which can be retried after the given time
+    pub fn rate_limited(retry_after: Duration) -> Self {
+        let retry_after_ms = u64::try_from(retry_after.as_millis()).unwrap_or(u64::MAX);
+
+        CommandError {
+            retry_after_ms: Some(retry_after_ms),
+            ..CommandError::new(
+                CommandErrorCode::RateLimited,
+                format!("rate limited, retry after {} ms", retry_after_ms),
+            )
+        }
+    }
+
+    pub fn not_joined(room: &str) -> Self {
+        CommandError::in_room(
+            CommandErrorCode::NotJoined,
+            room,
+            format!("you have not joined room '{}'", room),
+        )
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl From<CommandError> for event::Event {
+    fn from(error: CommandError) -> Self {
+        event::Event::CommandError(event::CommandErrorReplyEvent {
+            room: error.room,
+            code: error.code,
+            message: error.message,
+            retry_after_ms: error.retry_after_ms,
+            request_id: error.request_id,
+        })
+    }
+}
@@ -1,37 +1,276 @@
-use std::sync::Arc;
+use std::{io, net::SocketAddr, os::unix::fs::FileTypeExt, path::Path, sync::Arc, time::Duration};
 
 use anyhow::Context;
+use clap::Parser;
+use comms::transport::tls::{self, TlsAcceptor};
 use room_manager::RoomManagerBuilder;
-use tokio::{net::TcpListener, signal::ctrl_c, sync::broadcast, task::JoinSet};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream, UnixListener, UnixStream},
+    signal::ctrl_c,
+    sync::broadcast,
+    task::JoinSet,
+    time,
+};
 
-use crate::room_manager::ChatRoomMetadata;
+use crate::account_store::AccountStore;
+use crate::config::{Cli, Config, HistoryConfig, HistoryStoreKind, MessageHooksConfig, TlsConfig};
+use crate::connection_limiter::ConnectionLimiter;
+use crate::room_manager::{
+    HistoryStore, InMemoryHistoryStore, MaxLinksHook, RoomManager, SegmentedLogHistoryStore,
+    WordBlocklistHook,
+};
+use crate::session::SuspendedSessions;
+use crate::session_registry::SessionRegistry;
 
+mod account_store;
+mod config;
+mod connection_limiter;
+mod error;
+mod rate_limiter;
 mod room_manager;
 mod session;
+mod session_registry;
+mod websocket;
 
-const PORT: u16 = 8080;
-const CHAT_ROOMS_METADATA: &str = include_str!("../resources/chat_rooms_metadata.json");
+/// Create the history store selected in the config
+fn create_history_store(config: &HistoryConfig) -> anyhow::Result<Arc<dyn HistoryStore>> {
+    match config.store {
+        HistoryStoreKind::Memory => Ok(Arc::new(InMemoryHistoryStore::new(config.retention))),
+        HistoryStoreKind::Log => Ok(Arc::new(SegmentedLogHistoryStore::open(
+            &config.dir,
+            config.retention,
+        )?)),
+    }
+}
+
+/// Register the built-in message hooks enabled in the config
+fn with_message_hooks(
+    mut builder: RoomManagerBuilder,
+    config: &MessageHooksConfig,
+) -> RoomManagerBuilder {
+    if !config.blocked_words.is_empty() {
+        builder = builder.message_hook(Arc::new(WordBlocklistHook::new(
+            &config.blocked_words,
+            config.blocked_words_action,
+        )));
+    }
+    if let Some(max_links) = config.max_links {
+        builder = builder.message_hook(Arc::new(MaxLinksHook::new(max_links)));
+    }
+
+    for (room, room_config) in config.rooms.iter() {
+        if !room_config.blocked_words.is_empty() {
+            builder = builder.room_message_hook(
+                room,
+                Arc::new(WordBlocklistHook::new(
+                    &room_config.blocked_words,
+                    config.blocked_words_action,
+                )),
+            );
+        }
+        if let Some(max_links) = room_config.max_links {
+            builder = builder.room_message_hook(room, Arc::new(MaxLinksHook::new(max_links)));
+        }
+    }
+
+    builder
+}
+
+/// Create the TLS acceptor of the TCP connections if TLS is configured
+fn create_tls_acceptor(config: &TlsConfig) -> anyhow::Result<Option<TlsAcceptor>> {
+    let (Some(cert_file), Some(key_file)) = (config.cert_file.as_ref(), config.key_file.as_ref())
+    else {
+        return Ok(None);
+    };
+
+    // clients can pin a self-signed certificate with its fingerprint
+    if let Some(certificate) = tls::load_certificates(cert_file)?.first() {
+        println!(
+            "TLS certificate fingerprint: {}",
+            tls::Fingerprint::of(certificate)
+        );
+    }
+
+    tls::acceptor(cert_file, key_file).map(Some)
+}
+
+/// Run the TLS handshake of a TCP connection, the connection is closed if it fails or does not complete in time
+async fn accept_tls(
+    acceptor: &TlsAcceptor,
+    socket: TcpStream,
+    addr: SocketAddr,
+    timeout: Duration,
+) -> Option<tls::server::TlsStream<TcpStream>> {
+    match time::timeout(timeout, acceptor.accept(socket)).await {
+        Ok(Ok(stream)) => Some(stream),
+        Ok(Err(err)) => {
+            println!("TLS handshake with {} failed: {}", addr, err);
+            None
+        }
+        Err(_) => {
+            println!("TLS handshake with {} timed out", addr);
+            None
+        }
+    }
+}
+
+/// Bind to the Unix socket path, replacing the socket left behind by a previous run of the server
+fn bind_unix_socket(path: &Path) -> anyhow::Result<UnixListener> {
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        std::fs::remove_file(path)
+            .with_context(|| format!("could not remove the stale socket '{}'", path.display()))?;
+    }
+
+    UnixListener::bind(path).with_context(|| format!("could not bind to '{}'", path.display()))
+}
+
+/// Accept the next TCP connection, waits forever if the server does not listen on TCP
+async fn accept_tcp(listener: Option<&TcpListener>) -> io::Result<(TcpStream, SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Accept the next Unix socket connection, waits forever if the server does not listen on a Unix socket
+async fn accept_unix(listener: Option<&UnixListener>) -> io::Result<UnixStream> {
+    match listener {
+        Some(listener) => listener.accept().await.map(|(socket, _addr)| socket),
+        None => std::future::pending().await,
+    }
+}
+
+/// [ConnectionContext] holds what the session of every accepted connection shares with the server
+#[derive(Clone)]
+struct ConnectionContext {
+    config: Arc<Config>,
+    room_manager: Arc<RoomManager>,
+    account_store: Arc<AccountStore>,
+    session_registry: Arc<SessionRegistry>,
+    suspended_sessions: Arc<SuspendedSessions>,
+    tls_acceptor: Option<TlsAcceptor>,
+}
+
+impl ConnectionContext {
+    /// Handle the session of a connection until the user quits or the server shuts down
+    async fn handle_user_session<S>(
+        self,
+        quit_rx: broadcast::Receiver<()>,
+        stream: S,
+    ) -> anyhow::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        session::handle_user_session(
+            self.config,
+            self.room_manager,
+            self.account_store,
+            self.session_registry,
+            self.suspended_sessions,
+            quit_rx,
+            stream,
+        )
+        .await
+    }
+}
 
 #[tokio::main]
-async fn main() {
-    let chat_room_metadata: Vec<ChatRoomMetadata> = serde_json::from_str(CHAT_ROOMS_METADATA)
-        .expect("could not parse the chat rooms metadatas");
+async fn main() -> anyhow::Result<()> {
+    let config = Arc::new(Config::load(Cli::parse())?);
+
+    let chat_room_metadata = config.chat_rooms()?;
+    for setting in config.settings_of_unknown_rooms(&chat_room_metadata) {
+        println!(
+            "{} names a room that does not exist, it only applies once the room is created",
+            setting
+        );
+    }
+    let account_store = Arc::new(
+        AccountStore::open(&config.accounts_file).context("could not open the account store")?,
+    );
+    let history_store =
+        create_history_store(&config.history).context("could not create the history store")?;
     let room_manager = Arc::new(
         chat_room_metadata
             .into_iter()
-            .fold(RoomManagerBuilder::new(), |builder, metadata| {
-                builder.create_room(metadata)
-            })
-            .build(),
+            .fold(
+                with_message_hooks(RoomManagerBuilder::new(), &config.message_hooks)
+                    .history_store(history_store)
+                    .room_channel_capacity(config.channels.room_capacity)
+                    .broadcast_channel_capacity(config.channels.broadcast_capacity),
+                |builder, metadata| builder.create_room(metadata),
+            )
+            .build()
+            .context("could not build the room manager")?,
     );
+    let session_registry = Arc::new(SessionRegistry::new());
+    let suspended_sessions = Arc::new(SuspendedSessions::new(config.resume.grace_period()));
+    let connection_limiter = ConnectionLimiter::new(config.limits.clone());
+    let tls_acceptor = create_tls_acceptor(&config.tls).context("could not set up TLS")?;
 
     let mut join_set: JoinSet<anyhow::Result<()>> = JoinSet::new();
-    let server = TcpListener::bind(format!("0.0.0.0:{}", PORT))
-        .await
-        .expect("could not bind to the port");
+    let tcp_listener = if config.tcp {
+        let listener = TcpListener::bind((config.bind_address, config.port))
+            .await
+            .with_context(|| {
+                format!("could not bind to {}:{}", config.bind_address, config.port)
+            })?;
+
+        Some(listener)
+    } else {
+        None
+    };
+    let websocket_listener = match config.websocket_port {
+        Some(port) => Some(
+            TcpListener::bind((config.bind_address, port))
+                .await
+                .with_context(|| format!("could not bind to {}:{}", config.bind_address, port))?,
+        ),
+        None => None,
+    };
+    let unix_listener = config
+        .unix_socket
+        .as_deref()
+        .map(bind_unix_socket)
+        .transpose()?;
     let (quit_tx, quit_rx) = broadcast::channel::<()>(1);
+    let context = ConnectionContext {
+        config: Arc::clone(&config),
+        room_manager,
+        account_store,
+        session_registry,
+        suspended_sessions,
+        tls_acceptor,
+    };
 
-    println!("Listening on port {}", PORT);
+    if tcp_listener.is_some() {
+        println!(
+            "Listening on {}:{}{}",
+            config.bind_address,
+            config.port,
+            if context.tls_acceptor.is_some() {
+                " with TLS"
+            } else {
+                ""
+            }
+        );
+    }
+    if let Some(port) = config.websocket_port {
+        println!(
+            "Listening for WebSocket clients on {}:{}{}",
+            config.bind_address,
+            port,
+            if context.tls_acceptor.is_some() {
+                " with TLS"
+            } else {
+                ""
+            }
+        );
+    }
+    if let Some(path) = config.unix_socket.as_ref() {
+        println!("Listening on {}", path.display());
+    }
     loop {
         tokio::select! {
             Ok(_) = ctrl_c() => {
@@ -39,12 +278,83 @@ async fn main() {
                 quit_tx.send(()).context("failed to send quit signal").unwrap();
                 break;
             }
-            Ok((socket, _)) = server.accept() => {
-                join_set.spawn(session::handle_user_session(Arc::clone(&room_manager), quit_rx.resubscribe(), socket));
+            Ok((socket, addr)) = accept_tcp(tcp_listener.as_ref()) => {
+                // the socket is closed right away if the connection is above the limits
+                let Some(permit) = connection_limiter.try_acquire(Some(addr.ip())) else {
+                    println!("Refused connection from {}, connection limit reached.", addr);
+                    continue;
+                };
+
+                let context = context.clone();
+                let quit_rx = quit_rx.resubscribe();
+                join_set.spawn(async move {
+                    let _permit = permit;
+
+                    match context.tls_acceptor.clone() {
+                        Some(acceptor) => {
+                            let timeout = context.config.heartbeat.idle_timeout();
+                            let Some(stream) = accept_tls(&acceptor, socket, addr, timeout).await else {
+                                return Ok(());
+                            };
+
+                            context.handle_user_session(quit_rx, stream).await
+                        }
+                        None => context.handle_user_session(quit_rx, socket).await,
+                    }
+                });
+            }
+            Ok((socket, addr)) = accept_tcp(websocket_listener.as_ref()) => {
+                let Some(permit) = connection_limiter.try_acquire(Some(addr.ip())) else {
+                    println!("Refused WebSocket connection from {}, connection limit reached.", addr);
+                    continue;
+                };
+
+                let context = context.clone();
+                let quit_rx = quit_rx.resubscribe();
+                join_set.spawn(async move {
+                    let _permit = permit;
+
+                    // the WebSocket handshake runs over TLS when it is configured, like the TCP connections
+                    let max_frame_size = context.config.limits.max_frame_size;
+                    let timeout = context.config.heartbeat.idle_timeout();
+                    let stream = match context.tls_acceptor.clone() {
+                        Some(acceptor) => {
+                            let Some(stream) = accept_tls(&acceptor, socket, addr, timeout).await else {
+                                return Ok(());
+                            };
+
+                            websocket::accept(stream, addr, max_frame_size, timeout).await
+                        }
+                        None => websocket::accept(socket, addr, max_frame_size, timeout).await,
+                    };
+                    let Some(stream) = stream else {
+                        return Ok(());
+                    };
+
+                    context.handle_user_session(quit_rx, stream).await
+                });
+            }
+            Ok(socket) = accept_unix(unix_listener.as_ref()) => {
+                let Some(permit) = connection_limiter.try_acquire(None) else {
+                    println!("Refused connection on the Unix socket, connection limit reached.");
+                    continue;
+                };
+
+                let session = context.clone().handle_user_session(quit_rx.resubscribe(), socket);
+                join_set.spawn(async move {
+                    let _permit = permit;
+
+                    session.await
+                });
             }
         }
     }
 
     while join_set.join_next().await.is_some() {}
+    if let Some(path) = config.unix_socket.as_ref() {
+        let _ = std::fs::remove_file(path);
+    }
     println!("Server shut down");
+
+    Ok(())
 }
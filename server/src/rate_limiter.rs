@@ -0,0 +1,362 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use comms::command::UserCommand;
+
+use crate::config::{RateBudget, RateLimitsConfig};
+
+/// [TokenBucket] allows bursts up to its capacity, and refills at a steady rate afterwards
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    refill_per_sec: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket
+    fn new(capacity: f64, refill_per_sec: f64, now: Instant) -> Self {
+        TokenBucket {
+            capacity,
+            refill_per_sec,
+            tokens: capacity,
+            refilled_at: now,
+        }
+    }
+
+    fn from_budget(budget: &RateBudget, now: Instant) -> Self {
+        TokenBucket::new(budget.burst as f64, budget.per_sec, now)
+    }
+
+    /// Takes a token, returns how long it takes until the next token is available if the bucket is empty
+    fn try_take(&mut self, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.refill_per_sec).min(self.capacity);
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / self.refill_per_sec,
+            ))
+        }
+    }
+}
+
+/// [RateLimited] is the verdict on a command above the rate limit of the session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimited {
+    /// How long to wait before the command can be retried
+    pub retry_after: Duration,
+    /// Whether the session has been rate limited too often and has to be disconnected
+    pub disconnect: bool,
+    /// Whether the command is dropped without telling the user, it does not count as a violation
+    pub silent: bool,
+}
+
+/// The budgets of a room with budgets of its own, see [crate::config::RoomRateLimitsConfig]
+#[derive(Debug, Default)]
+struct RoomBuckets {
+    messages: Option<TokenBucket>,
+    joins: Option<TokenBucket>,
+}
+
+/// The kind of budget a command is taken from
+#[derive(Debug, Clone, Copy)]
+enum CommandKind {
+    Message,
+    Join,
+    Typing,
+    Auth,
+    Other,
+}
+
+/// [SessionRateLimiter] keeps the budgets of the commands of a single session
+///
+/// Messages, joins, typing notifications, authentication attempts and the other commands have separate budgets. The rooms configured with
+/// budgets of their own use those for the messages sent to them and their joins, instead of the global ones.
+/// Typing notifications above their budget are dropped silently, a client typing steadily is not a violation.
+#[derive(Debug)]
+pub struct SessionRateLimiter {
+    config: RateLimitsConfig,
+    messages: TokenBucket,
+    joins: TokenBucket,
+    typing: TokenBucket,
+    auth: TokenBucket,
+    commands: TokenBucket,
+    /// Budgets of the rooms configured with budgets of their own, created when the room is first used
+    rooms: HashMap<String, RoomBuckets>,
+    /// Every rate limited command takes a token, the session is disconnected once it is empty
+    violations: TokenBucket,
+}
+
+impl SessionRateLimiter {
+    pub fn new(config: &RateLimitsConfig) -> Self {
+        SessionRateLimiter::new_at(config, Instant::now())
+    }
+
+    fn new_at(config: &RateLimitsConfig, now: Instant) -> Self {
+        let max_violations = config.max_violations as f64;
+
+        SessionRateLimiter {
+            config: config.clone(),
+            messages: TokenBucket::from_budget(&config.messages, now),
+            joins: TokenBucket::from_budget(&config.joins, now),
+            typing: TokenBucket::from_budget(&config.typing, now),
+            auth: TokenBucket::from_budget(&config.auth, now),
+            commands: TokenBucket::from_budget(&config.commands, now),
+            rooms: HashMap::new(),
+            violations: TokenBucket::new(
+                max_violations,
+                max_violations / config.violation_window().as_secs_f64(),
+                now,
+            ),
+        }
+    }
+
+    /// Takes the budget of a command, fails if the budget of its kind is spent
+    /// Quitting and the heartbeat are never limited
+    pub fn check(&mut self, command: &UserCommand) -> Result<(), RateLimited> {
+        self.check_at(command, Instant::now())
+    }
+
+    fn check_at(&mut self, command: &UserCommand, now: Instant) -> Result<(), RateLimited> {
+        let (kind, room) = match command {
+            UserCommand::Quit(_) | UserCommand::Ping(_) | UserCommand::Pong(_) => return Ok(()),
+            UserCommand::SendMessage(cmd) => (CommandKind::Message, Some(&cmd.room)),
+            UserCommand::SendDirectMessage(_) => (CommandKind::Message, None),
+            UserCommand::JoinRoom(cmd) => (CommandKind::Join, Some(&cmd.room)),
+            UserCommand::TypingStarted(_) | UserCommand::TypingStopped(_) => {
+                (CommandKind::Typing, None)
+            }
+            UserCommand::Register(_) | UserCommand::Login(_) | UserCommand::ResumeSession(_) => {
+                (CommandKind::Auth, None)
+            }
+            _ => (CommandKind::Other, None),
+        };
+
+        let Err(retry_after) = self.bucket(kind, room, now).try_take(now) else {
+            return Ok(());
+        };
+
+        if let CommandKind::Typing = kind {
+            return Err(RateLimited {
+                retry_after,
+                disconnect: false,
+                silent: true,
+            });
+        }
+
+        Err(RateLimited {
+            retry_after,
+            disconnect: self.violations.try_take(now).is_err(),
+            silent: false,
+        })
+    }
+
+    /// The bucket to take the budget of a command from, the one of its room if the room has a budget of its own
+    fn bucket(
+        &mut self,
+        kind: CommandKind,
+        room: Option<&String>,
+        now: Instant,
+    ) -> &mut TokenBucket {
+        let room_config = room.and_then(|room| Some((room, self.config.rooms.get(room)?)));
+        let room_budget = room_config.and_then(|(room, room_config)| match kind {
+            CommandKind::Message => Some((room, room_config.messages?)),
+            CommandKind::Join => Some((room, room_config.joins?)),
+            CommandKind::Typing | CommandKind::Auth | CommandKind::Other => None,
+        });
+
+        match (kind, room_budget) {
+            (_, Some((room, budget))) => {
+                let room_buckets = self.rooms.entry(room.clone()).or_default();
+                let bucket = match kind {
+                    CommandKind::Join => &mut room_buckets.joins,
+                    _ => &mut room_buckets.messages,
+                };
+
+                bucket.get_or_insert_with(|| TokenBucket::from_budget(&budget, now))
+            }
+            (CommandKind::Message, None) => &mut self.messages,
+            (CommandKind::Join, None) => &mut self.joins,
+            (CommandKind::Typing, None) => &mut self.typing,
+            (CommandKind::Auth, None) => &mut self.auth,
+            (CommandKind::Other, None) => &mut self.commands,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use comms::command;
+
+    use super::*;
+    use crate::config::RoomRateLimitsConfig;
+
+    fn send_message(room: &str) -> UserCommand {
+        UserCommand::SendMessage(command::SendMessageCommand {
+            room: room.into(),
+            content: "hello".into(),
+            request_id: None,
+        })
+    }
+
+    fn join_room(room: &str) -> UserCommand {
+        UserCommand::JoinRoom(command::JoinRoomCommand {
+            room: room.into(),
+            request_id: None,
+        })
+    }
+
+    fn test_config() -> RateLimitsConfig {
+        RateLimitsConfig {
+            messages: RateBudget {
+                burst: 2,
+                per_sec: 1.0,
+            },
+            joins: RateBudget {
+                burst: 1,
+                per_sec: 0.05,
+            },
+            typing: RateBudget {
+                burst: 1,
+                per_sec: 0.5,
+            },
+            auth: RateBudget {
+                burst: 1,
+                per_sec: 0.1,
+            },
+            commands: RateBudget {
+                burst: 1,
+                per_sec: 1.0,
+            },
+            max_violations: 3,
+            violation_window_secs: 30,
+            rooms: HashMap::from([(
+                String::from("quiet"),
+                RoomRateLimitsConfig {
+                    messages: Some(RateBudget {
+                        burst: 1,
+                        per_sec: 0.1,
+                    }),
+                    joins: None,
+                },
+            )]),
+        }
+    }
+
+    #[test]
+    fn test_budget_refills() {
+        let start = Instant::now();
+        let mut limiter = SessionRateLimiter::new_at(&test_config(), start);
+
+        // the burst is allowed at once, the next message has to wait for the refill
+        assert!(limiter.check_at(&send_message("general"), start).is_ok());
+        assert!(limiter.check_at(&send_message("general"), start).is_ok());
+        let limited = limiter
+            .check_at(&send_message("general"), start)
+            .unwrap_err();
+        assert_eq!(limited.retry_after, Duration::from_secs(1));
+        assert!(!limited.disconnect);
+
+        let later = start + Duration::from_millis(500);
+        let limited = limiter
+            .check_at(&send_message("general"), later)
+            .unwrap_err();
+        assert_eq!(limited.retry_after, Duration::from_millis(500));
+
+        let later = start + Duration::from_secs(1);
+        assert!(limiter.check_at(&send_message("general"), later).is_ok());
+    }
+
+    #[test]
+    fn test_kinds_and_rooms_have_separate_budgets() {
+        let start = Instant::now();
+        let mut limiter = SessionRateLimiter::new_at(&test_config(), start);
+
+        assert!(limiter.check_at(&join_room("general"), start).is_ok());
+        assert!(limiter.check_at(&join_room("quiet"), start).is_err());
+
+        // the room with a budget of its own does not take from the global one
+        assert!(limiter.check_at(&send_message("quiet"), start).is_ok());
+        let limited = limiter.check_at(&send_message("quiet"), start).unwrap_err();
+        assert_eq!(limited.retry_after, Duration::from_secs(10));
+        assert!(limiter.check_at(&send_message("general"), start).is_ok());
+        assert!(limiter.check_at(&send_message("general"), start).is_ok());
+
+        let nickname = UserCommand::SetNickname(command::SetNicknameCommand {
+            nickname: "nick".into(),
+            request_id: None,
+        });
+        assert!(limiter.check_at(&nickname, start).is_ok());
+        assert!(limiter.check_at(&nickname, start).is_err());
+        let quit = UserCommand::Quit(command::QuitCommand::default());
+        assert!(limiter.check_at(&quit, start).is_ok());
+    }
+
+    #[test]
+    fn test_repeat_offenders_are_disconnected() {
+        let start = Instant::now();
+        let mut limiter = SessionRateLimiter::new_at(&test_config(), start);
+
+        assert!(limiter.check_at(&join_room("general"), start).is_ok());
+        for _ in 0..3 {
+            let limited = limiter.check_at(&join_room("general"), start).unwrap_err();
+            assert!(!limited.disconnect);
+        }
+
+        // the violations are forgiven over the window, at one every ten seconds here
+        let later = start + Duration::from_secs(10);
+        let limited = limiter.check_at(&join_room("general"), later).unwrap_err();
+        assert!(!limited.disconnect);
+        let limited = limiter.check_at(&join_room("general"), later).unwrap_err();
+        assert!(limited.disconnect);
+    }
+
+    #[test]
+    fn test_heartbeat_and_typing_are_not_violations() {
+        let start = Instant::now();
+        let mut limiter = SessionRateLimiter::new_at(&test_config(), start);
+
+        let ping = UserCommand::Ping(command::PingCommand {
+            timestamp: 1,
+            request_id: None,
+        });
+        let pong = UserCommand::Pong(command::PongCommand {
+            timestamp: 1,
+            request_id: None,
+        });
+        let typing = UserCommand::TypingStarted(command::TypingStartedCommand {
+            room: "general".into(),
+            request_id: None,
+        });
+
+        assert!(limiter.check_at(&typing, start).is_ok());
+        for _ in 0..10 {
+            assert!(limiter.check_at(&ping, start).is_ok());
+            assert!(limiter.check_at(&pong, start).is_ok());
+
+            let limited = limiter.check_at(&typing, start).unwrap_err();
+            assert!(limited.silent);
+            assert!(!limited.disconnect);
+        }
+
+        // typing takes neither from the budget of the other commands nor from the violations
+        let nickname = UserCommand::SetNickname(command::SetNicknameCommand {
+            nickname: "nick".into(),
+            request_id: None,
+        });
+        assert!(limiter.check_at(&nickname, start).is_ok());
+        for _ in 0..3 {
+            assert!(!limiter.check_at(&nickname, start).unwrap_err().disconnect);
+        }
+    }
+}
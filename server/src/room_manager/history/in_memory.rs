@@ -0,0 +1,66 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use comms::event::UserMessageBroadcastEvent;
+
+use super::{page_of, HistoryStore};
+
+#[derive(Debug)]
+/// [InMemoryHistoryStore] keeps the latest messages of each room in memory
+/// The history is lost when the server is restarted
+pub struct InMemoryHistoryStore {
+    retention: usize,
+    rooms: Mutex<HashMap<String, VecDeque<UserMessageBroadcastEvent>>>,
+}
+
+impl InMemoryHistoryStore {
+    pub fn new(retention: usize) -> Self {
+        InMemoryHistoryStore {
+            retention,
+            rooms: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl HistoryStore for InMemoryHistoryStore {
+    fn append(&self, message: &UserMessageBroadcastEvent) -> anyhow::Result<()> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let messages = rooms.entry(message.room.clone()).or_default();
+
+        messages.push_back(message.clone());
+        if messages.len() > self.retention {
+            messages.pop_front();
+        }
+
+        Ok(())
+    }
+
+    fn page(
+        &self,
+        room: &str,
+        before: Option<u64>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<UserMessageBroadcastEvent>> {
+        let rooms = self.rooms.lock().unwrap();
+
+        Ok(rooms
+            .get(room)
+            .map(|messages| page_of(messages, before, limit))
+            .unwrap_or_default())
+    }
+
+    fn last_message_id(&self, room: &str) -> anyhow::Result<Option<u64>> {
+        let rooms = self.rooms.lock().unwrap();
+
+        Ok(rooms
+            .get(room)
+            .and_then(|messages| messages.back())
+            .map(|message| message.id))
+    }
+
+    fn remove_room(&self, room: &str) -> anyhow::Result<()> {
+        self.rooms.lock().unwrap().remove(room);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::VecDeque;
+use std::fmt::Debug;
+
+use comms::event::UserMessageBroadcastEvent;
+
+pub use self::in_memory::InMemoryHistoryStore;
+pub use self::segmented_log::SegmentedLogHistoryStore;
+
+mod in_memory;
+mod segmented_log;
+
+/// Number of messages kept per room if no retention limit is configured
+pub const DEFAULT_HISTORY_RETENTION: usize = 100;
+
+/// [HistoryStore] records the messages sent to the rooms and serves them back page by page
+///
+/// Implementations keep at most a configured number of messages per room, dropping the oldest ones.
+/// The methods are called while the room is locked, so messages of a room are appended in the order of their ids.
+pub trait HistoryStore: Debug + Send + Sync {
+    /// Append a message to the history of the room it was sent to
+    fn append(&self, message: &UserMessageBroadcastEvent) -> anyhow::Result<()>;
+
+    /// Get the latest `limit` messages of a room with an id lower than `before`, ordered oldest to newest
+    /// Starts from the latest message of the room if `before` is `None`
+    fn page(
+        &self,
+        room: &str,
+        before: Option<u64>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<UserMessageBroadcastEvent>>;
+
+    /// The id of the latest message recorded for the room, used to continue the message ids after a restart
+    fn last_message_id(&self, room: &str) -> anyhow::Result<Option<u64>>;
+
+    /// Delete the whole history of a room, a room created later with the same name starts from scratch
+    fn remove_room(&self, room: &str) -> anyhow::Result<()>;
+}
+
+/// Get a page from messages ordered by their ids, see [HistoryStore::page]
+fn page_of(
+    messages: &VecDeque<UserMessageBroadcastEvent>,
+    before: Option<u64>,
+    limit: usize,
+) -> Vec<UserMessageBroadcastEvent> {
+    // messages are ordered by id, so everything before the cursor is a prefix of them
+    let end = match before {
+        Some(before) => messages.partition_point(|message| message.id < before),
+        None => messages.len(),
+    };
+    let start = end.saturating_sub(limit);
+
+    messages.range(start..end).cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use nanoid::nanoid;
+
+    use super::*;
+
+    fn message(room: &str, id: u64) -> UserMessageBroadcastEvent {
+        UserMessageBroadcastEvent {
+            id,
+            timestamp: 1700000000000 + id,
+            room: room.into(),
+            user_id: "user".into(),
+            content: format!("msg{}", id),
+            unrecorded: false,
+        }
+    }
+
+    fn contents(messages: &[UserMessageBroadcastEvent]) -> Vec<String> {
+        messages.iter().map(|m| m.content.clone()).collect()
+    }
+
+    /// A directory under the system temp dir which is removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            TempDir(std::env::temp_dir().join(format!("chat-history-{}", nanoid!())))
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn assert_limit(store: &dyn HistoryStore, retention: usize) {
+        for id in 1..=(retention as u64 + 2) {
+            store.append(&message("test", id)).unwrap();
+        }
+
+        let messages = store.page("test", None, usize::MAX).unwrap();
+        assert_eq!(messages.len(), retention);
+        assert_eq!(messages.first().unwrap().id, 3);
+        assert_eq!(
+            store.last_message_id("test").unwrap(),
+            Some(retention as u64 + 2)
+        );
+    }
+
+    fn assert_pagination(store: &dyn HistoryStore) {
+        for id in 1..=25 {
+            store.append(&message("test", id)).unwrap();
+        }
+
+        let page = store.page("test", None, 10).unwrap();
+        assert_eq!(page.first().unwrap().id, 16);
+        assert_eq!(page.last().unwrap().id, 25);
+
+        let page = store.page("test", Some(16), 10).unwrap();
+        assert_eq!(page.first().unwrap().id, 6);
+        assert_eq!(page.last().unwrap().id, 15);
+
+        let page = store.page("test", Some(6), 10).unwrap();
+        assert_eq!(
+            contents(&page),
+            vec!["msg1", "msg2", "msg3", "msg4", "msg5"]
+        );
+    }
+
+    fn assert_rooms_are_isolated(store: &dyn HistoryStore) {
+        store.append(&message("room-1", 1)).unwrap();
+        store.append(&message("room-2", 1)).unwrap();
+        store.append(&message("room-2", 2)).unwrap();
+
+        assert_eq!(store.page("room-1", None, 10).unwrap().len(), 1);
+        assert_eq!(store.page("room-2", None, 10).unwrap().len(), 2);
+        assert_eq!(store.page("room-3", None, 10).unwrap().len(), 0);
+        assert_eq!(store.last_message_id("room-3").unwrap(), None);
+    }
+
+    fn assert_remove_room(store: &dyn HistoryStore) {
+        store.append(&message("room-1", 1)).unwrap();
+        store.append(&message("room-2", 1)).unwrap();
+
+        store.remove_room("room-1").unwrap();
+
+        assert_eq!(store.page("room-1", None, 10).unwrap().len(), 0);
+        assert_eq!(store.last_message_id("room-1").unwrap(), None);
+        assert_eq!(store.page("room-2", None, 10).unwrap().len(), 1);
+
+        // the room can be recorded again after it was removed
+        store.append(&message("room-1", 1)).unwrap();
+        assert_eq!(store.last_message_id("room-1").unwrap(), Some(1));
+    }
+
+    #[test]
+    fn test_limit_in_memory() {
+        assert_limit(&InMemoryHistoryStore::new(10), 10);
+    }
+
+    #[test]
+    fn test_pagination_in_memory() {
+        assert_pagination(&InMemoryHistoryStore::new(DEFAULT_HISTORY_RETENTION));
+    }
+
+    #[test]
+    fn test_rooms_are_isolated_in_memory() {
+        assert_rooms_are_isolated(&InMemoryHistoryStore::new(DEFAULT_HISTORY_RETENTION));
+    }
+
+    #[test]
+    fn test_remove_room_in_memory() {
+        assert_remove_room(&InMemoryHistoryStore::new(DEFAULT_HISTORY_RETENTION));
+    }
+
+    #[test]
+    fn test_limit_segmented_log() {
+        let dir = TempDir::new();
+        assert_limit(&SegmentedLogHistoryStore::open(&dir.0, 10).unwrap(), 10);
+    }
+
+    #[test]
+    fn test_pagination_segmented_log() {
+        let dir = TempDir::new();
+        assert_pagination(
+            &SegmentedLogHistoryStore::open(&dir.0, DEFAULT_HISTORY_RETENTION).unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_rooms_are_isolated_segmented_log() {
+        let dir = TempDir::new();
+        assert_rooms_are_isolated(
+            &SegmentedLogHistoryStore::open(&dir.0, DEFAULT_HISTORY_RETENTION).unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_remove_room_segmented_log() {
+        let dir = TempDir::new();
+        assert_remove_room(
+            &SegmentedLogHistoryStore::open(&dir.0, DEFAULT_HISTORY_RETENTION).unwrap(),
+        );
+
+        // only the message recorded after the removal is read back from the disk
+        let store = SegmentedLogHistoryStore::open(&dir.0, DEFAULT_HISTORY_RETENTION).unwrap();
+        assert_eq!(store.page("room-1", None, 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_segmented_log_reads_do_not_create_directories() {
+        let dir = TempDir::new();
+        let store = SegmentedLogHistoryStore::open(&dir.0, DEFAULT_HISTORY_RETENTION).unwrap();
+
+        assert_eq!(store.last_message_id("test").unwrap(), None);
+        assert_eq!(store.page("test", None, 10).unwrap().len(), 0);
+        assert!(!dir.0.join("test").exists());
+
+        store.append(&message("test", 1)).unwrap();
+        assert!(dir.0.join("test").exists());
+    }
+
+    #[test]
+    fn test_segmented_log_rejects_empty_room_name() {
+        let dir = TempDir::new();
+        let store = SegmentedLogHistoryStore::open(&dir.0, DEFAULT_HISTORY_RETENTION).unwrap();
+        store.append(&message("test", 1)).unwrap();
+
+        assert!(store.append(&message("", 1)).is_err());
+        assert!(store.remove_room("").is_err());
+
+        // the history of the other rooms is left untouched
+        let store = SegmentedLogHistoryStore::open(&dir.0, DEFAULT_HISTORY_RETENTION).unwrap();
+        assert_eq!(store.last_message_id("test").unwrap(), Some(1));
+    }
+
+    #[test]
+    fn test_segmented_log_survives_restart() {
+        let dir = TempDir::new();
+
+        {
+            let store = SegmentedLogHistoryStore::open(&dir.0, 10).unwrap();
+            for id in 1..=25 {
+                store.append(&message("test", id)).unwrap();
+            }
+        }
+
+        let store = SegmentedLogHistoryStore::open(&dir.0, 10).unwrap();
+        let messages = store.page("test", None, usize::MAX).unwrap();
+        assert_eq!(messages.len(), 10);
+        assert_eq!(messages.first().unwrap().id, 16);
+        assert_eq!(store.last_message_id("test").unwrap(), Some(25));
+
+        // appending continues after the recovered messages
+        store.append(&message("test", 26)).unwrap();
+        assert_eq!(store.page("test", None, 1).unwrap().first().unwrap().id, 26);
+    }
+
+    #[test]
+    fn test_segmented_log_recovers_torn_line() {
+        let dir = TempDir::new();
+
+        {
+            let store = SegmentedLogHistoryStore::open(&dir.0, 10).unwrap();
+            for id in 1..=3 {
+                store.append(&message("test", id)).unwrap();
+            }
+        }
+
+        // a line with invalid UTF-8 followed by a line cut off in the middle of a write
+        let segment = std::fs::read_dir(dir.0.join("test"))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&segment)
+            .unwrap();
+        std::io::Write::write_all(&mut file, b"\xff\xfe\n{\"id\":4,\"ts\":17").unwrap();
+        drop(file);
+
+        {
+            let store = SegmentedLogHistoryStore::open(&dir.0, 10).unwrap();
+            assert_eq!(store.last_message_id("test").unwrap(), Some(3));
+            store.append(&message("test", 4)).unwrap();
+        }
+
+        let store = SegmentedLogHistoryStore::open(&dir.0, 10).unwrap();
+        let messages = store.page("test", None, usize::MAX).unwrap();
+        assert_eq!(contents(&messages), vec!["msg1", "msg2", "msg3", "msg4"]);
+    }
+
+    #[test]
+    fn test_segmented_log_drops_old_segments() {
+        let dir = TempDir::new();
+        let store = SegmentedLogHistoryStore::open(&dir.0, 10).unwrap();
+
+        for id in 1..=100 {
+            store.append(&message("test", id)).unwrap();
+        }
+
+        let segment_count = std::fs::read_dir(dir.0.join("test")).unwrap().count();
+        assert!(segment_count <= 3, "found {} segments", segment_count);
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use comms::event::UserMessageBroadcastEvent;
+
+use super::{page_of, HistoryStore};
+
+const SEGMENT_EXTENSION: &str = "log";
+
+#[derive(Debug)]
+/// [SegmentedLogHistoryStore] persists the messages of each room to an append-only log on disk
+///
+/// Every room gets its own directory of segment files, each holding newline delimited JSON messages
+/// and named after the id of its first message. Segments are only ever appended to, and a segment is
+/// deleted as a whole once the newer segments alone hold enough messages to fill the retention limit.
+/// The messages within the retention limit are cached in memory to serve the history pages.
+///
+/// The disk is only touched while the log of a single room is locked, never while the map of rooms is,
+/// and the blocking file I/O is moved off the async worker threads when running on a multi-threaded runtime.
+pub struct SegmentedLogHistoryStore {
+    dir: PathBuf,
+    retention: usize,
+    /// The log of a room is `None` until it is read from the disk on the first access
+    rooms: Mutex<HashMap<String, Arc<Mutex<Option<RoomLog>>>>>,
+}
+
+impl SegmentedLogHistoryStore {
+    /// Opens a store in the given directory, creating the directory if it does not exist
+    /// The logs of a room are read from the disk when the room is first accessed
+    pub fn open(dir: impl AsRef<Path>, retention: usize) -> anyhow::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("could not create history directory {:?}", dir))?;
+
+        Ok(SegmentedLogHistoryStore {
+            dir,
+            retention,
+            rooms: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The directory holding the segments of a room
+    /// An empty room name is rejected, as it would point to the directory of the whole store
+    fn room_dir(&self, room: &str) -> anyhow::Result<PathBuf> {
+        anyhow::ensure!(
+            !room.is_empty(),
+            "the history of a room without a name can not be stored"
+        );
+
+        Ok(self.dir.join(room_dir_name(room)))
+    }
+
+    fn with_room<T>(
+        &self,
+        room: &str,
+        f: impl FnOnce(&mut RoomLog) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let dir = self.room_dir(room)?;
+        let room_log = self
+            .rooms
+            .lock()
+            .unwrap()
+            .entry(String::from(room))
+            .or_default()
+            .clone();
+
+        blocking(|| {
+            let mut room_log = room_log.lock().unwrap();
+
+            let room_log = match room_log.as_mut() {
+                Some(room_log) => room_log,
+                None => room_log.insert(
+                    RoomLog::open(dir, self.retention)
+                        .with_context(|| format!("could not open history of room '{}'", room))?,
+                ),
+            };
+
+            f(room_log)
+        })
+    }
+}
+
+impl HistoryStore for SegmentedLogHistoryStore {
+    fn append(&self, message: &UserMessageBroadcastEvent) -> anyhow::Result<()> {
+        self.with_room(&message.room, |room_log| room_log.append(message))
+    }
+
+    fn page(
+        &self,
+        room: &str,
+        before: Option<u64>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<UserMessageBroadcastEvent>> {
+        self.with_room(room, |room_log| Ok(page_of(&room_log.cache, before, limit)))
+    }
+
+    fn last_message_id(&self, room: &str) -> anyhow::Result<Option<u64>> {
+        self.with_room(room, |room_log| {
+            Ok(room_log.cache.back().map(|message| message.id))
+        })
+    }
+
+    fn remove_room(&self, room: &str) -> anyhow::Result<()> {
+        let dir = self.room_dir(room)?;
+        let room_log = self.rooms.lock().unwrap().remove(room);
+
+        blocking(|| {
+            // waits for a message of the room which is still being appended
+            let _room_log = room_log.as_ref().map(|room_log| room_log.lock().unwrap());
+
+            if dir.exists() {
+                fs::remove_dir_all(&dir)
+                    .with_context(|| format!("could not remove history directory {:?}", dir))?;
+            }
+
+            Ok(())
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Segment {
+    path: PathBuf,
+    message_count: usize,
+}
+
+#[derive(Debug)]
+/// [RoomLog] is the on disk log of a single room
+struct RoomLog {
+    dir: PathBuf,
+    retention: usize,
+    /// Segments ordered oldest to newest, messages are appended to the last one
+    segments: VecDeque<Segment>,
+    /// Open handle of the last segment, if it still has room for more messages
+    writer: Option<File>,
+    /// Number of messages in all segments
+    message_count: usize,
+    /// The latest messages within the retention limit
+    cache: VecDeque<UserMessageBroadcastEvent>,
+}
+
+impl RoomLog {
+    /// Reads the segments of the room, the directory is only created once a message is appended
+    fn open(dir: PathBuf, retention: usize) -> anyhow::Result<Self> {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries.collect::<Vec<_>>(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut segment_paths = entries
+            .into_iter()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter_map(|path| {
+                let first_id = path
+                    .extension()
+                    .filter(|extension| *extension == SEGMENT_EXTENSION)
+                    .and_then(|_| path.file_stem()?.to_str()?.parse::<u64>().ok())?;
+
+                Some((first_id, path))
+            })
+            .collect::<Vec<_>>();
+        segment_paths.sort_by_key(|(first_id, _)| *first_id);
+
+        let mut room_log = RoomLog {
+            dir,
+            retention,
+            segments: VecDeque::new(),
+            writer: None,
+            message_count: 0,
+            cache: VecDeque::with_capacity(retention),
+        };
+
+        for (_, path) in segment_paths {
+            let mut message_count = 0;
+
+            let contents = fs::read(&path)?;
+            let complete_len = contents
+                .iter()
+                .rposition(|byte| *byte == b'\n')
+                .map_or(0, |index| index + 1);
+
+            // lines which are not valid messages, e.g. with invalid UTF-8, are skipped
+            for line in contents[..complete_len].split(|byte| *byte == b'\n') {
+                if let Ok(message) = serde_json::from_slice::<UserMessageBroadcastEvent>(line) {
+                    room_log.cache_message(message);
+                    message_count += 1;
+                }
+            }
+
+            // a line partially written at the end of a segment is completed if it holds a whole
+            // message and cut off otherwise, so the next appended message starts on its own line
+            let tail = &contents[complete_len..];
+            if !tail.is_empty() {
+                if let Ok(message) = serde_json::from_slice::<UserMessageBroadcastEvent>(tail) {
+                    OpenOptions::new()
+                        .append(true)
+                        .open(&path)?
+                        .write_all(b"\n")?;
+                    room_log.cache_message(message);
+                    message_count += 1;
+                } else {
+                    OpenOptions::new()
+                        .write(true)
+                        .open(&path)?
+                        .set_len(complete_len as u64)?;
+                }
+            }
+
+            room_log.message_count += message_count;
+            room_log.segments.push_back(Segment {
+                path,
+                message_count,
+            });
+        }
+
+        if let Some(segment) = room_log.segments.back() {
+            if segment.message_count < room_log.segment_capacity() {
+                room_log.writer = Some(OpenOptions::new().append(true).open(&segment.path)?);
+            }
+        }
+
+        Ok(room_log)
+    }
+
+    /// Every segment holds as many messages as the retention limit
+    fn segment_capacity(&self) -> usize {
+        self.retention.max(1)
+    }
+
+    fn cache_message(&mut self, message: UserMessageBroadcastEvent) {
+        self.cache.push_back(message);
+
+        if self.cache.len() > self.retention {
+            self.cache.pop_front();
+        }
+    }
+
+    fn append(&mut self, message: &UserMessageBroadcastEvent) -> anyhow::Result<()> {
+        if self.writer.is_none() {
+            self.start_segment(message.id)?;
+        }
+
+        let mut line = serde_json::to_vec(message)?;
+        line.push(b'\n');
+
+        self.writer
+            .as_mut()
+            .expect("a segment is always open for appending")
+            .write_all(&line)
+            .context("could not append to the history segment")?;
+
+        let segment = self.segments.back_mut().expect("a segment exists");
+        segment.message_count += 1;
+        self.message_count += 1;
+
+        if segment.message_count >= self.segment_capacity() {
+            self.writer = None;
+        }
+
+        self.cache_message(message.clone());
+        self.drop_expired_segments()
+    }
+
+    fn start_segment(&mut self, first_id: u64) -> anyhow::Result<()> {
+        let path = self
+            .dir
+            .join(format!("{:020}.{}", first_id, SEGMENT_EXTENSION));
+        fs::create_dir_all(&self.dir)?;
+        let writer = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        self.segments.push_back(Segment {
+            path,
+            message_count: 0,
+        });
+        self.writer = Some(writer);
+
+        Ok(())
+    }
+
+    /// Delete the oldest segments while the remaining segments hold enough messages for the retention limit
+    fn drop_expired_segments(&mut self) -> anyhow::Result<()> {
+        while self.segments.len() > 1
+            && self.message_count - self.segments[0].message_count >= self.retention
+        {
+            let segment = self.segments.pop_front().expect("a segment exists");
+
+            fs::remove_file(&segment.path)
+                .with_context(|| format!("could not remove segment {:?}", segment.path))?;
+            self.message_count -= segment.message_count;
+        }
+
+        Ok(())
+    }
+}
+
+/// Run blocking file I/O without stalling the other tasks of the async worker thread
+/// The current-thread runtime of the tests can not hand its tasks over, so the I/O just runs in place there
+fn blocking<T>(f: impl FnOnce() -> T) -> T {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(f)
+        }
+        _ => f(),
+    }
+}
+
+/// Room names are escaped before being used as directory names, so they can not point outside of the store
+fn room_dir_name(room: &str) -> String {
+    room.bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
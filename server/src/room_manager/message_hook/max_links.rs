@@ -0,0 +1,61 @@
+use super::{HookVerdict, Message, MessageHook};
+
+/// Prefixes of the words counted as links, compared case insensitively
+const LINK_PREFIXES: &[&str] = &["http://", "https://", "www."];
+
+/// [MaxLinksHook] rejects the messages containing more than a number of links, e.g. to keep spam out of a room
+#[derive(Debug, Clone)]
+pub struct MaxLinksHook {
+    max_links: usize,
+}
+
+impl MaxLinksHook {
+    pub fn new(max_links: usize) -> Self {
+        MaxLinksHook { max_links }
+    }
+}
+
+impl MessageHook for MaxLinksHook {
+    fn on_message(&self, message: &Message) -> HookVerdict {
+        let links = message
+            .content
+            .split_whitespace()
+            .filter(|word| is_link(word))
+            .count();
+
+        if links > self.max_links {
+            HookVerdict::Reject(format!(
+                "message can contain at most {} links",
+                self.max_links
+            ))
+        } else {
+            HookVerdict::Allow
+        }
+    }
+}
+
+fn is_link(word: &str) -> bool {
+    LINK_PREFIXES.iter().any(|prefix| {
+        word.get(..prefix.len())
+            .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+    })
+}
+
+#[test]
+fn test_links_are_counted() {
+    let hook = MaxLinksHook::new(1);
+    let verdict = |content| {
+        hook.on_message(&Message {
+            room: "general",
+            user_id: "alice",
+            content,
+        })
+    };
+
+    assert_eq!(verdict("see https://example.com"), HookVerdict::Allow);
+    assert_eq!(verdict("no links in here, www"), HookVerdict::Allow);
+    assert_eq!(
+        verdict("HTTPS://a.com and www.b.com"),
+        HookVerdict::Reject("message can contain at most 1 links".into())
+    );
+}
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+pub use self::max_links::MaxLinksHook;
+pub use self::word_blocklist::{BlocklistAction, WordBlocklistHook};
+
+mod max_links;
+mod word_blocklist;
+
+/// [Message] is a message a user is about to send to a room, as seen by the hooks
+#[derive(Debug, Clone, Copy)]
+pub struct Message<'a> {
+    pub room: &'a str,
+    pub user_id: &'a str,
+    pub content: &'a str,
+}
+
+/// [HookVerdict] is what a hook has decided about a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookVerdict {
+    /// Pass the message on to the next hook unchanged
+    Allow,
+    /// Replace the content of the message, the next hooks see the new content
+    Rewrite(String),
+    /// Refuse the message, the reason is sent back to the user
+    Reject(String),
+    /// Drop the message without telling the user, only the sender sees it as if it was sent
+    ShadowDrop,
+}
+
+/// [MessageHook] inspects the messages sent to a room before they are recorded and broadcast
+///
+/// Hooks run one after the other, in the order they were registered, until one of them rejects or drops the message.
+/// They are called on the task of the sending session, before the room is locked, so they should not block.
+pub trait MessageHook: Debug + Send + Sync {
+    fn on_message(&self, message: &Message) -> HookVerdict;
+}
+
+/// [HookOutcome] is the outcome of running all the hooks of a room on a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookOutcome {
+    /// Record and broadcast the message with the given content
+    Send(String),
+    /// Refuse the message for the given reason
+    Reject(String),
+    /// Only show the message with the given content to its sender
+    ShadowDrop(String),
+}
+
+/// Run the hooks on a message in order, see [MessageHook]
+pub fn run_hooks(
+    hooks: &[Arc<dyn MessageHook>],
+    room: &str,
+    user_id: &str,
+    mut content: String,
+) -> HookOutcome {
+    for hook in hooks {
+        let message = Message {
+            room,
+            user_id,
+            content: &content,
+        };
+
+        match hook.on_message(&message) {
+            HookVerdict::Allow => {}
+            HookVerdict::Rewrite(rewritten) => content = rewritten,
+            HookVerdict::Reject(reason) => return HookOutcome::Reject(reason),
+            HookVerdict::ShadowDrop => return HookOutcome::ShadowDrop(content),
+        }
+    }
+
+    HookOutcome::Send(content)
+}
+
+/// [MessageHooks] holds the hooks registered for all rooms and the ones registered for a single room
+#[derive(Debug, Clone, Default)]
+pub(super) struct MessageHooks {
+    global: Vec<Arc<dyn MessageHook>>,
+    by_room: HashMap<String, Vec<Arc<dyn MessageHook>>>,
+}
+
+impl MessageHooks {
+    pub fn add_global(&mut self, hook: Arc<dyn MessageHook>) {
+        self.global.push(hook);
+    }
+
+    pub fn add_for_room(&mut self, room: &str, hook: Arc<dyn MessageHook>) {
+        self.by_room
+            .entry(String::from(room))
+            .or_default()
+            .push(hook);
+    }
+
+    /// The hooks of a room, the ones registered for all rooms run first
+    pub fn for_room(&self, room: &str) -> Vec<Arc<dyn MessageHook>> {
+        self.global
+            .iter()
+            .chain(self.by_room.get(room).into_iter().flatten())
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Drops the messages of a single user
+    #[derive(Debug)]
+    struct ShadowBanHook(&'static str);
+
+    impl MessageHook for ShadowBanHook {
+        fn on_message(&self, message: &Message) -> HookVerdict {
+            if message.user_id == self.0 {
+                HookVerdict::ShadowDrop
+            } else {
+                HookVerdict::Allow
+            }
+        }
+    }
+
+    #[test]
+    fn test_hooks_run_in_order() {
+        let mut hooks = MessageHooks::default();
+        hooks.add_for_room("general", Arc::new(ShadowBanHook("mallory")));
+        hooks.add_global(Arc::new(WordBlocklistHook::new(
+            ["darn"],
+            BlocklistAction::Mask,
+        )));
+        hooks.add_global(Arc::new(MaxLinksHook::new(1)));
+
+        let general = hooks.for_room("general");
+        let random = hooks.for_room("random");
+        assert_eq!(general.len(), 3);
+        assert_eq!(random.len(), 2);
+
+        assert_eq!(
+            run_hooks(&general, "general", "alice", "darn it".into()),
+            HookOutcome::Send("**** it".into())
+        );
+        // the later hooks see the rewritten content
+        assert_eq!(
+            run_hooks(&general, "general", "mallory", "darn it".into()),
+            HookOutcome::ShadowDrop("**** it".into())
+        );
+        assert_eq!(
+            run_hooks(&random, "random", "mallory", "darn it".into()),
+            HookOutcome::Send("**** it".into())
+        );
+        assert!(matches!(
+            run_hooks(
+                &general,
+                "general",
+                "mallory",
+                "https://a.com https://b.com".into()
+            ),
+            HookOutcome::Reject(_)
+        ));
+    }
+}
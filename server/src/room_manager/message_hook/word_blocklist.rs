@@ -0,0 +1,145 @@
+use std::collections::HashSet;
+
+use serde::Deserialize;
+
+use super::{HookVerdict, Message, MessageHook};
+
+/// What happens to a message containing a blocked word
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlocklistAction {
+    /// The message is refused
+    Reject,
+    /// Every character of the blocked words is replaced with an asterisk
+    Mask,
+    /// The message is only shown to its sender
+    Drop,
+}
+
+/// [WordBlocklistHook] rejects, masks or drops the messages containing a blocked word
+///
+/// Words are compared case insensitively, and only whole words match, e.g. blocking "ass" does not block "class".
+#[derive(Debug, Clone)]
+pub struct WordBlocklistHook {
+    words: HashSet<String>,
+    action: BlocklistAction,
+}
+
+impl WordBlocklistHook {
+    pub fn new<I, S>(words: I, action: BlocklistAction) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        WordBlocklistHook {
+            words: words
+                .into_iter()
+                .map(|word| word.as_ref().to_lowercase())
+                .collect(),
+            action,
+        }
+    }
+
+    fn is_blocked(&self, word: &str) -> bool {
+        self.words.contains(&word.to_lowercase())
+    }
+}
+
+impl MessageHook for WordBlocklistHook {
+    fn on_message(&self, message: &Message) -> HookVerdict {
+        let content = message.content;
+        let blocked = words(content)
+            .filter(|(start, end)| self.is_blocked(&content[*start..*end]))
+            .collect::<Vec<_>>();
+
+        if blocked.is_empty() {
+            return HookVerdict::Allow;
+        }
+
+        match self.action {
+            BlocklistAction::Reject => {
+                HookVerdict::Reject(String::from("message contains a blocked word"))
+            }
+            BlocklistAction::Mask => {
+                let mut masked = String::with_capacity(content.len());
+                let mut copied = 0;
+                for (start, end) in blocked {
+                    masked.push_str(&content[copied..start]);
+                    masked.extend(content[start..end].chars().map(|_| '*'));
+                    copied = end;
+                }
+                masked.push_str(&content[copied..]);
+
+                HookVerdict::Rewrite(masked)
+            }
+            BlocklistAction::Drop => {
+                // the sender is not told, so the moderators can only find out from the logs
+                println!(
+                    "dropped a message of user '{}' to room '{}' for a blocked word",
+                    message.user_id, message.room
+                );
+
+                HookVerdict::ShadowDrop
+            }
+        }
+    }
+}
+
+/// The byte ranges of the words of the content, a word is a run of alphanumeric characters
+fn words(content: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let mut chars = content.char_indices().peekable();
+
+    std::iter::from_fn(move || {
+        let (start, _) = chars.find(|(_, c)| c.is_alphanumeric())?;
+        let mut end = content.len();
+        for (index, c) in chars.by_ref() {
+            if !c.is_alphanumeric() {
+                end = index;
+                break;
+            }
+        }
+
+        Some((start, end))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verdict(hook: &WordBlocklistHook, content: &str) -> HookVerdict {
+        hook.on_message(&Message {
+            room: "general",
+            user_id: "alice",
+            content,
+        })
+    }
+
+    #[test]
+    fn test_blocked_words_are_rejected() {
+        let hook = WordBlocklistHook::new(["Heck"], BlocklistAction::Reject);
+
+        assert_eq!(
+            verdict(&hook, "what the hecking heckler"),
+            HookVerdict::Allow
+        );
+        assert!(matches!(
+            verdict(&hook, "what the HECK?"),
+            HookVerdict::Reject(_)
+        ));
+    }
+
+    #[test]
+    fn test_blocked_words_are_masked_or_dropped() {
+        let hook = WordBlocklistHook::new(["heck", "darn"], BlocklistAction::Mask);
+
+        assert_eq!(verdict(&hook, "all good"), HookVerdict::Allow);
+        assert_eq!(
+            verdict(&hook, "Heck, darn it! heckler darn"),
+            HookVerdict::Rewrite("****, **** it! heckler ****".into())
+        );
+
+        let hook = WordBlocklistHook::new(["heck"], BlocklistAction::Drop);
+        assert_eq!(verdict(&hook, "heck"), HookVerdict::ShadowDrop);
+    }
+}
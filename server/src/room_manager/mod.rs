@@ -2,46 +2,128 @@ use std::sync::Arc;
 
 use tokio::sync::Mutex;
 
+pub use self::history::{
+    HistoryStore, InMemoryHistoryStore, SegmentedLogHistoryStore, DEFAULT_HISTORY_RETENTION,
+};
+use self::message_hook::MessageHooks;
+pub use self::message_hook::{BlocklistAction, MaxLinksHook, MessageHook, WordBlocklistHook};
 use self::room::ChatRoom;
-pub use self::room::{ChatRoomMetadata, SessionAndUserId, UserSessionHandle};
+pub use self::room::{
+    now_millis, ChatRoomMetadata, SessionAndUserId, UserSessionHandle, DEFAULT_HISTORY_PAGE_SIZE,
+    DEFAULT_ROOM_CHANNEL_CAPACITY,
+};
 
-pub use self::room_manager::RoomManager;
+pub use self::room_manager::{RoomManager, DEFAULT_BROADCAST_CHANNEL_CAPACITY};
 
+mod history;
+mod message_hook;
 mod room;
 #[allow(clippy::module_inception)]
 mod room_manager;
 
 #[derive(Debug)]
 pub struct RoomManagerBuilder {
-    chat_rooms: Vec<(ChatRoomMetadata, Arc<Mutex<ChatRoom>>)>,
+    chat_room_metadata: Vec<ChatRoomMetadata>,
+    history_store: Option<Arc<dyn HistoryStore>>,
+    message_hooks: MessageHooks,
+    room_channel_capacity: usize,
+    broadcast_channel_capacity: usize,
 }
 
 impl RoomManagerBuilder {
     pub fn new() -> Self {
         RoomManagerBuilder {
-            chat_rooms: Vec::new(),
+            chat_room_metadata: Vec::new(),
+            history_store: None,
+            message_hooks: MessageHooks::default(),
+            room_channel_capacity: DEFAULT_ROOM_CHANNEL_CAPACITY,
+            broadcast_channel_capacity: DEFAULT_BROADCAST_CHANNEL_CAPACITY,
         }
     }
 
     /// Add a room to the room manager
     /// Will panic if a room with the same name already exists
     pub fn create_room(mut self, metadata: ChatRoomMetadata) -> Self {
-        let chat_room = Arc::new(Mutex::new(ChatRoom::new(metadata.clone())));
-
         if self
-            .chat_rooms
+            .chat_room_metadata
             .iter()
-            .any(|(m, _)| m.name.eq(&metadata.name))
+            .any(|m| m.name.eq(&metadata.name))
         {
             panic!("room with the same name already exists");
         }
 
-        self.chat_rooms.push((metadata, chat_room));
+        self.chat_room_metadata.push(metadata);
+
+        self
+    }
+
+    /// Set the store which records the message history of all rooms
+    /// Defaults to an [InMemoryHistoryStore] with [DEFAULT_HISTORY_RETENTION]
+    pub fn history_store(mut self, history_store: Arc<dyn HistoryStore>) -> Self {
+        self.history_store = Some(history_store);
+
+        self
+    }
+
+    /// Add a hook which runs on the messages sent to every room, including the rooms created at runtime
+    /// The hooks for all rooms run before the hooks of a single room, in the order they were added
+    pub fn message_hook(mut self, hook: Arc<dyn MessageHook>) -> Self {
+        self.message_hooks.add_global(hook);
+
+        self
+    }
+
+    /// Add a hook which only runs on the messages sent to the room with the given name
+    /// The hook also applies to a room created at runtime with the name
+    pub fn room_message_hook(mut self, room: &str, hook: Arc<dyn MessageHook>) -> Self {
+        self.message_hooks.add_for_room(room, hook);
+
+        self
+    }
+
+    /// Set the number of events buffered for each participant of a room
+    /// Defaults to [DEFAULT_ROOM_CHANNEL_CAPACITY]
+    pub fn room_channel_capacity(mut self, capacity: usize) -> Self {
+        self.room_channel_capacity = capacity;
+
+        self
+    }
+
+    /// Set the number of events buffered for each session for the events sent to all sessions
+    /// Defaults to [DEFAULT_BROADCAST_CHANNEL_CAPACITY]
+    pub fn broadcast_channel_capacity(mut self, capacity: usize) -> Self {
+        self.broadcast_channel_capacity = capacity;
 
         self
     }
 
-    pub fn build(self) -> RoomManager {
-        RoomManager::new(self.chat_rooms)
+    /// Build the room manager, fails if the history of a room can not be read from the history store
+    pub fn build(self) -> anyhow::Result<RoomManager> {
+        let history_store = self
+            .history_store
+            .unwrap_or_else(|| Arc::new(InMemoryHistoryStore::new(DEFAULT_HISTORY_RETENTION)));
+
+        let chat_rooms = self
+            .chat_room_metadata
+            .into_iter()
+            .map(|metadata| {
+                let chat_room = ChatRoom::new(
+                    metadata.clone(),
+                    history_store.clone(),
+                    self.message_hooks.for_room(&metadata.name),
+                    self.room_channel_capacity,
+                )?;
+
+                Ok((metadata, Arc::new(Mutex::new(chat_room))))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(RoomManager::new(
+            chat_rooms,
+            history_store,
+            self.message_hooks,
+            self.room_channel_capacity,
+            self.broadcast_channel_capacity,
+        ))
     }
 }
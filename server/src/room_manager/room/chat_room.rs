@@ -1,24 +1,70 @@
-use comms::event::UserMessageBroadcastEvent;
-use comms::event::{self, Event};
+use comms::event::{self, CommandErrorCode, Event, ModerationAction};
+use comms::event::{UserDetail, UserMessageBroadcastEvent};
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::broadcast;
 use tokio::sync::Mutex;
 
 use super::{
-    user_registry::UserRegistry, user_session_handle::UserSessionHandle, SessionAndUserId,
+    room_event_receiver::RoomEventReceiver, user_registry::UserRegistry,
+    user_session_handle::UserSessionHandle, SessionAndUserId,
 };
+use crate::error::CommandError;
+use crate::room_manager::history::HistoryStore;
+use crate::room_manager::message_hook::MessageHook;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 /// [ChatRoomMetadata] holds the metadata that identifies a chat room
 pub struct ChatRoomMetadata {
     pub name: String,
     pub description: String,
+    /// The user who owns the room, rooms created at runtime are owned by their creator
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// The users who can moderate the room next to its owner
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub moderators: Vec<String>,
 }
 
-const BROADCAST_CHANNEL_CAPACITY: usize = 100;
-const MAX_HISTORY: usize = 10;
+/// [RoomRole] is what a user is allowed to do in a room, ordered from the least to the most privileged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RoomRole {
+    Member,
+    /// Can kick, ban and mute the members of the room
+    Moderator,
+    /// Can kick, ban and mute the moderators of the room as well
+    Owner,
+}
+
+/// Number of events buffered for each participant of a room, unless configured otherwise
+pub const DEFAULT_ROOM_CHANNEL_CAPACITY: usize = 100;
+/// Number of messages returned by a history request which does not specify a limit
+pub const DEFAULT_HISTORY_PAGE_SIZE: usize = 10;
+/// Upper bound of messages a single history request can return
+pub const MAX_HISTORY_PAGE_SIZE: usize = 50;
+/// The typing of a user ends this long after their latest start
+pub const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+/// Starts of a user who is already typing are announced again at most this often
+pub const TYPING_THROTTLE: Duration = Duration::from_secs(3);
+
+/// [Typing] is a user typing a message to the room
+#[derive(Debug, Clone, Copy)]
+struct Typing {
+    /// When the typing was last announced to the participants
+    announced_at: Instant,
+    /// When the typing ends unless the user starts typing again
+    expires_at: Instant,
+}
+
+/// [HistoryPage] is a slice of the recorded messages of a room, ordered oldest to newest
+#[derive(Debug, Clone)]
+pub struct HistoryPage {
+    pub messages: Vec<UserMessageBroadcastEvent>,
+    /// The cursor to request the next (older) page with, if there are older messages
+    pub next_cursor: Option<u64>,
+}
 
 #[derive(Debug)]
 /// [ChatRoom] handles the participants of a chat room and the primary broadcast channel
@@ -27,67 +73,190 @@ pub struct ChatRoom {
     metadata: ChatRoomMetadata,
     broadcast_tx: broadcast::Sender<Event>,
     user_registry: UserRegistry,
-    history: VecDeque<UserMessageBroadcastEvent>,
+    /// Store of the recorded messages, shared between all rooms
+    history_store: Arc<dyn HistoryStore>,
+    next_message_id: u64,
+    /// Roles of the users above [RoomRole::Member], by user id
+    roles: HashMap<String, RoomRole>,
+    /// Banned users by user id, with the time their ban ends at if it is not permanent
+    bans: HashMap<String, Option<u64>>,
+    /// Muted users by user id, with the time their mute ends at if it is not permanent
+    mutes: HashMap<String, Option<u64>>,
+    /// Hooks run on every message before it is recorded, see [MessageHook]
+    message_hooks: Vec<Arc<dyn MessageHook>>,
+    /// Users typing a message, by user id
+    typing: HashMap<String, Typing>,
+    /// Sessions removed from the room by a kick or a ban, by session id, with the event that removed them
+    /// They are kept until the session leaves the room, in case the session has missed the event
+    removed_sessions: HashMap<String, event::UserModeratedBroadcastEvent>,
+    /// Set when the room is deleted, nothing is recorded in its history after that
+    closed: bool,
 }
 
 impl ChatRoom {
-    pub fn new(metadata: ChatRoomMetadata) -> Self {
-        let (broadcast_tx, _) = broadcast::channel(BROADCAST_CHANNEL_CAPACITY);
+    /// Creates a room which continues the message ids from the history recorded in the store
+    /// The channel capacity is the number of events buffered for each participant
+    pub fn new(
+        metadata: ChatRoomMetadata,
+        history_store: Arc<dyn HistoryStore>,
+        message_hooks: Vec<Arc<dyn MessageHook>>,
+        channel_capacity: usize,
+    ) -> anyhow::Result<Self> {
+        let (broadcast_tx, _) = broadcast::channel(channel_capacity);
+        let last_message_id = history_store.last_message_id(&metadata.name)?;
+
+        let mut roles = metadata
+            .moderators
+            .iter()
+            .map(|user_id| (user_id.clone(), RoomRole::Moderator))
+            .collect::<HashMap<_, _>>();
+        if let Some(owner) = metadata.owner.as_ref() {
+            roles.insert(owner.clone(), RoomRole::Owner);
+        }
 
-        ChatRoom {
+        Ok(ChatRoom {
             metadata,
             broadcast_tx,
             user_registry: UserRegistry::new(),
-            history: VecDeque::with_capacity(MAX_HISTORY),
-        }
+            history_store,
+            next_message_id: last_message_id.map_or(1, |id| id + 1),
+            roles,
+            bans: HashMap::new(),
+            mutes: HashMap::new(),
+            message_hooks,
+            typing: HashMap::new(),
+            removed_sessions: HashMap::new(),
+            closed: false,
+        })
     }
 
-    pub fn get_unique_user_ids(&self) -> Vec<String> {
-        self.user_registry.get_unique_user_ids()
+    pub fn message_hooks(&self) -> &[Arc<dyn MessageHook>] {
+        &self.message_hooks
     }
 
-    /// Add a participant to the room and broadcast that they joined
+    pub fn name(&self) -> &str {
+        &self.metadata.name
+    }
+
+    pub fn get_unique_users(&self) -> Vec<UserDetail> {
+        self.user_registry.get_unique_users()
+    }
+
+    pub fn role(&self, user_id: &str) -> RoomRole {
+        self.roles.get(user_id).copied().unwrap_or(RoomRole::Member)
+    }
+
+    /// Close the room before it is deleted, the sessions still holding it can not join, send or record anymore
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
+    /// Refuses the messages of the sessions which are not in the room, e.g. after a kick,
+    /// and of the users who are banned from or muted in the room
+    pub fn check_can_send(
+        &mut self,
+        session_and_user_id: &SessionAndUserId,
+    ) -> Result<(), CommandError> {
+        if self.closed {
+            return Err(CommandError::room_not_found(&self.metadata.name));
+        }
+
+        if !self.user_registry.contains_session(session_and_user_id) {
+            return Err(CommandError::not_joined(&self.metadata.name));
+        }
+
+        let user_id = &session_and_user_id.user_id;
+        if is_restricted(&mut self.bans, user_id) {
+            return Err(CommandError::in_room(
+                CommandErrorCode::Banned,
+                &self.metadata.name,
+                format!("you are banned from room '{}'", self.metadata.name),
+            ));
+        }
+
+        if is_restricted(&mut self.mutes, user_id) {
+            return Err(CommandError::in_room(
+                CommandErrorCode::Muted,
+                &self.metadata.name,
+                format!("you are muted in room '{}'", self.metadata.name),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Add a participant to the room and broadcast that they joined, banned users are refused
     ///
     /// # Returns
     ///
-    /// - A broadcast receiver for the user to receive messages from the room
+    /// - A [RoomEventReceiver] for the user to receive messages from the room
     /// - A [UserSessionHandle] for the user to be able to interact with the room
     pub fn join(
         &mut self,
         session_and_user_id: &SessionAndUserId,
+        display_name: &str,
         chat_room_arc: Arc<Mutex<ChatRoom>>, // pass the chat room arc to the user session handle so it can record messages without broadcasting them
-    ) -> (broadcast::Receiver<Event>, UserSessionHandle) {
+    ) -> Result<(RoomEventReceiver, UserSessionHandle), CommandError> {
+        if self.closed {
+            return Err(CommandError::room_not_found(&self.metadata.name));
+        }
+
+        if is_restricted(&mut self.bans, &session_and_user_id.user_id) {
+            return Err(CommandError::in_room(
+                CommandErrorCode::Banned,
+                &self.metadata.name,
+                format!("you are banned from room '{}'", self.metadata.name),
+            ));
+        }
+
         let broadcast_tx = self.broadcast_tx.clone();
-        let broadcast_rx = broadcast_tx.subscribe();
-        let user_session_handle = UserSessionHandle::new(
+        // the messages recorded so far are not broadcast to the user anymore, they can only be read from the history
+        let receiver = RoomEventReceiver::new(
+            broadcast_tx.subscribe(),
+            chat_room_arc.clone(),
             self.metadata.name.clone(),
-            broadcast_tx,
             session_and_user_id.clone(),
-            chat_room_arc,
+            self.next_message_id - 1,
         );
+        let user_session_handle =
+            UserSessionHandle::new(broadcast_tx, session_and_user_id.clone(), chat_room_arc);
 
         // If the user is new e.g. they do not have another session with same user id,
         // broadcast that they joined to all users
-        if self.user_registry.insert(&user_session_handle) {
+        if self
+            .user_registry
+            .insert(&user_session_handle, display_name)
+        {
             let _ = self.broadcast_tx.send(Event::RoomParticipation(
                 event::RoomParticipationBroadcastEvent {
                     user_id: session_and_user_id.user_id.clone(),
+                    display_name: String::from(display_name),
                     room: self.metadata.name.clone(),
                     status: event::RoomParticipationStatus::Joined,
                 },
             ));
         }
 
-        (broadcast_rx, user_session_handle)
+        Ok((receiver, user_session_handle))
     }
 
     /// Remove a participant from the room and broadcast that they left
     /// Consume the [UserSessionHandle] to drop it
     pub fn leave(&mut self, user_session_handle: UserSessionHandle) {
+        self.removed_sessions
+            .remove(user_session_handle.session_id());
+        let display_name = self
+            .user_registry
+            .display_name(user_session_handle.user_id())
+            .map(String::from)
+            .unwrap_or_else(|| String::from(user_session_handle.user_id()));
+
         if self.user_registry.remove(&user_session_handle) {
+            self.typing.remove(user_session_handle.user_id());
             let _ = self.broadcast_tx.send(Event::RoomParticipation(
                 event::RoomParticipationBroadcastEvent {
                     user_id: String::from(user_session_handle.user_id()),
+                    display_name,
                     room: self.metadata.name.clone(),
                     status: event::RoomParticipationStatus::Left,
                 },
@@ -95,55 +264,474 @@ impl ChatRoom {
         }
     }
 
-    // Send a message to all users in the room and record it in the history
-    pub fn send_message(&mut self, user_id: String, content: String) {
-        let message_event = event::UserMessageBroadcastEvent {
+    /// Update the nickname of a participant and broadcast it, does nothing if the user is not in the room
+    pub fn change_nickname(&mut self, user_id: &str, nickname: &str) {
+        if self.user_registry.set_display_name(user_id, nickname) {
+            let _ = self.broadcast_tx.send(Event::NicknameChanged(
+                event::NicknameChangedBroadcastEvent {
+                    room: Some(self.metadata.name.clone()),
+                    user_id: String::from(user_id),
+                    nickname: String::from(nickname),
+                    request_id: None,
+                },
+            ));
+        }
+    }
+
+    /// Kick, ban, unban, mute or unmute a user of the room and broadcast it to the participants
+    ///
+    /// Only the owner and the moderators can moderate the room, and only the users with a lower role than theirs.
+    /// The users who are not in the room can be banned and muted as well, but not kicked.
+    /// A kick with a duration keeps the user from joining again until it has passed.
+    /// The sessions of a kicked or banned user are removed from the room right away, see [ChatRoom::removal_of].
+    pub fn moderate(
+        &mut self,
+        moderator_id: &str,
+        user_id: &str,
+        action: ModerationAction,
+        duration: Option<Duration>,
+    ) -> Result<(), CommandError> {
+        let room = &self.metadata.name;
+        let moderator_role = self.role(moderator_id);
+        if moderator_role < RoomRole::Moderator {
+            return Err(CommandError::in_room(
+                CommandErrorCode::PermissionDenied,
+                room,
+                format!(
+                    "only the owner and the moderators of room '{}' can moderate it",
+                    room
+                ),
+            ));
+        }
+        if self.role(user_id) >= moderator_role {
+            return Err(CommandError::in_room(
+                CommandErrorCode::PermissionDenied,
+                room,
+                format!("you can not moderate user '{}' in room '{}'", user_id, room),
+            ));
+        }
+
+        let expires_at = duration.map(|duration| {
+            let millis = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+
+            now_millis().saturating_add(millis)
+        });
+
+        match action {
+            ModerationAction::Kicked => {
+                if !self.user_registry.contains(user_id) {
+                    return Err(CommandError::in_room(
+                        CommandErrorCode::UserNotFound,
+                        room,
+                        format!("user '{}' is not in room '{}'", user_id, room),
+                    ));
+                }
+                if expires_at.is_some() {
+                    self.bans.insert(String::from(user_id), expires_at);
+                }
+            }
+            ModerationAction::Banned => {
+                self.bans.insert(String::from(user_id), expires_at);
+            }
+            ModerationAction::Muted => {
+                self.mutes.insert(String::from(user_id), expires_at);
+            }
+            ModerationAction::Unbanned => {
+                if !is_restricted(&mut self.bans, user_id) {
+                    return Err(CommandError::in_room(
+                        CommandErrorCode::InvalidCommand,
+                        room,
+                        format!("user '{}' is not banned from room '{}'", user_id, room),
+                    ));
+                }
+                self.bans.remove(user_id);
+            }
+            ModerationAction::Unmuted => {
+                if !is_restricted(&mut self.mutes, user_id) {
+                    return Err(CommandError::in_room(
+                        CommandErrorCode::InvalidCommand,
+                        room,
+                        format!("user '{}' is not muted in room '{}'", user_id, room),
+                    ));
+                }
+                self.mutes.remove(user_id);
+            }
+        }
+
+        let moderated = event::UserModeratedBroadcastEvent {
+            room: room.clone(),
+            user_id: String::from(user_id),
+            moderator_id: String::from(moderator_id),
+            action,
+            expires_at,
+        };
+
+        // the sessions of a kicked or banned user drop their handles when they receive the event,
+        // but they can not act in the room anymore even if they never do
+        if matches!(
+            moderated.action,
+            ModerationAction::Kicked | ModerationAction::Banned
+        ) {
+            for session_id in self.user_registry.remove_user(user_id) {
+                self.removed_sessions.insert(session_id, moderated.clone());
+            }
+            self.typing.remove(user_id);
+        }
+
+        let _ = self.broadcast_tx.send(Event::UserModerated(moderated));
+
+        Ok(())
+    }
+
+    /// The kick or the ban that has removed the session from the room, if any
+    pub fn removal_of(&self, session_id: &str) -> Option<event::UserModeratedBroadcastEvent> {
+        self.removed_sessions.get(session_id).cloned()
+    }
+
+    /// Record a message in the history without broadcasting it
+    /// The message is assigned the next message id of the room and the current server time
+    pub fn record_message(
+        &mut self,
+        user_id: String,
+        content: String,
+    ) -> anyhow::Result<UserMessageBroadcastEvent> {
+        anyhow::ensure!(
+            !self.closed,
+            "room '{}' has been deleted",
+            self.metadata.name
+        );
+
+        let message = UserMessageBroadcastEvent {
+            id: self.next_message_id,
+            timestamp: now_millis(),
             room: self.metadata.name.clone(),
             user_id,
             content,
+            unrecorded: false,
+        };
+
+        self.history_store.append(&message)?;
+        self.next_message_id += 1;
+
+        Ok(message)
+    }
+
+    /// Mark the user as typing until [TYPING_TIMEOUT] from now and announce it to the participants,
+    /// unless it has been announced within [TYPING_THROTTLE]
+    /// Returns whether the user has just started typing, the caller has to expire the typing then, see [ChatRoom::expire_typing]
+    pub fn start_typing(&mut self, user_id: &str, now: Instant) -> bool {
+        let expires_at = now + TYPING_TIMEOUT;
+        let started = match self.typing.get_mut(user_id) {
+            Some(typing) => {
+                typing.expires_at = expires_at;
+                if now.saturating_duration_since(typing.announced_at) < TYPING_THROTTLE {
+                    return false;
+                }
+
+                typing.announced_at = now;
+                false
+            }
+            None => {
+                self.typing.insert(
+                    String::from(user_id),
+                    Typing {
+                        announced_at: now,
+                        expires_at,
+                    },
+                );
+                true
+            }
         };
 
-        self.history.push_back(message_event.clone());
-        if self.history.len() > MAX_HISTORY {
-            self.history.pop_front();
+        self.broadcast_typing(user_id, true);
+
+        started
+    }
+
+    /// End the typing of the user and announce it to the participants, if the user is typing
+    pub fn stop_typing(&mut self, user_id: &str) {
+        if self.typing.remove(user_id).is_some() {
+            self.broadcast_typing(user_id, false);
         }
+    }
+
+    /// End the typing of the user if it has expired
+    /// Returns when the typing expires if the user is still typing
+    pub fn expire_typing(&mut self, user_id: &str, now: Instant) -> Option<Instant> {
+        let expires_at = self.typing.get(user_id)?.expires_at;
+        if expires_at > now {
+            return Some(expires_at);
+        }
+
+        self.stop_typing(user_id);
 
-        // broadcast
-        let _ = self.broadcast_tx.send(Event::UserMessage(message_event));
+        None
     }
 
-    // Record a message in the history without broadcasting it
-    pub fn record_message(&mut self, message: &UserMessageBroadcastEvent) {
-        self.history.push_back(message.clone());
+    /// End the typing of the user without announcing it, the participants end it when they receive the user's message
+    pub fn clear_typing(&mut self, user_id: &str) {
+        self.typing.remove(user_id);
+    }
+
+    fn broadcast_typing(&self, user_id: &str, typing: bool) {
+        let _ = self
+            .broadcast_tx
+            .send(Event::UserTyping(event::UserTypingBroadcastEvent {
+                room: self.metadata.name.clone(),
+                user_id: String::from(user_id),
+                typing,
+            }));
+    }
 
-        if self.history.len() > MAX_HISTORY {
-            self.history.pop_front();
+    /// Create a message which is only shown to its sender, without recording it
+    /// It shares the id of the latest recorded message, so that the recorded ids stay consecutive,
+    /// and is marked as unrecorded, so that the id is not used to dedupe it
+    pub fn shadow_message(&self, user_id: String, content: String) -> UserMessageBroadcastEvent {
+        UserMessageBroadcastEvent {
+            id: self.next_message_id - 1,
+            timestamp: now_millis(),
+            room: self.metadata.name.clone(),
+            user_id,
+            content,
+            unrecorded: true,
         }
     }
 
-    // Get the history of messages in the room
-    pub fn get_history(&self) -> Vec<UserMessageBroadcastEvent> {
-        self.history.iter().cloned().collect()
+    /// Get the messages recorded after the given message id, ordered oldest to newest, at most `limit` of them
+    pub fn messages_after(
+        &self,
+        after: u64,
+        limit: usize,
+    ) -> anyhow::Result<Vec<UserMessageBroadcastEvent>> {
+        // message ids of a room are consecutive, so the page ending right after the limit starts after the given id
+        let before = after.saturating_add(limit as u64).saturating_add(1);
+        let mut messages = self
+            .history_store
+            .page(&self.metadata.name, Some(before), limit)?;
+        messages.retain(|message| message.id > after);
+
+        Ok(messages)
+    }
+
+    /// Get a page of the messages recorded in the room
+    ///
+    /// # Arguments
+    ///
+    /// - `before` - Only messages with an id lower than this cursor are returned, starts from the latest message if `None`
+    /// - `limit` - Maximum number of messages to return, capped at [MAX_HISTORY_PAGE_SIZE]
+    pub fn get_history(&self, before: Option<u64>, limit: usize) -> anyhow::Result<HistoryPage> {
+        let limit = limit.min(MAX_HISTORY_PAGE_SIZE);
+        // ask for one more message than the limit, to know whether there are older messages
+        let mut messages = self
+            .history_store
+            .page(&self.metadata.name, before, limit + 1)?;
+
+        let next_cursor = if messages.len() > limit {
+            messages.remove(0);
+            messages.first().map(|message| message.id)
+        } else {
+            None
+        };
+
+        Ok(HistoryPage {
+            messages,
+            next_cursor,
+        })
     }
 }
 
-#[test]
-fn test_limit() {
+/// Whether the user has a ban or a mute that has not ended yet, an ended one is removed
+fn is_restricted(restrictions: &mut HashMap<String, Option<u64>>, user_id: &str) -> bool {
+    match restrictions.get(user_id) {
+        Some(Some(expires_at)) if *expires_at <= now_millis() => {
+            restrictions.remove(user_id);
+            false
+        }
+        Some(_) => true,
+        None => false,
+    }
+}
+
+/// Current server time in milliseconds since the Unix epoch
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+fn test_room() -> ChatRoom {
+    use crate::room_manager::history::{InMemoryHistoryStore, DEFAULT_HISTORY_RETENTION};
+
     let metadata = ChatRoomMetadata {
         name: "test".into(),
         description: "desc".into(),
+        owner: None,
+        moderators: Vec::new(),
     };
 
-    let mut room = ChatRoom::new(metadata);
+    ChatRoom::new(
+        metadata,
+        Arc::new(InMemoryHistoryStore::new(DEFAULT_HISTORY_RETENTION)),
+        Vec::new(),
+        DEFAULT_ROOM_CHANNEL_CAPACITY,
+    )
+    .unwrap()
+}
 
-    for i in 0..12 {
-        let msg = event::UserMessageBroadcastEvent {
-            room: "test".into(),
-            user_id: "user".into(),
-            content: format!("msg{}", i),
-        };
-        room.record_message(&msg);
+#[test]
+fn test_page_size_limit() {
+    let mut room = test_room();
+
+    for i in 0..MAX_HISTORY_PAGE_SIZE + 2 {
+        room.record_message("user".into(), format!("msg{}", i))
+            .unwrap();
     }
 
-    assert_eq!(room.get_history().len(), 10);
+    // a single page can not exceed the page size limit
+    let page = room.get_history(None, usize::MAX).unwrap();
+    assert_eq!(page.messages.len(), MAX_HISTORY_PAGE_SIZE);
+    assert_eq!(page.next_cursor, Some(3));
+}
+
+#[test]
+fn test_history_pagination() {
+    let mut room = test_room();
+
+    for i in 0..25 {
+        room.record_message("user".into(), format!("msg{}", i))
+            .unwrap();
+    }
+
+    // the latest page, ids are 1 based so msg24 has the id 25
+    let page = room.get_history(None, 10).unwrap();
+    assert_eq!(page.messages.len(), 10);
+    assert_eq!(page.messages.first().unwrap().content, "msg15");
+    assert_eq!(page.messages.last().unwrap().content, "msg24");
+    assert_eq!(page.next_cursor, Some(16));
+
+    let page = room.get_history(page.next_cursor, 10).unwrap();
+    assert_eq!(page.messages.first().unwrap().content, "msg5");
+    assert_eq!(page.messages.last().unwrap().content, "msg14");
+    assert_eq!(page.next_cursor, Some(6));
+
+    // the last page is partial and has no further cursor
+    let page = room.get_history(page.next_cursor, 10).unwrap();
+    assert_eq!(page.messages.len(), 5);
+    assert_eq!(page.messages.first().unwrap().content, "msg0");
+    assert_eq!(page.next_cursor, None);
+}
+
+#[test]
+fn test_message_ids_are_monotonic() {
+    let mut room = test_room();
+
+    let first = room.record_message("user".into(), "first".into()).unwrap();
+    let second = room.record_message("user".into(), "second".into()).unwrap();
+
+    assert_eq!(first.room, "test");
+    assert!(second.id > first.id);
+    assert!(second.timestamp >= first.timestamp);
+}
+
+#[test]
+fn test_message_ids_continue_from_store() {
+    use crate::room_manager::history::InMemoryHistoryStore;
+
+    let history_store: Arc<dyn HistoryStore> = Arc::new(InMemoryHistoryStore::new(10));
+    let metadata = ChatRoomMetadata {
+        name: "test".into(),
+        description: "desc".into(),
+        owner: None,
+        moderators: Vec::new(),
+    };
+
+    let mut room = ChatRoom::new(
+        metadata.clone(),
+        history_store.clone(),
+        Vec::new(),
+        DEFAULT_ROOM_CHANNEL_CAPACITY,
+    )
+    .unwrap();
+    room.record_message("user".into(), "first".into()).unwrap();
+
+    // a room created on the same store, e.g. after a restart, does not reuse ids
+    let mut room = ChatRoom::new(
+        metadata,
+        history_store,
+        Vec::new(),
+        DEFAULT_ROOM_CHANNEL_CAPACITY,
+    )
+    .unwrap();
+    let message = room.record_message("user".into(), "second".into()).unwrap();
+    assert_eq!(message.id, 2);
+}
+
+#[test]
+fn test_resync_after_shadow_message() {
+    let mut room = test_room();
+
+    room.record_message("user".into(), "first".into()).unwrap();
+    room.record_message("user".into(), "second".into()).unwrap();
+    let shadow = room.shadow_message("user".into(), "dropped".into());
+    room.record_message("user".into(), "third".into()).unwrap();
+    room.record_message("user".into(), "fourth".into()).unwrap();
+
+    // the shadow copy takes no id of its own, so the resync finds every message it asks for
+    assert_eq!(shadow.id, 2);
+    assert!(shadow.unrecorded);
+    let messages = room.messages_after(1, 3).unwrap();
+    let contents = messages
+        .iter()
+        .map(|message| message.content.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(contents, vec!["second", "third", "fourth"]);
+}
+
+#[test]
+fn test_typing_is_throttled_and_expires() {
+    let mut room = test_room();
+    let mut receiver = room.broadcast_tx.subscribe();
+    let mut typing_events = || {
+        std::iter::from_fn(|| receiver.try_recv().ok())
+            .map(|event| match event {
+                Event::UserTyping(event) => (event.user_id, event.typing),
+                other => panic!("unexpected event: {:?}", other),
+            })
+            .collect::<Vec<_>>()
+    };
+    let start = Instant::now();
+
+    assert!(room.start_typing("alice", start));
+    assert!(!room.start_typing("alice", start + Duration::from_secs(1)));
+    assert_eq!(typing_events(), vec![("alice".into(), true)]);
+
+    // the typing is announced again after the throttle, and each start extends it
+    let restart = start + TYPING_THROTTLE;
+    assert!(!room.start_typing("alice", restart));
+    assert_eq!(typing_events(), vec![("alice".into(), true)]);
+    assert_eq!(
+        room.expire_typing("alice", start + TYPING_TIMEOUT),
+        Some(restart + TYPING_TIMEOUT)
+    );
+    assert_eq!(room.expire_typing("alice", restart + TYPING_TIMEOUT), None);
+    assert_eq!(typing_events(), vec![("alice".into(), false)]);
+    assert_eq!(room.expire_typing("alice", restart + TYPING_TIMEOUT), None);
+
+    // a message ends the typing without a stopped event, stopping twice is only announced once
+    assert!(room.start_typing("alice", start));
+    room.clear_typing("alice");
+    room.stop_typing("alice");
+    assert!(room.start_typing("bob", start));
+    room.stop_typing("bob");
+    room.stop_typing("bob");
+    assert_eq!(
+        typing_events(),
+        vec![
+            ("alice".into(), true),
+            ("bob".into(), true),
+            ("bob".into(), false)
+        ]
+    );
 }
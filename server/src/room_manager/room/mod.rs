@@ -1,6 +1,11 @@
 mod chat_room;
+mod room_event_receiver;
 mod user_registry;
 mod user_session_handle;
 
-pub use self::chat_room::{ChatRoom, ChatRoomMetadata};
+pub use self::chat_room::{
+    now_millis, ChatRoom, ChatRoomMetadata, HistoryPage, DEFAULT_HISTORY_PAGE_SIZE,
+    DEFAULT_ROOM_CHANNEL_CAPACITY,
+};
+pub use self::room_event_receiver::RoomEventReceiver;
 pub use self::user_session_handle::{SessionAndUserId, UserSessionHandle};
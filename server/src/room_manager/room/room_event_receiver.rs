@@ -0,0 +1,227 @@
+use std::sync::Arc;
+
+use comms::event::{Event, ModerationAction, RoomResyncEvent};
+use tokio::sync::{broadcast, broadcast::error::RecvError, Mutex};
+
+use super::{ChatRoom, SessionAndUserId};
+
+#[derive(Debug)]
+/// [RoomEventReceiver] receives the events of a room for a single session
+///
+/// If the session falls behind the room and the broadcast channel drops events for it,
+/// the missed messages are re-sent from the room history in a [Event::RoomResync] and receiving continues.
+/// The kick or the ban that removes the session from the room is the last event it receives, even if it was missed.
+pub struct RoomEventReceiver {
+    broadcast_rx: broadcast::Receiver<Event>,
+    chat_room: Arc<Mutex<ChatRoom>>,
+    room: String,
+    session_and_user_id: SessionAndUserId,
+    /// The id of the latest message the session has received, messages up to it are not sent again
+    last_message_id: u64,
+    /// Whether the session has been removed from the room by a kick or a ban
+    removed: bool,
+}
+
+impl RoomEventReceiver {
+    pub(super) fn new(
+        broadcast_rx: broadcast::Receiver<Event>,
+        chat_room: Arc<Mutex<ChatRoom>>,
+        room: String,
+        session_and_user_id: SessionAndUserId,
+        last_message_id: u64,
+    ) -> Self {
+        RoomEventReceiver {
+            broadcast_rx,
+            chat_room,
+            room,
+            session_and_user_id,
+            last_message_id,
+            removed: false,
+        }
+    }
+
+    /// Receive the next event of the room, returns `None` once the room is gone or the session has been removed from it
+    pub async fn recv(&mut self) -> Option<Event> {
+        if self.removed {
+            return None;
+        }
+
+        loop {
+            match self.broadcast_rx.recv().await {
+                // the message has already been re-sent after the session has lagged behind
+                Ok(Event::UserMessage(message)) if message.id <= self.last_message_id => continue,
+                Ok(event) => {
+                    match &event {
+                        Event::UserMessage(message) => self.last_message_id = message.id,
+                        Event::UserModerated(moderated)
+                            if moderated.user_id == self.session_and_user_id.user_id
+                                && matches!(
+                                    moderated.action,
+                                    ModerationAction::Kicked | ModerationAction::Banned
+                                ) =>
+                        {
+                            self.removed = true;
+                        }
+                        _ => {}
+                    }
+
+                    return Some(event);
+                }
+                Err(RecvError::Lagged(skipped)) => return Some(self.resync(skipped).await),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Build the event that tells the session about the events it has missed, along with the missed messages
+    async fn resync(&mut self, skipped: u64) -> Event {
+        let room = self.chat_room.lock().await;
+
+        // the session is told it has been removed from the room instead, if the kick or the ban was missed
+        if let Some(removal) = room.removal_of(&self.session_and_user_id.session_id) {
+            self.removed = true;
+
+            return Event::UserModerated(removal);
+        }
+
+        // every missed message is a missed event, so the missed messages are within the next `skipped` ids
+        let messages = room
+            .messages_after(self.last_message_id, skipped as usize)
+            .unwrap_or_else(|err| {
+                println!("could not read history of room '{}': {:#}", self.room, err);
+
+                Vec::new()
+            });
+
+        if let Some(message) = messages.last() {
+            self.last_message_id = message.id;
+        }
+
+        Event::RoomResync(RoomResyncEvent {
+            room: self.room.clone(),
+            skipped,
+            messages,
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_resync_after_lag() {
+    use super::{ChatRoomMetadata, SessionAndUserId};
+    use crate::room_manager::history::InMemoryHistoryStore;
+
+    let metadata = ChatRoomMetadata {
+        name: "test".into(),
+        description: "desc".into(),
+        owner: None,
+        moderators: Vec::new(),
+    };
+    // a tiny channel, so the receiver lags behind after a few messages
+    let chat_room = ChatRoom::new(
+        metadata,
+        Arc::new(InMemoryHistoryStore::new(100)),
+        Vec::new(),
+        2,
+    )
+    .unwrap();
+    let chat_room = Arc::new(Mutex::new(chat_room));
+    let session_and_user_id = SessionAndUserId {
+        session_id: "session".into(),
+        user_id: "user".into(),
+    };
+
+    let (mut receiver, handle) = chat_room
+        .lock()
+        .await
+        .join(&session_and_user_id, "user", chat_room.clone())
+        .unwrap();
+
+    for i in 1..=5 {
+        handle
+            .send_message(format!("message {}", i), Ok)
+            .await
+            .unwrap();
+    }
+
+    // the participation event and the first three messages are dropped by the channel
+    match receiver.recv().await {
+        Some(Event::RoomResync(event)) => {
+            assert_eq!(event.skipped, 4);
+            let ids = event.messages.iter().map(|m| m.id).collect::<Vec<_>>();
+            assert_eq!(ids, vec![1, 2, 3, 4]);
+        }
+        other => panic!("expected a resync event, got {:?}", other),
+    }
+
+    // the messages left in the channel are received once, after the re-sent ones
+    match receiver.recv().await {
+        Some(Event::UserMessage(message)) => assert_eq!(message.id, 5),
+        other => panic!("expected a message, got {:?}", other),
+    }
+
+    handle.send_message("message 6".into(), Ok).await.unwrap();
+    match receiver.recv().await {
+        Some(Event::UserMessage(message)) => assert_eq!(message.id, 6),
+        other => panic!("expected a message, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_missed_kick_ends_receiving() {
+    use super::{ChatRoomMetadata, SessionAndUserId};
+    use crate::room_manager::history::InMemoryHistoryStore;
+
+    let metadata = ChatRoomMetadata {
+        name: "test".into(),
+        description: "desc".into(),
+        owner: Some("owner".into()),
+        moderators: Vec::new(),
+    };
+    // a tiny channel, so the kick is dropped for the user who lags behind
+    let chat_room = ChatRoom::new(
+        metadata,
+        Arc::new(InMemoryHistoryStore::new(100)),
+        Vec::new(),
+        2,
+    )
+    .unwrap();
+    let chat_room = Arc::new(Mutex::new(chat_room));
+    let session = |user_id: &str| SessionAndUserId {
+        session_id: format!("{}-session", user_id),
+        user_id: user_id.into(),
+    };
+
+    let (_owner_receiver, owner) = chat_room
+        .lock()
+        .await
+        .join(&session("owner"), "owner", chat_room.clone())
+        .unwrap();
+    let (mut receiver, handle) = chat_room
+        .lock()
+        .await
+        .join(&session("user"), "user", chat_room.clone())
+        .unwrap();
+
+    owner
+        .moderate("user", ModerationAction::Kicked, None)
+        .await
+        .unwrap();
+    for i in 1..=3 {
+        owner
+            .send_message(format!("message {}", i), Ok)
+            .await
+            .unwrap();
+    }
+
+    // the kicked session can not act in the room anymore, even before it has handled the kick
+    assert!(handle.send_message("still here".into(), Ok).await.is_err());
+
+    match receiver.recv().await {
+        Some(Event::UserModerated(event)) => {
+            assert_eq!(event.user_id, "user");
+            assert_eq!(event.action, ModerationAction::Kicked);
+        }
+        other => panic!("expected the kick, got {:?}", other),
+    }
+    assert!(receiver.recv().await.is_none());
+}
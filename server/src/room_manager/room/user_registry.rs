@@ -1,11 +1,14 @@
 use std::collections::{HashMap, HashSet};
 
-use super::user_session_handle::UserSessionHandle;
+use comms::event::UserDetail;
+
+use super::user_session_handle::{SessionAndUserId, UserSessionHandle};
 
 #[derive(Debug)]
 pub struct UserRegistry {
     user_id_to_sessions: HashMap<String, HashSet<String>>,
-    user_ids: HashSet<String>,
+    /// Display names of the users in the room, by user id
+    user_id_to_display_name: HashMap<String, String>,
 }
 
 /// [UserRegistry] is a smart container for keeping track of which unique list of users are in a room
@@ -15,26 +18,25 @@ impl UserRegistry {
     pub fn new() -> Self {
         UserRegistry {
             user_id_to_sessions: HashMap::new(),
-            user_ids: HashSet::new(),
+            user_id_to_display_name: HashMap::new(),
         }
     }
 
     /// Add a user to the room, returns true if the user is a new user
-    pub fn insert(&mut self, user_session_handle: &UserSessionHandle) -> bool {
+    /// The display name of a user is only recorded when their first session is added
+    pub fn insert(&mut self, user_session_handle: &UserSessionHandle, display_name: &str) -> bool {
         let user_id = String::from(user_session_handle.user_id());
         let session_id = String::from(user_session_handle.session_id());
 
-        let sessions = self
-            .user_id_to_sessions
-            .entry(user_id.clone())
-            .or_insert_with(HashSet::new);
+        let sessions = self.user_id_to_sessions.entry(user_id.clone()).or_default();
 
         sessions.insert(session_id);
 
         let is_new_user = sessions.len() == 1;
 
         if is_new_user {
-            self.user_ids.insert(user_id);
+            self.user_id_to_display_name
+                .insert(user_id, String::from(display_name));
         }
 
         is_new_user
@@ -53,7 +55,7 @@ impl UserRegistry {
 
             if sessions.is_empty() {
                 self.user_id_to_sessions.remove(&user_id);
-                self.user_ids.remove(&user_id);
+                self.user_id_to_display_name.remove(&user_id);
 
                 true
             } else {
@@ -64,7 +66,53 @@ impl UserRegistry {
         }
     }
 
-    pub fn get_unique_user_ids(&self) -> Vec<String> {
-        self.user_ids.iter().cloned().collect()
+    /// Removes every session of the user from the participant list, returns the ids of the removed sessions
+    pub fn remove_user(&mut self, user_id: &str) -> Vec<String> {
+        self.user_id_to_display_name.remove(user_id);
+
+        self.user_id_to_sessions
+            .remove(user_id)
+            .map(|sessions| sessions.into_iter().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns true if the user has at least one session in the room
+    pub fn contains(&self, user_id: &str) -> bool {
+        self.user_id_to_sessions.contains_key(user_id)
+    }
+
+    /// Returns true if the given session of the user is in the room
+    pub fn contains_session(&self, session_and_user_id: &SessionAndUserId) -> bool {
+        self.user_id_to_sessions
+            .get(&session_and_user_id.user_id)
+            .is_some_and(|sessions| sessions.contains(&session_and_user_id.session_id))
+    }
+
+    pub fn get_unique_users(&self) -> Vec<UserDetail> {
+        self.user_id_to_display_name
+            .iter()
+            .map(|(user_id, display_name)| UserDetail {
+                user_id: user_id.clone(),
+                display_name: display_name.clone(),
+            })
+            .collect()
+    }
+
+    pub fn display_name(&self, user_id: &str) -> Option<&str> {
+        self.user_id_to_display_name
+            .get(user_id)
+            .map(String::as_str)
+    }
+
+    /// Updates the display name of a user, returns true if the user is in the room
+    pub fn set_display_name(&mut self, user_id: &str, display_name: &str) -> bool {
+        match self.user_id_to_display_name.get_mut(user_id) {
+            Some(current) => {
+                *current = String::from(display_name);
+
+                true
+            }
+            None => false,
+        }
     }
 }
@@ -1,9 +1,12 @@
 use anyhow::Context;
-use comms::event;
-use std::sync::Arc;
+use comms::event::{self, CommandErrorCode, ModerationAction, UserMessageBroadcastEvent};
+use std::{sync::Arc, time::Duration};
 use tokio::sync::{broadcast, Mutex};
+use tokio::time::{self, Instant};
 
-use super::ChatRoom;
+use super::{chat_room::TYPING_TIMEOUT, ChatRoom, HistoryPage};
+use crate::error::CommandError;
+use crate::room_manager::message_hook::{run_hooks, HookOutcome};
 
 #[derive(Debug, Clone)]
 
@@ -18,8 +21,6 @@ pub struct SessionAndUserId {
 ///
 /// It is created when a user joins a room and is handed out to the user.
 pub struct UserSessionHandle {
-    /// The name of the room which is associated with this handle
-    room: String,
     /// The channel to use for sending events to the all users of the room
     broadcast_tx: broadcast::Sender<event::Event>,
     /// The session and user id associated with this handle
@@ -30,23 +31,17 @@ pub struct UserSessionHandle {
 
 impl UserSessionHandle {
     pub(super) fn new(
-        room: String,
         broadcast_tx: broadcast::Sender<event::Event>,
         session_and_user_id: SessionAndUserId,
         chat_room: Arc<Mutex<ChatRoom>>,
     ) -> Self {
         UserSessionHandle {
-            room,
             broadcast_tx,
             session_and_user_id,
             chat_room,
         }
     }
 
-    pub fn room(&self) -> &str {
-        &self.room
-    }
-
     pub fn session_id(&self) -> &str {
         &self.session_and_user_id.session_id
     }
@@ -55,23 +50,134 @@ impl UserSessionHandle {
         &self.session_and_user_id.user_id
     }
 
-    // Send a message to the room
-    pub async fn send_message(&self, content: String) -> anyhow::Result<()> {
-        let message_event = event::UserMessageBroadcastEvent {
-            room: self.room.clone(),
-            user_id: self.session_and_user_id.user_id.clone(),
-            content,
+    /// Send a message to the room, refused if the user is banned from or muted in the room
+    ///
+    /// The message hooks of the room run on the message before it is recorded and broadcast.
+    /// If a hook has dropped the message, it is returned to be shown to the sender alone.
+    /// The content the hooks let through is checked with `validate` again, as a hook may have rewritten it.
+    pub async fn send_message(
+        &self,
+        content: String,
+        validate: impl FnOnce(String) -> Result<String, CommandError>,
+    ) -> Result<Option<UserMessageBroadcastEvent>, CommandError> {
+        // the hooks run without holding the room lock, so they do not hold up the other participants
+        let (room_name, hooks) = {
+            let room = self.chat_room.lock().await;
+            (String::from(room.name()), room.message_hooks().to_vec())
+        };
+        // a hook may rewrite the content into one the sender could not have sent
+        let outcome = match run_hooks(&hooks, &room_name, self.user_id(), content) {
+            HookOutcome::Send(content) => {
+                HookOutcome::Send(validate(content).map_err(|err| err.with_room(&room_name))?)
+            }
+            HookOutcome::ShadowDrop(content) => {
+                HookOutcome::ShadowDrop(validate(content).map_err(|err| err.with_room(&room_name))?)
+            }
+            outcome => outcome,
+        };
+
+        let mut room = self.chat_room.lock().await;
+        room.check_can_send(&self.session_and_user_id)?;
+
+        let content = match outcome {
+            HookOutcome::Send(content) => content,
+            HookOutcome::Reject(reason) => {
+                return Err(CommandError::in_room(
+                    CommandErrorCode::MessageRejected,
+                    room.name(),
+                    reason,
+                ))
+            }
+            HookOutcome::ShadowDrop(content) => {
+                let message =
+                    room.shadow_message(self.session_and_user_id.user_id.clone(), content);
+
+                return Ok(Some(message));
+            }
         };
 
+        let result = room
+            .record_message(self.session_and_user_id.user_id.clone(), content)
+            .and_then(|message_event| {
+                // the participants end the typing of the user when they receive the message
+                room.clear_typing(&message_event.user_id);
+
+                // broadcast while still holding the room lock, so messages are delivered in the order of their ids
+                self.broadcast_tx
+                    .send(event::Event::UserMessage(message_event))
+                    .context("could not write to the broadcast channel")?;
+
+                Ok(None)
+            });
+
+        result.map_err(|err| {
+            println!(
+                "could not send message to room '{}': {:#}",
+                room.name(),
+                err
+            );
+
+            CommandError::in_room(
+                CommandErrorCode::Internal,
+                room.name(),
+                "could not send the message",
+            )
+        })
+    }
+
+    /// Tell the participants of the room that the user has started or stopped typing, see [ChatRoom::start_typing]
+    /// The typing of the users who can not send messages to the room is ignored
+    pub async fn set_typing(&self, typing: bool) {
+        let mut room = self.chat_room.lock().await;
+        if !typing {
+            room.stop_typing(self.user_id());
+            return;
+        }
+
+        if room.check_can_send(&self.session_and_user_id).is_err()
+            || !room.start_typing(self.user_id(), Instant::now().into_std())
         {
-            let mut room = self.chat_room.lock().await;
-            room.record_message(&message_event);
+            return;
         }
 
-        self.broadcast_tx
-            .send(event::Event::UserMessage(message_event))
-            .context("could not write to the broadcast channel")?;
+        // the typing ends once it has not been started again for a while, or with the room
+        let chat_room = Arc::downgrade(&self.chat_room);
+        let user_id = self.session_and_user_id.user_id.clone();
+        tokio::spawn(async move {
+            let mut expires_at = Instant::now() + TYPING_TIMEOUT;
+            loop {
+                time::sleep_until(expires_at).await;
+
+                let Some(chat_room) = chat_room.upgrade() else {
+                    break;
+                };
+                let expires_later = chat_room
+                    .lock()
+                    .await
+                    .expire_typing(&user_id, Instant::now().into_std());
+                match expires_later {
+                    Some(expires_later) => expires_at = Instant::from_std(expires_later),
+                    None => break,
+                }
+            }
+        });
+    }
+
+    /// Moderate a user of the room as the user of this handle, see [ChatRoom::moderate]
+    pub async fn moderate(
+        &self,
+        user_id: &str,
+        action: ModerationAction,
+        duration: Option<Duration>,
+    ) -> Result<(), CommandError> {
+        self.chat_room
+            .lock()
+            .await
+            .moderate(self.user_id(), user_id, action, duration)
+    }
 
-        Ok(())
+    /// Get a page of the recorded messages of the room, see [ChatRoom::get_history]
+    pub async fn history(&self, before: Option<u64>, limit: usize) -> anyhow::Result<HistoryPage> {
+        self.chat_room.lock().await.get_history(before, limit)
     }
 }
@@ -1,66 +1,232 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
 
-use comms::event::Event;
+use comms::event::{self, CommandErrorCode, Event, RoomDetail, UserDetail};
 use tokio::sync::{broadcast, Mutex};
 
-use super::room::{ChatRoom, ChatRoomMetadata, SessionAndUserId, UserSessionHandle};
+use crate::error::CommandError;
 
-pub type RoomJoinResult = (broadcast::Receiver<Event>, UserSessionHandle, Vec<String>);
+use super::history::HistoryStore;
+use super::message_hook::MessageHooks;
+use super::room::{
+    ChatRoom, ChatRoomMetadata, RoomEventReceiver, SessionAndUserId, UserSessionHandle,
+};
 
-#[derive(Debug, Clone)]
+pub type RoomJoinResult = (RoomEventReceiver, UserSessionHandle, Vec<UserDetail>);
+
+/// Number of events buffered for each session for the events sent to all sessions, unless configured otherwise
+pub const DEFAULT_BROADCAST_CHANNEL_CAPACITY: usize = 100;
+const MAX_ROOM_NAME_LENGTH: usize = 32;
+const MAX_ROOM_DESCRIPTION_LENGTH: usize = 200;
+
+#[derive(Debug, Default)]
+struct ChatRooms {
+    by_name: HashMap<String, Arc<Mutex<ChatRoom>>>,
+    /// Metadata of the rooms in the order they were created
+    /// The owner of a room is the only one who can delete it, rooms without an owner can not be deleted
+    metadata: Vec<ChatRoomMetadata>,
+}
+
+#[derive(Debug)]
 pub struct RoomManager {
-    chat_rooms: HashMap<String, Arc<Mutex<ChatRoom>>>,
-    chat_room_metadata: Vec<ChatRoomMetadata>,
+    chat_rooms: RwLock<ChatRooms>,
+    history_store: Arc<dyn HistoryStore>,
+    /// Hooks of the rooms created while the server is running
+    message_hooks: MessageHooks,
+    /// Capacity of the broadcast channel of the rooms created while the server is running
+    room_channel_capacity: usize,
+    /// The channel to use for sending events to all logged in sessions, e.g. room creation and deletion
+    broadcast_tx: broadcast::Sender<Event>,
 }
 
 impl RoomManager {
-    pub(super) fn new(chat_rooms: Vec<(ChatRoomMetadata, Arc<Mutex<ChatRoom>>)>) -> RoomManager {
-        let chat_room_metadata = chat_rooms
-            .iter()
-            .map(|(metadata, _)| metadata.clone())
-            .collect();
-
-        RoomManager {
-            chat_room_metadata,
-            chat_rooms: chat_rooms
+    pub(super) fn new(
+        chat_rooms: Vec<(ChatRoomMetadata, Arc<Mutex<ChatRoom>>)>,
+        history_store: Arc<dyn HistoryStore>,
+        message_hooks: MessageHooks,
+        room_channel_capacity: usize,
+        broadcast_channel_capacity: usize,
+    ) -> RoomManager {
+        let (broadcast_tx, _) = broadcast::channel(broadcast_channel_capacity);
+        let chat_rooms = ChatRooms {
+            metadata: chat_rooms
+                .iter()
+                .map(|(metadata, _)| metadata.clone())
+                .collect(),
+            by_name: chat_rooms
                 .into_iter()
                 .map(|(metadata, chat_room)| (metadata.name.clone(), chat_room))
                 .collect(),
+        };
+
+        RoomManager {
+            chat_rooms: RwLock::new(chat_rooms),
+            history_store,
+            message_hooks,
+            room_channel_capacity,
+            broadcast_tx,
         }
     }
 
-    pub fn chat_room_metadata(&self) -> &Vec<ChatRoomMetadata> {
-        &self.chat_room_metadata
+    pub fn chat_room_metadata(&self) -> Vec<ChatRoomMetadata> {
+        self.chat_rooms.read().unwrap().metadata.clone()
+    }
+
+    /// Subscribe to the events which are sent to all logged in sessions
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.broadcast_tx.subscribe()
+    }
+
+    fn get_room(&self, room_name: &str) -> Option<Arc<Mutex<ChatRoom>>> {
+        self.chat_rooms
+            .read()
+            .unwrap()
+            .by_name
+            .get(room_name)
+            .cloned()
     }
 
-    /// Joins to a room given a user session
+    /// Joins to a room given a user session and the name to display for the user
     pub async fn join_room(
         &self,
         room_name: &str,
         session_and_user_id: &SessionAndUserId,
-    ) -> anyhow::Result<RoomJoinResult> {
+        display_name: &str,
+    ) -> Result<RoomJoinResult, CommandError> {
         let room_arc = self
-            .chat_rooms
-            .get(room_name)
-            .ok_or_else(|| anyhow::anyhow!("room '{}' not found", room_name))?
-            .clone();
+            .get_room(room_name)
+            .ok_or_else(|| CommandError::room_not_found(room_name))?;
 
         let mut room = room_arc.lock().await;
-        let (broadcast_rx, user_session_handle) = room.join(session_and_user_id, room_arc.clone());
+        let (receiver, user_session_handle) =
+            room.join(session_and_user_id, display_name, room_arc.clone())?;
 
-        Ok((
-            broadcast_rx,
-            user_session_handle,
-            room.get_unique_user_ids().clone(),
-        ))
+        Ok((receiver, user_session_handle, room.get_unique_users()))
     }
 
-    pub async fn drop_user_session_handle(&self, handle: UserSessionHandle) -> anyhow::Result<()> {
-        let room = self
+    /// Creates a new room owned by the given user and announces it to all logged in sessions
+    pub fn create_room(&self, metadata: ChatRoomMetadata, owner: &str) -> Result<(), CommandError> {
+        validate_room_metadata(&metadata)?;
+        let metadata = ChatRoomMetadata {
+            owner: Some(String::from(owner)),
+            ..metadata
+        };
+
+        let mut chat_rooms = self.chat_rooms.write().unwrap();
+        if chat_rooms.by_name.contains_key(&metadata.name) {
+            return Err(CommandError::in_room(
+                CommandErrorCode::RoomAlreadyExists,
+                &metadata.name,
+                format!("room '{}' already exists", metadata.name),
+            ));
+        }
+
+        let chat_room = ChatRoom::new(
+            metadata.clone(),
+            self.history_store.clone(),
+            self.message_hooks.for_room(&metadata.name),
+            self.room_channel_capacity,
+        )
+        .map_err(|err| {
+            println!("could not create room '{}': {:#}", metadata.name, err);
+
+            CommandError::in_room(
+                CommandErrorCode::Internal,
+                &metadata.name,
+                "could not create the room",
+            )
+        })?;
+
+        chat_rooms
+            .by_name
+            .insert(metadata.name.clone(), Arc::new(Mutex::new(chat_room)));
+        chat_rooms.metadata.push(metadata.clone());
+
+        let _ = self
+            .broadcast_tx
+            .send(Event::RoomCreated(event::RoomCreatedBroadcastEvent {
+                room: RoomDetail {
+                    name: metadata.name,
+                    description: metadata.description,
+                },
+            }));
+
+        Ok(())
+    }
+
+    /// Deletes a room owned by the given user and announces it to all logged in sessions
+    ///
+    /// The room can not be joined anymore once it is deleted. Participants of the room are
+    /// removed from it by their sessions when they receive the announcement.
+    pub async fn delete_room(&self, room_name: &str, user_id: &str) -> Result<(), CommandError> {
+        let room = {
+            let mut chat_rooms = self.chat_rooms.write().unwrap();
+            let Some(metadata) = chat_rooms
+                .metadata
+                .iter()
+                .find(|metadata| metadata.name == room_name)
+            else {
+                return Err(CommandError::room_not_found(room_name));
+            };
+
+            if metadata.owner.as_deref() != Some(user_id) {
+                return Err(CommandError::in_room(
+                    CommandErrorCode::PermissionDenied,
+                    room_name,
+                    format!("only the owner of room '{}' can delete it", room_name),
+                ));
+            }
+
+            chat_rooms
+                .metadata
+                .retain(|metadata| metadata.name != room_name);
+            chat_rooms.by_name.remove(room_name)
+        };
+
+        // the sessions still holding the room could record a message after its history is removed,
+        // so the room is closed under its lock first
+        if let Some(room) = room {
+            room.lock().await.close();
+        }
+
+        if let Err(err) = self.history_store.remove_room(room_name) {
+            println!(
+                "could not remove history of room '{}': {:#}",
+                room_name, err
+            );
+        }
+
+        let _ = self
+            .broadcast_tx
+            .send(Event::RoomDeleted(event::RoomDeletedBroadcastEvent {
+                room: String::from(room_name),
+            }));
+
+        Ok(())
+    }
+
+    /// Broadcasts the new nickname of a user to every room the user is in
+    pub async fn change_nickname(&self, user_id: &str, nickname: &str) {
+        let rooms = self
             .chat_rooms
-            .get(handle.room())
-            .ok_or_else(|| anyhow::anyhow!("room '{}' not found", handle.room()))?;
+            .read()
+            .unwrap()
+            .by_name
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
 
+        for room in rooms {
+            room.lock().await.change_nickname(user_id, nickname);
+        }
+    }
+
+    /// Removes the user session from the room of the handle
+    /// Works for deleted rooms as well, since the handle refers to the room directly
+    pub async fn drop_user_session_handle(&self, handle: UserSessionHandle) -> anyhow::Result<()> {
+        let room = handle.chat_room.clone();
         let mut room = room.lock().await;
 
         room.leave(handle);
@@ -68,3 +234,374 @@ impl RoomManager {
         Ok(())
     }
 }
+
+fn validate_room_metadata(metadata: &ChatRoomMetadata) -> Result<(), CommandError> {
+    let is_valid_name = !metadata.name.is_empty()
+        && metadata.name.len() <= MAX_ROOM_NAME_LENGTH
+        && metadata
+            .name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+    if !is_valid_name {
+        return Err(CommandError::new(
+            CommandErrorCode::InvalidCommand,
+            format!(
+                "room name must be 1 to {} characters of lowercase letters, digits, '_' or '-'",
+                MAX_ROOM_NAME_LENGTH
+            ),
+        ));
+    }
+
+    if metadata.description.chars().count() > MAX_ROOM_DESCRIPTION_LENGTH {
+        return Err(CommandError::new(
+            CommandErrorCode::InvalidCommand,
+            format!(
+                "room description must be at most {} characters",
+                MAX_ROOM_DESCRIPTION_LENGTH
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn test_room_manager() -> RoomManager {
+    super::RoomManagerBuilder::new()
+        .create_room(ChatRoomMetadata {
+            name: "general".into(),
+            description: "desc".into(),
+            owner: Some("alice".into()),
+            moderators: vec!["bob".into()],
+        })
+        .build()
+        .unwrap()
+}
+
+#[cfg(test)]
+fn test_metadata(name: &str) -> ChatRoomMetadata {
+    ChatRoomMetadata {
+        name: name.into(),
+        description: "desc".into(),
+        owner: None,
+        moderators: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+fn session_of(user_id: &str) -> SessionAndUserId {
+    SessionAndUserId {
+        session_id: format!("{}-session", user_id),
+        user_id: user_id.into(),
+    }
+}
+
+#[cfg(test)]
+fn error_code(error: CommandError) -> CommandErrorCode {
+    match Event::from(error) {
+        Event::CommandError(event) => event.code,
+        other => panic!("expected a command error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_moderation_is_restricted_by_role() {
+    use comms::event::ModerationAction;
+
+    let room_manager = test_room_manager();
+    let (_, bob, _) = room_manager
+        .join_room("general", &session_of("bob"), "bob")
+        .await
+        .unwrap();
+    let (_, carol, _) = room_manager
+        .join_room("general", &session_of("carol"), "carol")
+        .await
+        .unwrap();
+
+    // members can not moderate, moderators can not moderate the owner, nobody can moderate themselves
+    let err = carol
+        .moderate("bob", ModerationAction::Muted, None)
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), CommandErrorCode::PermissionDenied);
+    let err = bob
+        .moderate("alice", ModerationAction::Banned, None)
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), CommandErrorCode::PermissionDenied);
+    let err = bob
+        .moderate("bob", ModerationAction::Kicked, None)
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), CommandErrorCode::PermissionDenied);
+
+    // users who are not in the room can not be kicked, but they can be banned
+    let err = bob
+        .moderate("dave", ModerationAction::Kicked, None)
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), CommandErrorCode::UserNotFound);
+    bob.moderate("dave", ModerationAction::Banned, None)
+        .await
+        .unwrap();
+    let err = room_manager
+        .join_room("general", &session_of("dave"), "dave")
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), CommandErrorCode::Banned);
+
+    bob.moderate("dave", ModerationAction::Unbanned, None)
+        .await
+        .unwrap();
+    assert!(room_manager
+        .join_room("general", &session_of("dave"), "dave")
+        .await
+        .is_ok());
+    let err = bob
+        .moderate("dave", ModerationAction::Unbanned, None)
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), CommandErrorCode::InvalidCommand);
+}
+
+#[tokio::test]
+async fn test_muted_users_can_not_send_messages() {
+    use comms::event::ModerationAction;
+    use std::time::Duration;
+
+    let room_manager = test_room_manager();
+    let (mut bob_receiver, bob, _) = room_manager
+        .join_room("general", &session_of("bob"), "bob")
+        .await
+        .unwrap();
+    let (_, carol, _) = room_manager
+        .join_room("general", &session_of("carol"), "carol")
+        .await
+        .unwrap();
+
+    bob.moderate("carol", ModerationAction::Muted, None)
+        .await
+        .unwrap();
+    let err = carol.send_message("hello".into(), Ok).await.unwrap_err();
+    assert_eq!(error_code(err), CommandErrorCode::Muted);
+
+    // the participants are told about the mute, after carol has joined
+    loop {
+        match bob_receiver.recv().await.unwrap() {
+            Event::UserModerated(event) => {
+                assert_eq!(event.user_id, "carol");
+                assert_eq!(event.moderator_id, "bob");
+                assert_eq!(event.action, ModerationAction::Muted);
+                assert_eq!(event.expires_at, None);
+                break;
+            }
+            _ => continue,
+        }
+    }
+
+    bob.moderate("carol", ModerationAction::Unmuted, None)
+        .await
+        .unwrap();
+    carol.send_message("hello".into(), Ok).await.unwrap();
+
+    // a mute with a duration ends on its own
+    bob.moderate("carol", ModerationAction::Muted, Some(Duration::ZERO))
+        .await
+        .unwrap();
+    carol.send_message("hello again".into(), Ok).await.unwrap();
+
+    // a kick with a duration keeps the user out of the room until it has passed
+    bob.moderate(
+        "carol",
+        ModerationAction::Kicked,
+        Some(Duration::from_secs(60)),
+    )
+    .await
+    .unwrap();
+    room_manager.drop_user_session_handle(carol).await.unwrap();
+    let err = room_manager
+        .join_room("general", &session_of("carol"), "carol")
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), CommandErrorCode::Banned);
+}
+
+#[tokio::test]
+async fn test_message_hooks() {
+    use super::{BlocklistAction, MaxLinksHook, WordBlocklistHook};
+
+    let room_manager = super::RoomManagerBuilder::new()
+        .create_room(test_metadata("general"))
+        .message_hook(Arc::new(MaxLinksHook::new(0)))
+        .room_message_hook(
+            "general",
+            Arc::new(WordBlocklistHook::new(["heck"], BlocklistAction::Drop)),
+        )
+        .build()
+        .unwrap();
+    let (mut receiver, alice, _) = room_manager
+        .join_room("general", &session_of("alice"), "alice")
+        .await
+        .unwrap();
+
+    let err = alice
+        .send_message("see https://example.com".into(), Ok)
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), CommandErrorCode::MessageRejected);
+
+    // the dropped message is handed back for the sender alone, without taking a message id
+    let dropped = alice
+        .send_message("heck".into(), Ok)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(dropped.id, 0);
+    assert!(dropped.unrecorded);
+    assert!(alice
+        .send_message("hello".into(), Ok)
+        .await
+        .unwrap()
+        .is_none());
+    loop {
+        match receiver.recv().await.unwrap() {
+            Event::UserMessage(message) => {
+                assert_eq!(message.id, 1);
+                assert_eq!(message.content, "hello");
+                break;
+            }
+            _ => continue,
+        }
+    }
+
+    // the hooks for every room also run in the rooms created at runtime, unlike the ones of a single room
+    room_manager
+        .create_room(test_metadata("random"), "alice")
+        .unwrap();
+    let (_receiver, alice, _) = room_manager
+        .join_room("random", &session_of("alice"), "alice")
+        .await
+        .unwrap();
+    assert!(alice
+        .send_message("heck".into(), Ok)
+        .await
+        .unwrap()
+        .is_none());
+    let err = alice
+        .send_message("www.example.com".into(), Ok)
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), CommandErrorCode::MessageRejected);
+}
+
+#[tokio::test]
+async fn test_rewritten_messages_are_validated() {
+    use super::{BlocklistAction, WordBlocklistHook};
+
+    let room_manager = super::RoomManagerBuilder::new()
+        .create_room(test_metadata("general"))
+        .message_hook(Arc::new(WordBlocklistHook::new(
+            ["heck"],
+            BlocklistAction::Mask,
+        )))
+        .build()
+        .unwrap();
+    let (_receiver, alice, _) = room_manager
+        .join_room("general", &session_of("alice"), "alice")
+        .await
+        .unwrap();
+    let validate = |content: String| {
+        if content.contains('*') {
+            Err(CommandError::new(
+                CommandErrorCode::InvalidCharacters,
+                "message can not contain '*'",
+            ))
+        } else {
+            Ok(content)
+        }
+    };
+
+    // the content is valid as sent, but not after the hook has masked it
+    assert!(alice
+        .send_message("hello".into(), validate)
+        .await
+        .unwrap()
+        .is_none());
+    let err = alice
+        .send_message("oh heck".into(), validate)
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), CommandErrorCode::InvalidCharacters);
+}
+
+#[tokio::test]
+async fn test_create_and_delete_room() {
+    let room_manager = test_room_manager();
+    let mut broadcast_rx = room_manager.subscribe();
+    let session_and_user_id = SessionAndUserId {
+        session_id: "session".into(),
+        user_id: "alice".into(),
+    };
+
+    room_manager
+        .create_room(test_metadata("new-room"), "alice")
+        .unwrap();
+    assert!(matches!(
+        broadcast_rx.recv().await.unwrap(),
+        Event::RoomCreated(event) if event.room.name == "new-room"
+    ));
+    assert_eq!(room_manager.chat_room_metadata().len(), 2);
+
+    let (_, handle, _) = room_manager
+        .join_room("new-room", &session_and_user_id, "alice")
+        .await
+        .unwrap();
+
+    room_manager.delete_room("new-room", "alice").await.unwrap();
+    assert!(matches!(
+        broadcast_rx.recv().await.unwrap(),
+        Event::RoomDeleted(event) if event.room == "new-room"
+    ));
+    assert_eq!(room_manager.chat_room_metadata().len(), 1);
+    assert!(room_manager
+        .join_room("new-room", &session_and_user_id, "alice")
+        .await
+        .is_err());
+
+    // the room is closed, nothing is recorded in the history of a deleted room
+    let err = handle.send_message("hello".into(), Ok).await.unwrap_err();
+    assert_eq!(error_code(err), CommandErrorCode::RoomNotFound);
+
+    // members of the deleted room can still leave it
+    room_manager.drop_user_session_handle(handle).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_room_creation_and_deletion_is_restricted() {
+    let room_manager = test_room_manager();
+
+    assert!(room_manager
+        .create_room(test_metadata("general"), "alice")
+        .is_err());
+    assert!(room_manager
+        .create_room(test_metadata("Not A Slug"), "alice")
+        .is_err());
+
+    room_manager
+        .create_room(test_metadata("new-room"), "alice")
+        .unwrap();
+
+    // rooms can only be deleted by their owner, whether they were created at runtime or at startup
+    assert!(room_manager.delete_room("new-room", "bob").await.is_err());
+    assert!(room_manager.delete_room("general", "bob").await.is_err());
+    assert!(room_manager.delete_room("missing", "alice").await.is_err());
+    assert!(room_manager.delete_room("new-room", "alice").await.is_ok());
+    assert!(room_manager.delete_room("general", "alice").await.is_ok());
+
+    // a startup room without an owner can not be deleted
+    let room_manager = super::RoomManagerBuilder::new()
+        .create_room(test_metadata("lobby"))
+        .build()
+        .unwrap();
+    assert!(room_manager.delete_room("lobby", "alice").await.is_err());
+}
@@ -1,20 +1,36 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::Context;
 use comms::{
     command::UserCommand,
-    event::{self, Event},
+    event::{self, CommandErrorCode, Event, ModerationAction, RoomDetail},
 };
 use tokio::{
-    sync::mpsc,
+    sync::{broadcast::error::RecvError, mpsc},
     task::{AbortHandle, JoinSet},
 };
 
-use crate::room_manager::{RoomManager, SessionAndUserId, UserSessionHandle};
+use crate::account_store::AccountStore;
+use crate::config::MessagesConfig;
+use crate::error::CommandError;
+use crate::room_manager::{
+    ChatRoomMetadata, RoomManager, SessionAndUserId, UserSessionHandle, DEFAULT_HISTORY_PAGE_SIZE,
+};
+use crate::session_registry::SessionRegistry;
+
+use super::message_validator::MessageValidator;
 
 pub(super) struct ChatSession {
     session_and_user_id: SessionAndUserId,
     room_manager: Arc<RoomManager>,
+    account_store: Arc<AccountStore>,
+    session_registry: Arc<SessionRegistry>,
+    message_validator: MessageValidator,
     joined_rooms: HashMap<String, (UserSessionHandle, AbortHandle)>,
     join_set: JoinSet<()>,
     mpsc_tx: mpsc::Sender<Event>,
@@ -22,51 +38,104 @@ pub(super) struct ChatSession {
 }
 
 impl ChatSession {
-    pub fn new(session_id: &str, user_id: &str, room_manager: Arc<RoomManager>) -> Self {
-        let (mpsc_tx, mpsc_rx) = mpsc::channel(100);
+    pub fn new(
+        session_id: &str,
+        user_id: &str,
+        room_manager: Arc<RoomManager>,
+        account_store: Arc<AccountStore>,
+        session_registry: Arc<SessionRegistry>,
+        messages_config: &MessagesConfig,
+        channel_capacity: usize,
+    ) -> Self {
+        let (mpsc_tx, mpsc_rx) = mpsc::channel(channel_capacity);
         let session_and_user_id = SessionAndUserId {
             session_id: String::from(session_id),
             user_id: String::from(user_id),
         };
 
+        // events sent to the user directly, such as direct messages, are delivered via the same channel
+        session_registry.register(&session_and_user_id, mpsc_tx.clone());
+
+        // forward the events which are sent to all sessions, such as room creation and deletion
+        let mut join_set = JoinSet::new();
+        join_set.spawn(forward_server_events(room_manager.clone(), mpsc_tx.clone()));
+
         ChatSession {
             session_and_user_id,
             room_manager,
+            account_store,
+            session_registry,
+            message_validator: MessageValidator::new(messages_config),
             joined_rooms: HashMap::new(),
-            join_set: JoinSet::new(),
+            join_set,
             mpsc_tx,
             mpsc_rx,
         }
     }
 
-    /// Handle a user command related to room management such as; join, leave, send message
+    pub fn session_id(&self) -> &str {
+        &self.session_and_user_id.session_id
+    }
+
+    pub fn user_id(&self) -> &str {
+        &self.session_and_user_id.user_id
+    }
+
+    /// The rooms the user is currently participating in, ordered by name
+    pub fn joined_rooms(&self) -> Vec<String> {
+        let mut rooms = self.joined_rooms.keys().cloned().collect::<Vec<_>>();
+        rooms.sort();
+
+        rooms
+    }
+
+    /// Handle a user command related to room management such as; join, leave, send message, get history, set nickname,
+    /// create room, delete room, send direct message, kick, ban, unban, mute and unmute a user, start and stop typing
+    ///
+    /// Commands which can not be processed are answered with a [Event::CommandError],
+    /// an error is only returned if the session can not continue.
     pub async fn handle_user_command(&mut self, cmd: UserCommand) -> anyhow::Result<()> {
         match cmd {
             UserCommand::JoinRoom(cmd) => {
                 if self.joined_rooms.contains_key(&cmd.room) {
-                    return Err(anyhow::anyhow!("already joined room '{}'", &cmd.room));
+                    return self.reject(
+                        CommandError::in_room(
+                            CommandErrorCode::AlreadyJoined,
+                            &cmd.room,
+                            format!("already joined room '{}'", &cmd.room),
+                        )
+                        .with_request_id(cmd.request_id),
+                    );
                 }
 
-                let (mut broadcast_rx, user_session_handle, user_ids) = self
+                let display_name = self
+                    .account_store
+                    .display_name(&self.session_and_user_id.user_id);
+                let (mut receiver, user_session_handle, users) = match self
                     .room_manager
-                    .join_room(&cmd.room, &self.session_and_user_id)
-                    .await?;
+                    .join_room(&cmd.room, &self.session_and_user_id, &display_name)
+                    .await
+                {
+                    Ok(join_result) => join_result,
+                    Err(err) => return self.reject(err.with_request_id(cmd.request_id)),
+                };
+
+                // start with sending the user joined room event as a reply to the user,
+                // the room is stored even if it fails, so that it is left when the session ends
+                let replied = self.reply(Event::UserJoinedRoom(event::UserJoinedRoomReplyEvent {
+                    room: cmd.room.clone(),
+                    users,
+                    request_id: cmd.request_id.clone(),
+                }));
 
                 // spawn a task to forward broadcast messages to the users' mpsc channel
                 // hence the user can receive messages from different rooms via single channel
+                // if the user falls behind, the receiver re-sends the missed messages instead of stopping
                 let abort_handle = self.join_set.spawn({
                     let mpsc_tx = self.mpsc_tx.clone();
 
-                    // start with sending the user joined room event as a reply to the user
-                    mpsc_tx
-                        .send(Event::UserJoinedRoom(event::UserJoinedRoomReplyEvent {
-                            room: cmd.room.clone(),
-                            users: user_ids,
-                        }))
-                        .await?;
-
                     async move {
-                        while let Ok(event) = broadcast_rx.recv().await {
+                        while let Some(event) = receiver.recv().await {
                             let _ = mpsc_tx.send(event).await;
                         }
                     }
@@ -76,31 +145,207 @@ impl ChatSession {
                 // this is used to send messages to the room and to cancel the task when user leaves the room
                 self.joined_rooms
                     .insert(cmd.room.clone(), (user_session_handle, abort_handle));
-                
-                if let Some((user_session_handle, _)) = self.joined_rooms.get(&cmd.room) {
-                    // get the history of the room and send it to the user after joining the room
-                    let history = {
-                        let room = user_session_handle.chat_room.lock().await;
-                        room.get_history()
-                    };
+                replied?;
+
+                // send the most recent page of the room history to the user after joining the room
+                self.send_history(&cmd.room, None, DEFAULT_HISTORY_PAGE_SIZE, cmd.request_id)
+                    .await?;
+            }
+            UserCommand::SetNickname(cmd) => {
+                let user_id = &self.session_and_user_id.user_id;
+
+                if let Err(err) = self
+                    .account_store
+                    .set_nickname(user_id, &cmd.nickname)
+                    .await
+                {
+                    return self.reject(err.with_request_id(cmd.request_id));
+                }
+
+                self.room_manager
+                    .change_nickname(user_id, &cmd.nickname)
+                    .await;
+
+                // confirm the change to the user, even if they are not in any room
+                self.reply(Event::NicknameChanged(
+                    event::NicknameChangedBroadcastEvent {
+                        room: None,
+                        user_id: user_id.clone(),
+                        nickname: cmd.nickname,
+                        request_id: cmd.request_id,
+                    },
+                ))
+                .context("could not send nickname changed event")?;
+            }
+            UserCommand::CreateRoom(cmd) => {
+                // the description is printed by the clients as is, like the messages
+                let description = match self
+                    .message_validator
+                    .check_characters(cmd.description, "room description")
+                {
+                    Ok(description) => description,
+                    Err(err) => return self.reject(err.with_request_id(cmd.request_id)),
+                };
+
+                // the room manager makes the user the owner of the room
+                let metadata = ChatRoomMetadata {
+                    name: cmd.room,
+                    description,
+                    owner: None,
+                    moderators: Vec::new(),
+                };
+
+                // the user is notified about the new room along with all other sessions
+                if let Err(err) = self
+                    .room_manager
+                    .create_room(metadata, &self.session_and_user_id.user_id)
+                {
+                    return self.reject(err.with_request_id(cmd.request_id));
+                }
+            }
+            UserCommand::DeleteRoom(cmd) => {
+                if let Err(err) = self
+                    .room_manager
+                    .delete_room(&cmd.room, &self.session_and_user_id.user_id)
+                    .await
+                {
+                    return self.reject(err.with_request_id(cmd.request_id));
+                }
+            }
+            UserCommand::SendDirectMessage(cmd) => {
+                let user_id = &self.session_and_user_id.user_id;
 
-                    self.mpsc_tx
-                        .send(Event::RoomHistory(event::RoomHistoryReplyEvent {
-                            room: cmd.room.clone(),
-                            messages: history,
-                        }))
-                        .await?;
+                let result = if &cmd.user_id == user_id {
+                    Err(CommandError::new(
+                        CommandErrorCode::InvalidCommand,
+                        "you can not send a direct message to yourself",
+                    ))
+                } else if !self.account_store.exists(&cmd.user_id) {
+                    Err(CommandError::new(
+                        CommandErrorCode::UserNotFound,
+                        format!("user '{}' not found", cmd.user_id),
+                    ))
+                } else {
+                    // the user receives the message along with the recipient
+                    self.message_validator
+                        .validate(cmd.content)
+                        .and_then(|content| {
+                            self.session_registry.send_direct_message(
+                                &self.session_and_user_id,
+                                &self.account_store.display_name(user_id),
+                                &cmd.user_id,
+                                content,
+                                cmd.request_id.clone(),
+                            )
+                        })
+                };
+
+                if let Err(err) = result {
+                    return self.reject(err.with_request_id(cmd.request_id));
                 }
             }
-            UserCommand::SendMessage(cmd) => {
-                if let Some((user_session_handle, _)) = self.joined_rooms.get(&cmd.room) {
-                    let _ = user_session_handle.send_message(cmd.content).await;
+            UserCommand::GetHistory(cmd) => {
+                self.send_history(
+                    &cmd.room,
+                    cmd.before,
+                    cmd.limit.unwrap_or(DEFAULT_HISTORY_PAGE_SIZE),
+                    cmd.request_id,
+                )
+                .await?;
+            }
+            UserCommand::SendMessage(cmd) => match self.joined_rooms.get(&cmd.room) {
+                Some((user_session_handle, _)) => {
+                    let content = match self.message_validator.validate(cmd.content) {
+                        Ok(content) => content,
+                        Err(err) => {
+                            return self
+                                .reject(err.with_room(&cmd.room).with_request_id(cmd.request_id))
+                        }
+                    };
+
+                    // the content is validated again after the hooks of the room have run
+                    let validate = |content| self.message_validator.validate(content);
+                    match user_session_handle.send_message(content, validate).await {
+                        Ok(None) => {}
+                        // the message has been dropped by a hook of the room, the sender still sees it as sent
+                        Ok(Some(message)) => {
+                            self.reply(Event::UserMessage(message))
+                                .context("could not send the dropped message to the session")?;
+                        }
+                        Err(err) => return self.reject(err.with_request_id(cmd.request_id)),
+                    }
+                }
+                None => {
+                    return self.reject(
+                        CommandError::not_joined(&cmd.room).with_request_id(cmd.request_id),
+                    )
                 }
+            },
+            UserCommand::KickUser(cmd) => {
+                self.moderate(
+                    &cmd.room,
+                    &cmd.user_id,
+                    ModerationAction::Kicked,
+                    cmd.duration_secs,
+                    cmd.request_id,
+                )
+                .await?;
+            }
+            UserCommand::BanUser(cmd) => {
+                self.moderate(
+                    &cmd.room,
+                    &cmd.user_id,
+                    ModerationAction::Banned,
+                    cmd.duration_secs,
+                    cmd.request_id,
+                )
+                .await?;
+            }
+            UserCommand::UnbanUser(cmd) => {
+                self.moderate(
+                    &cmd.room,
+                    &cmd.user_id,
+                    ModerationAction::Unbanned,
+                    None,
+                    cmd.request_id,
+                )
+                .await?;
+            }
+            UserCommand::MuteUser(cmd) => {
+                self.moderate(
+                    &cmd.room,
+                    &cmd.user_id,
+                    ModerationAction::Muted,
+                    cmd.duration_secs,
+                    cmd.request_id,
+                )
+                .await?;
+            }
+            UserCommand::UnmuteUser(cmd) => {
+                self.moderate(
+                    &cmd.room,
+                    &cmd.user_id,
+                    ModerationAction::Unmuted,
+                    None,
+                    cmd.request_id,
+                )
+                .await?;
+            }
+            UserCommand::TypingStarted(cmd) => {
+                self.set_typing(&cmd.room, true, cmd.request_id).await?;
+            }
+            UserCommand::TypingStopped(cmd) => {
+                self.set_typing(&cmd.room, false, cmd.request_id).await?;
             }
             UserCommand::LeaveRoom(cmd) => {
                 // remove the room from joined rooms and drop user session handle for the room
-                if let Some(urp) = self.joined_rooms.remove(&cmd.room) {
-                    self.cleanup_room(urp).await?;
+                match self.joined_rooms.remove(&cmd.room) {
+                    Some(urp) => self.cleanup_room(urp).await?,
+                    None => {
+                        return self.reject(
+                            CommandError::not_joined(&cmd.room).with_request_id(cmd.request_id),
+                        )
+                    }
                 }
             }
             _ => {}
@@ -109,6 +354,97 @@ impl ChatSession {
         Ok(())
     }
 
+    /// Tell the participants of a joined room that the user has started or stopped typing
+    async fn set_typing(
+        &self,
+        room: &str,
+        typing: bool,
+        request_id: Option<String>,
+    ) -> anyhow::Result<()> {
+        match self.joined_rooms.get(room) {
+            Some((user_session_handle, _)) => {
+                user_session_handle.set_typing(typing).await;
+
+                Ok(())
+            }
+            None => self.reject(CommandError::not_joined(room).with_request_id(request_id)),
+        }
+    }
+
+    /// Moderate a user of a joined room, the moderator is told about the action along with the other participants
+    async fn moderate(
+        &self,
+        room: &str,
+        user_id: &str,
+        action: ModerationAction,
+        duration_secs: Option<u64>,
+        request_id: Option<String>,
+    ) -> anyhow::Result<()> {
+        let Some((user_session_handle, _)) = self.joined_rooms.get(room) else {
+            return self.reject(CommandError::not_joined(room).with_request_id(request_id));
+        };
+
+        if let Err(err) = user_session_handle
+            .moderate(user_id, action, duration_secs.map(Duration::from_secs))
+            .await
+        {
+            return self.reject(err.with_request_id(request_id));
+        }
+
+        Ok(())
+    }
+
+    /// Send a page of the history of a joined room to the user
+    async fn send_history(
+        &self,
+        room: &str,
+        before: Option<u64>,
+        limit: usize,
+        request_id: Option<String>,
+    ) -> anyhow::Result<()> {
+        let Some((user_session_handle, _)) = self.joined_rooms.get(room) else {
+            return self.reject(CommandError::not_joined(room).with_request_id(request_id));
+        };
+
+        let page = match user_session_handle.history(before, limit).await {
+            Ok(page) => page,
+            Err(err) => {
+                println!("could not read history of room '{}': {:#}", room, err);
+
+                return self.reject(
+                    CommandError::in_room(
+                        CommandErrorCode::Internal,
+                        room,
+                        "could not load the room history",
+                    )
+                    .with_request_id(request_id),
+                );
+            }
+        };
+
+        self.reply(Event::RoomHistory(event::RoomHistoryReplyEvent {
+            room: String::from(room),
+            messages: page.messages,
+            before,
+            next_cursor: page.next_cursor,
+            request_id,
+        }))
+    }
+
+    /// Reply to the user with the reason their command was rejected
+    pub fn reject(&self, error: CommandError) -> anyhow::Result<()> {
+        self.reply(Event::from(error))
+            .context("could not send the command error to the user")
+    }
+
+    /// Queue a reply to a command of the user
+    /// The channel is drained by the same loop that handles the commands, so a full channel fails instead of waiting
+    fn reply(&self, event: Event) -> anyhow::Result<()> {
+        self.mpsc_tx
+            .try_send(event)
+            .map_err(|_| anyhow::anyhow!("the events of the session are not written fast enough"))
+    }
+
     // TODO: optimize the performance of this function. leaving one by one may not be a good idea.
     /// Leave all the rooms the user is currently participating in
     pub async fn leave_all_rooms(&mut self) -> anyhow::Result<()> {
@@ -137,6 +473,30 @@ impl ChatSession {
         Ok(())
     }
 
+    /// Update the session for an event before it is sent to the user
+    /// The user is removed from a deleted room, or a room they are kicked or banned from, before they are told about it
+    pub async fn process_event(&mut self, event: &Event) -> anyhow::Result<()> {
+        let left_room = match event {
+            Event::RoomDeleted(event) => Some(&event.room),
+            Event::UserModerated(event)
+                if event.user_id == self.session_and_user_id.user_id
+                    && matches!(
+                        event.action,
+                        ModerationAction::Kicked | ModerationAction::Banned
+                    ) =>
+            {
+                Some(&event.room)
+            }
+            _ => None,
+        };
+
+        if let Some(urp) = left_room.and_then(|room| self.joined_rooms.remove(room)) {
+            self.cleanup_room(urp).await?;
+        }
+
+        Ok(())
+    }
+
     /// Receive an event that may have originated from any of the rooms the user is actively participating in
     pub async fn recv(&mut self) -> anyhow::Result<Event> {
         self.mpsc_rx
@@ -145,3 +505,188 @@ impl ChatSession {
             .context("could not recv from the broadcast channel")
     }
 }
+
+/// Forward the events sent to all sessions to the session channel
+/// If the session falls behind, the rooms created and deleted meanwhile are resynced from the room manager,
+/// so that the session does not keep a deleted room
+fn forward_server_events(
+    room_manager: Arc<RoomManager>,
+    mpsc_tx: mpsc::Sender<Event>,
+) -> impl Future<Output = ()> {
+    let room_names = |metadata: &[ChatRoomMetadata]| {
+        metadata
+            .iter()
+            .map(|metadata| metadata.name.clone())
+            .collect::<HashSet<_>>()
+    };
+    // the session is subscribed before it is handed out, the rooms it knows about are tracked from then on
+    let mut broadcast_rx = room_manager.subscribe();
+    let mut rooms = room_names(&room_manager.chat_room_metadata());
+
+    async move {
+        loop {
+            let events = match broadcast_rx.recv().await {
+                Ok(event) => {
+                    // the changes already resynced after falling behind are not sent twice
+                    let is_new = match &event {
+                        Event::RoomCreated(event) => rooms.insert(event.room.name.clone()),
+                        Event::RoomDeleted(event) => rooms.remove(&event.room),
+                        _ => true,
+                    };
+
+                    if is_new {
+                        vec![event]
+                    } else {
+                        Vec::new()
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    println!("session has missed {} server events", skipped);
+
+                    let metadata = room_manager.chat_room_metadata();
+                    let current = room_names(&metadata);
+                    let deleted = rooms.difference(&current).map(|room| {
+                        Event::RoomDeleted(event::RoomDeletedBroadcastEvent { room: room.clone() })
+                    });
+                    let created = metadata
+                        .iter()
+                        .filter(|metadata| !rooms.contains(&metadata.name))
+                        .map(|metadata| {
+                            Event::RoomCreated(event::RoomCreatedBroadcastEvent {
+                                room: RoomDetail {
+                                    name: metadata.name.clone(),
+                                    description: metadata.description.clone(),
+                                },
+                            })
+                        });
+                    let events = deleted.chain(created).collect::<Vec<_>>();
+                    rooms = current;
+
+                    events
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            for event in events {
+                let _ = mpsc_tx.send(event).await;
+            }
+        }
+    }
+}
+
+impl Drop for ChatSession {
+    fn drop(&mut self) {
+        self.session_registry.unregister(&self.session_and_user_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use comms::command::{JoinRoomCommand, LeaveRoomCommand, UserCommand};
+
+    use super::*;
+    use crate::config::Config;
+    use crate::room_manager::RoomManagerBuilder;
+
+    #[tokio::test]
+    async fn test_replies_do_not_wait_for_a_full_channel() {
+        let config = Config::default();
+        let mut chat_session = ChatSession::new(
+            "session",
+            "alice",
+            Arc::new(RoomManagerBuilder::new().build().unwrap()),
+            Arc::new(AccountStore::in_memory()),
+            Arc::new(SessionRegistry::new()),
+            &config.messages,
+            1,
+        );
+        let leave = || {
+            UserCommand::LeaveRoom(LeaveRoomCommand {
+                room: "general".into(),
+                request_id: None,
+            })
+        };
+
+        // the first rejection fills the channel, the second one fails instead of blocking the session
+        chat_session.handle_user_command(leave()).await.unwrap();
+        let result = tokio::time::timeout(
+            Duration::from_secs(1),
+            chat_session.handle_user_command(leave()),
+        )
+        .await
+        .expect("the reply has waited for the full channel");
+        assert!(result.is_err());
+
+        assert!(matches!(
+            chat_session.recv().await.unwrap(),
+            Event::CommandError(event) if event.code == CommandErrorCode::NotJoined
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_missed_room_deletion_is_resynced() {
+        let config = Config::default();
+        let room_manager = Arc::new(
+            RoomManagerBuilder::new()
+                .create_room(ChatRoomMetadata {
+                    name: "general".into(),
+                    description: String::new(),
+                    owner: Some("alice".into()),
+                    moderators: Vec::new(),
+                })
+                .broadcast_channel_capacity(1)
+                .build()
+                .unwrap(),
+        );
+        let mut chat_session = ChatSession::new(
+            "session",
+            "alice",
+            room_manager.clone(),
+            Arc::new(AccountStore::in_memory()),
+            Arc::new(SessionRegistry::new()),
+            &config.messages,
+            10,
+        );
+        chat_session
+            .handle_user_command(UserCommand::JoinRoom(JoinRoomCommand {
+                room: "general".into(),
+                request_id: None,
+            }))
+            .await
+            .unwrap();
+
+        // the deletion is pushed out of the server events by the rooms created after it
+        room_manager.delete_room("general", "alice").await.unwrap();
+        for room in ["first", "second"] {
+            room_manager
+                .create_room(
+                    ChatRoomMetadata {
+                        name: room.into(),
+                        description: String::new(),
+                        owner: None,
+                        moderators: Vec::new(),
+                    },
+                    "alice",
+                )
+                .unwrap();
+        }
+
+        let mut rooms = Vec::new();
+        while rooms.len() < 3 {
+            let event = tokio::time::timeout(Duration::from_secs(1), chat_session.recv())
+                .await
+                .expect("the missed deletion was not resynced")
+                .unwrap();
+            chat_session.process_event(&event).await.unwrap();
+            match event {
+                Event::RoomDeleted(event) => rooms.push(format!("-{}", event.room)),
+                Event::RoomCreated(event) => rooms.push(format!("+{}", event.room.name)),
+                _ => {}
+            }
+        }
+
+        rooms.sort();
+        assert_eq!(rooms, ["+first", "+second", "-general"]);
+        assert!(chat_session.joined_rooms().is_empty());
+    }
+}
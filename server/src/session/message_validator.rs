@@ -0,0 +1,220 @@
+use std::{iter::Peekable, str::Chars};
+
+use comms::event::CommandErrorCode;
+
+use crate::config::{ControlCharacters, MessagesConfig};
+use crate::error::CommandError;
+
+/// Escape character, which starts the terminal escape sequences
+const ESC: char = '\u{1b}';
+/// Single character form of the control sequence introducer, `ESC [`
+const CSI: char = '\u{9b}';
+/// Bell character, which may end an operating system command
+const BEL: char = '\u{7}';
+
+/// [MessageValidator] checks the content of the room and direct messages before they are sent
+///
+/// The content is printed as is by the clients, so terminal escape sequences could move the cursor,
+/// change the colors or the title of the terminal of every participant.
+#[derive(Debug, Clone)]
+pub(super) struct MessageValidator {
+    max_length: usize,
+    control_characters: ControlCharacters,
+}
+
+impl MessageValidator {
+    pub fn new(config: &MessagesConfig) -> Self {
+        MessageValidator {
+            max_length: config.max_length,
+            control_characters: config.control_characters,
+        }
+    }
+
+    /// Returns the content to send, with the control characters stripped if they are not refused
+    /// Fails if the content is empty, too long or contains refused control characters
+    pub fn validate(&self, content: String) -> Result<String, CommandError> {
+        let content = self.check_characters(content, "message")?;
+
+        if content.trim().is_empty() {
+            return Err(CommandError::new(
+                CommandErrorCode::EmptyMessage,
+                "message can not be empty",
+            ));
+        }
+
+        let length = content.chars().count();
+        if length > self.max_length {
+            return Err(CommandError::new(
+                CommandErrorCode::MessageTooLong,
+                format!(
+                    "message is {} characters long, it can be at most {} characters",
+                    length, self.max_length
+                ),
+            ));
+        }
+
+        Ok(content)
+    }
+
+    /// Returns the text with the control characters stripped if they are not refused
+    /// Fails if the text contains refused control characters, `what` names the text in the error
+    pub fn check_characters(&self, text: String, what: &str) -> Result<String, CommandError> {
+        if !text.chars().any(char::is_control) {
+            Ok(text)
+        } else if self.control_characters == ControlCharacters::Strip {
+            Ok(strip_control_characters(&text))
+        } else {
+            Err(CommandError::new(
+                CommandErrorCode::InvalidCharacters,
+                format!(
+                    "{} can not contain control characters or escape sequences",
+                    what
+                ),
+            ))
+        }
+    }
+}
+
+/// Removes the terminal escape sequences and the control characters, line breaks and tabs are replaced with spaces
+fn strip_control_characters(content: &str) -> String {
+    let mut stripped = String::with_capacity(content.len());
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ESC => skip_escape_sequence(&mut chars),
+            CSI => skip_control_sequence(&mut chars),
+            '\t' | '\n' | '\r' => stripped.push(' '),
+            c if c.is_control() => {}
+            c => stripped.push(c),
+        }
+    }
+
+    stripped
+}
+
+/// Skips the rest of an escape sequence, after its escape character
+fn skip_escape_sequence(chars: &mut Peekable<Chars>) {
+    match chars.next() {
+        Some('[') => skip_control_sequence(chars),
+        // operating system commands and the other strings are ended by the string terminator, `ESC \`, or a bell
+        Some(']' | 'P' | 'X' | '^' | '_') => {
+            while let Some(c) = chars.next() {
+                if c == BEL || (c == ESC && chars.next_if_eq(&'\\').is_some()) {
+                    break;
+                }
+            }
+        }
+        // two character sequences, such as `ESC c` which resets the terminal
+        _ => {}
+    }
+}
+
+/// Skips the parameters of a control sequence up to and including its final byte, e.g. `31m` of `ESC [ 31m`
+fn skip_control_sequence(chars: &mut Peekable<Chars>) {
+    for c in chars.by_ref() {
+        if ('\u{40}'..='\u{7e}').contains(&c) {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validator(control_characters: ControlCharacters) -> MessageValidator {
+        MessageValidator::new(&MessagesConfig {
+            max_length: 10,
+            control_characters,
+        })
+    }
+
+    fn error_code(result: Result<String, CommandError>) -> CommandErrorCode {
+        match comms::event::Event::from(result.unwrap_err()) {
+            comms::event::Event::CommandError(event) => event.code,
+            event => panic!("unexpected event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_escape_sequences_are_stripped() {
+        let validator = validator(ControlCharacters::Strip);
+
+        assert_eq!(validator.validate("hello".into()).unwrap(), "hello");
+        assert_eq!(
+            validator.validate("\u{1b}[31mred\u{1b}[0m".into()).unwrap(),
+            "red"
+        );
+        assert_eq!(
+            validator
+                .validate("\u{1b}]0;title\u{7}a\u{1b}]8;;x\u{1b}\\b".into())
+                .unwrap(),
+            "ab"
+        );
+        assert_eq!(
+            validator.validate("\u{1b}ca\u{9b}2Jb\u{0}".into()).unwrap(),
+            "ab"
+        );
+        assert_eq!(validator.validate("a\r\nb\tc".into()).unwrap(), "a  b c");
+        // the length is counted in characters after stripping
+        assert_eq!(
+            validator.validate("\u{1b}[1mhéllo wörl".into()).unwrap(),
+            "héllo wörl"
+        );
+    }
+
+    #[test]
+    fn test_invalid_messages_are_rejected() {
+        let strip = validator(ControlCharacters::Strip);
+
+        assert_eq!(
+            error_code(strip.validate(String::new())),
+            CommandErrorCode::EmptyMessage
+        );
+        assert_eq!(
+            error_code(strip.validate(" \n\t".into())),
+            CommandErrorCode::EmptyMessage
+        );
+        assert_eq!(
+            error_code(strip.validate("\u{1b}[2J".into())),
+            CommandErrorCode::EmptyMessage
+        );
+        assert_eq!(
+            error_code(strip.validate("hello world".into())),
+            CommandErrorCode::MessageTooLong
+        );
+
+        let reject = validator(ControlCharacters::Reject);
+        assert_eq!(reject.validate("hello".into()).unwrap(), "hello");
+        assert_eq!(
+            error_code(reject.validate("\u{1b}[31mred".into())),
+            CommandErrorCode::InvalidCharacters
+        );
+        assert_eq!(
+            error_code(reject.validate("a\nb".into())),
+            CommandErrorCode::InvalidCharacters
+        );
+    }
+
+    #[test]
+    fn test_room_descriptions_are_checked_for_control_characters() {
+        // the message length limit does not apply to the other texts
+        assert_eq!(
+            validator(ControlCharacters::Strip)
+                .check_characters(
+                    "\u{1b}]0;title\u{7}a room about rust".into(),
+                    "room description"
+                )
+                .unwrap(),
+            "a room about rust"
+        );
+        assert_eq!(
+            error_code(
+                validator(ControlCharacters::Reject)
+                    .check_characters("\u{1b}[2Jrust".into(), "room description")
+            ),
+            CommandErrorCode::InvalidCharacters
+        );
+    }
+}
@@ -1,88 +1,497 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use comms::{
-    command::UserCommand,
-    event::{self, RoomDetail},
-    transport,
+    command::{PingCommand, UserCommand},
+    event::{self, CommandErrorCode, RoomDetail},
+    transport::{
+        self,
+        server::{CommandStream, EventWriter},
+        FrameTooLargeError,
+    },
 };
 use nanoid::nanoid;
-use tokio::{net::TcpStream, sync::broadcast};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::broadcast,
+    time::{self, Instant},
+};
 use tokio_stream::StreamExt;
 
-use crate::room_manager::RoomManager;
+use crate::{
+    account_store::AccountStore,
+    config::Config,
+    error::CommandError,
+    rate_limiter::SessionRateLimiter,
+    room_manager::{now_millis, RoomManager},
+    session_registry::SessionRegistry,
+};
 
 use self::chat_session::ChatSession;
+pub use self::suspended_sessions::SuspendedSessions;
 
 mod chat_session;
+mod message_validator;
+mod suspended_sessions;
+
+/// Number of failed attempts to register, log in or resume a session after which the connection is closed
+const MAX_FAILED_AUTH_ATTEMPTS: usize = 10;
 
-/// Given a tcp stream and a room manager, handles the user session
-/// until the user quits the session, or the tcp stream is closed for some reason, or the server shuts down
-pub async fn handle_user_session(
+/// Given the server config, a stream, a room manager, an account store, a session registry and the suspended sessions,
+/// handles the user session until the user quits the session, or the stream is closed or stays idle for too long, or the server shuts down
+///
+/// The stream is any connection to the user, e.g. a TCP or a Unix socket.
+/// If the connection is lost, the session is suspended for the grace period instead of leaving its rooms,
+/// so that the user can resume it with their resume token.
+pub async fn handle_user_session<S>(
+    config: Arc<Config>,
     room_manager: Arc<RoomManager>,
+    account_store: Arc<AccountStore>,
+    session_registry: Arc<SessionRegistry>,
+    suspended_sessions: Arc<SuspendedSessions>,
     mut quit_rx: broadcast::Receiver<()>,
-    stream: TcpStream,
-) -> anyhow::Result<()> {
-    let session_id = nanoid!();
-    // Generate a random id for the user, since we don't have a login system
-    let user_id = String::from(&nanoid!()[0..5]);
-    // Split the tcp stream into a command stream and an event writer with better ergonomics
-    let (mut commands, mut event_writer) = transport::server::split_tcp_stream(stream);
-
-    // Welcoming the user with a login successful event and necessary information about the server
-    event_writer
-        .write(&event::Event::LoginSuccessful(
-            event::LoginSuccessfulReplyEvent {
-                session_id: session_id.clone(),
+    stream: S,
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    // Split the stream into a command stream and an event writer with better ergonomics
+    // The client picks the codec, which is told apart by the first bytes it sends
+    let (mut commands, mut event_writer) = match time::timeout(
+        config.heartbeat.idle_timeout(),
+        transport::server::split_stream_detecting_codec(stream, config.limits.max_frame_size),
+    )
+    .await
+    {
+        Ok(split) => split?,
+        Err(_) => return Ok(()),
+    };
+
+    // The attempts to authenticate take from the same budgets as the commands of the session afterwards
+    let mut rate_limiter = SessionRateLimiter::new(&config.rate_limits);
+
+    // The user has to register, log in or resume a session before they can participate in any room
+    let Some(authenticated) = authenticate(
+        &account_store,
+        &suspended_sessions,
+        &mut commands,
+        &mut event_writer,
+        &mut quit_rx,
+        &mut rate_limiter,
+        config.heartbeat.idle_timeout(),
+    )
+    .await?
+    else {
+        return Ok(());
+    };
+
+    // a new token is handed out on every login and resume, so that a token can only be used once
+    let resume_token = suspended_sessions
+        .is_enabled()
+        .then(SuspendedSessions::new_token);
+    let rooms = room_manager
+        .chat_room_metadata()
+        .iter()
+        .map(|metadata| RoomDetail {
+            name: metadata.name.clone(),
+            description: metadata.description.clone(),
+        })
+        .collect();
+
+    let (mut chat_session, welcome) = match authenticated {
+        Authenticated::LoggedIn {
+            user_id,
+            request_id,
+        } => {
+            // Create a chat session with the given room manager
+            // Chat Session will abstract the user session handling logic for multiple rooms
+            // It is created before listing the rooms, so the user is told about any room created afterwards
+            let chat_session = ChatSession::new(
+                &nanoid!(),
+                &user_id,
+                room_manager.clone(),
+                account_store.clone(),
+                session_registry,
+                &config.messages,
+                config.channels.session_capacity,
+            );
+
+            // Welcoming the user with a login successful event and necessary information about the server
+            let welcome = event::Event::LoginSuccessful(event::LoginSuccessfulReplyEvent {
+                session_id: chat_session.session_id().to_string(),
                 user_id: user_id.clone(),
-                rooms: room_manager
-                    .chat_room_metadata()
-                    .iter()
-                    .map(|metadata| RoomDetail {
-                        name: metadata.name.clone(),
-                        description: metadata.description.clone(),
-                    })
-                    .collect(),
-            },
-        ))
-        .await?;
+                display_name: account_store.display_name(&user_id),
+                rooms,
+                resume_token: resume_token.clone(),
+                request_id,
+            });
 
-    // Create a chat session with the given room manager
-    // Chat Session will abstract the user session handling logic for multiple rooms
-    let mut chat_session = ChatSession::new(&session_id, &user_id, room_manager);
+            (chat_session, welcome)
+        }
+        // the events the session has missed meanwhile are waiting in its channel, they are sent after the reply
+        Authenticated::Resumed {
+            chat_session,
+            request_id,
+        } => {
+            let welcome = event::Event::SessionResumed(event::SessionResumedReplyEvent {
+                session_id: chat_session.session_id().to_string(),
+                user_id: chat_session.user_id().to_string(),
+                display_name: account_store.display_name(chat_session.user_id()),
+                rooms,
+                joined_rooms: chat_session.joined_rooms(),
+                resume_token: resume_token.clone().unwrap_or_default(),
+                request_id,
+            });
+
+            (chat_session, welcome)
+        }
+    };
+
+    let end = match event_writer.write(&welcome).await {
+        Ok(()) => {
+            run_session(
+                &config,
+                &mut chat_session,
+                &mut commands,
+                &mut event_writer,
+                &mut quit_rx,
+                &mut rate_limiter,
+            )
+            .await?
+        }
+        Err(_) => SessionEnd::ConnectionLost,
+    };
+
+    match (end, resume_token) {
+        // the user leaves the rooms only if they do not resume the session in time
+        (SessionEnd::ConnectionLost, Some(resume_token)) => {
+            println!(
+                "Suspending session '{}' of user '{}'.",
+                chat_session.session_id(),
+                chat_session.user_id()
+            );
+            suspended_sessions.suspend(resume_token, chat_session);
+        }
+        // If the server is shutting down, we don't need to notify other users about the user's departure or cleanup resources
+        (SessionEnd::ServerShutdown, _) => {}
+        // We need to clean up resources in a way that the other users are notified about the user's departure
+        _ => chat_session.leave_all_rooms().await?,
+    }
+
+    // a TLS client is told the connection was closed on purpose, the user may be gone already
+    let _ = event_writer.shutdown().await;
+
+    Ok(())
+}
+
+/// How a session has ended
+enum SessionEnd {
+    /// The user has quit, or has broken the protocol
+    Quit,
+    /// The stream was closed, or stayed idle for too long, the user may come back
+    ConnectionLost,
+    /// The server is shutting down
+    ServerShutdown,
+}
+
+/// Handles the commands of an authenticated user, and sends them the events of their session, until the session ends
+async fn run_session(
+    config: &Config,
+    chat_session: &mut ChatSession,
+    commands: &mut CommandStream,
+    event_writer: &mut EventWriter,
+    quit_rx: &mut broadcast::Receiver<()>,
+    rate_limiter: &mut SessionRateLimiter,
+) -> anyhow::Result<SessionEnd> {
+    // the client is pinged once the connection has been idle for a while, and dropped if it stays silent
+    // a half-open connection is only noticed this way, as writing to it does not fail right away
+    let mut last_activity = Instant::now();
+    let mut heartbeat = time::interval(config.heartbeat.interval());
 
     loop {
         tokio::select! {
-            cmd = commands.next() => match cmd {
-                // If the user closes the tcp stream, or sends a quit cmd
-                // We need to clean up resources in a way that the other users are notified about the user's departure
-                None | Some(Ok(UserCommand::Quit(_))) => {
-                    chat_session.leave_all_rooms().await?;
-                    break;
+            cmd = commands.next() => {
+                last_activity = Instant::now();
+
+                // The user is told when to retry a command above the rate limit, a repeat offender is disconnected as if they had quit
+                if let Some(Ok(cmd)) = &cmd {
+                    if let Err(limited) = rate_limiter.check(cmd) {
+                        if limited.silent {
+                            continue;
+                        }
+
+                        let error = CommandError::rate_limited(limited.retry_after)
+                            .with_request_id(cmd.request_id().map(String::from));
+                        if limited.disconnect {
+                            println!(
+                                "Disconnecting session '{}' of user '{}', it was rate limited too often.",
+                                chat_session.session_id(),
+                                chat_session.user_id()
+                            );
+                            let _ = event_writer.write(&event::Event::from(error)).await;
+                            return Ok(SessionEnd::Quit);
+                        }
+
+                        // the loop is the only reader of the session channel, so the error is written right away
+                        if event_writer.write(&event::Event::from(error)).await.is_err() {
+                            return Ok(SessionEnd::ConnectionLost);
+                        }
+                        continue;
+                    }
                 }
-                // Handle a valid user command
-                Some(Ok(cmd)) => match cmd {
-                    // For user session related commands, we need to handle them in the chat session
-                    UserCommand::JoinRoom(_) | UserCommand::SendMessage(_) | UserCommand::LeaveRoom(_) => {
-                        chat_session.handle_user_command(cmd).await?;
+
+                match cmd {
+                    // The user has closed the stream, they may resume the session
+                    None => return Ok(SessionEnd::ConnectionLost),
+                    // The user has quit the session on purpose
+                    Some(Ok(UserCommand::Quit(_))) => return Ok(SessionEnd::Quit),
+                    // Handle a valid user command
+                    Some(Ok(cmd)) => match cmd {
+                        // For user session related commands, we need to handle them in the chat session
+                        UserCommand::JoinRoom(_)
+                        | UserCommand::SendMessage(_)
+                        | UserCommand::LeaveRoom(_)
+                        | UserCommand::GetHistory(_)
+                        | UserCommand::SetNickname(_)
+                        | UserCommand::CreateRoom(_)
+                        | UserCommand::DeleteRoom(_)
+                        | UserCommand::KickUser(_)
+                        | UserCommand::BanUser(_)
+                        | UserCommand::UnbanUser(_)
+                        | UserCommand::MuteUser(_)
+                        | UserCommand::UnmuteUser(_)
+                        | UserCommand::TypingStarted(_)
+                        | UserCommand::TypingStopped(_)
+                        | UserCommand::SendDirectMessage(_) => {
+                            // the replies are dropped into the session channel without waiting, the session is
+                            // disconnected if it is full, as the user does not read their events fast enough
+                            if let Err(err) = chat_session.handle_user_command(cmd).await {
+                                println!(
+                                    "Disconnecting session '{}' of user '{}': {:#}",
+                                    chat_session.session_id(),
+                                    chat_session.user_id(),
+                                    err
+                                );
+                                return Ok(SessionEnd::Quit);
+                            }
+                        }
+                        UserCommand::Hello(_) | UserCommand::Register(_) | UserCommand::Login(_) | UserCommand::ResumeSession(_) => {
+                            let error = CommandError::new(
+                                CommandErrorCode::AlreadyAuthenticated,
+                                "you have already logged in",
+                            )
+                            .with_request_id(cmd.request_id().map(String::from));
+                            if event_writer.write(&event::Event::from(error)).await.is_err() {
+                                return Ok(SessionEnd::ConnectionLost);
+                            }
+                        }
+                        UserCommand::Ping(cmd) => {
+                            let written = event_writer.write(&pong(cmd)).await;
+                            if written.is_err() {
+                                return Ok(SessionEnd::ConnectionLost);
+                            }
+                        }
+                        // the pong only needs to reset the idle timer, which any command does
+                        _ => {}
+                    }
+                    // The rest of an oversized frame can not be told apart from the next command,
+                    // so the user is told why and disconnected as if they had quit
+                    Some(Err(err)) if err.is::<FrameTooLargeError>() => {
+                        let _ = event_writer.write(&protocol_error(&err)).await;
+                        return Ok(SessionEnd::Quit);
+                    }
+                    // The user has sent something we could not parse, let them know instead of dropping it silently
+                    Some(Err(err)) => {
+                        let error = CommandError::new(CommandErrorCode::InvalidCommand, format!("{:#}", err));
+                        if event_writer.write(&event::Event::from(error)).await.is_err() {
+                            return Ok(SessionEnd::ConnectionLost);
+                        }
                     }
-                    _ => {}
                 }
-                _ => {}
             },
             // Aggregated events from the chat session are sent to the user
             Ok(event) = chat_session.recv() => {
-                event_writer.write(&event).await?;
+                chat_session.process_event(&event).await?;
+                if event_writer.write(&event).await.is_err() {
+                    return Ok(SessionEnd::ConnectionLost);
+                }
             }
-            // If the server is shutting down, we can just close the tcp streams
-            // and exit the session handler. Since the server is shutting down,
-            // we don't need to notify other users about the user's departure or cleanup resources
+            // Ping the user if they have been idle for a while, so that a live client answers before it times out
+            _ = heartbeat.tick() => {
+                if last_activity.elapsed() >= config.heartbeat.interval() {
+                    let ping = event::Event::Ping(event::PingEvent {
+                        timestamp: now_millis(),
+                    });
+                    if event_writer.write(&ping).await.is_err() {
+                        return Ok(SessionEnd::ConnectionLost);
+                    }
+                }
+            }
+            // The user has not sent anything for too long, the connection is most likely dead
+            _ = time::sleep_until(last_activity + config.heartbeat.idle_timeout()) => {
+                println!("Closing idle session '{}' of user '{}'.", chat_session.session_id(), chat_session.user_id());
+                return Ok(SessionEnd::ConnectionLost);
+            }
+            // If the server is shutting down, we can just close the streams and exit the session handler
             Ok(_) = quit_rx.recv() => {
-                drop(event_writer);
                 println!("Gracefully shutting down user tcp stream.");
-                break;
+                return Ok(SessionEnd::ServerShutdown);
             }
         }
     }
+}
 
-    Ok(())
+/// The reply to a ping of the user
+fn pong(cmd: PingCommand) -> event::Event {
+    event::Event::Pong(event::PongReplyEvent {
+        timestamp: cmd.timestamp,
+        request_id: cmd.request_id,
+    })
+}
+
+/// The reply to a user who has broken the protocol, sent right before they are disconnected
+fn protocol_error(err: &anyhow::Error) -> event::Event {
+    event::Event::from(CommandError::new(
+        CommandErrorCode::ProtocolError,
+        format!("{:#}", err),
+    ))
+}
+
+/// How the user has been authenticated
+enum Authenticated {
+    /// The user has registered or logged in, a new session is started for them
+    LoggedIn {
+        user_id: String,
+        request_id: Option<String>,
+    },
+    /// The user has resumed a suspended session
+    Resumed {
+        chat_session: ChatSession,
+        request_id: Option<String>,
+    },
+}
+
+/// Waits for the user to register, log in or resume a session successfully, answering the hello and the pings of the user
+/// and rejecting every other command meanwhile
+/// Returns how the user was authenticated, along with the request id of the command that authenticated them,
+/// or `None` if the session has ended before the user was authenticated, because they did not authenticate within
+/// the idle timeout, failed too many times or went above the rate limits too often
+async fn authenticate(
+    account_store: &AccountStore,
+    suspended_sessions: &SuspendedSessions,
+    commands: &mut CommandStream,
+    event_writer: &mut EventWriter,
+    quit_rx: &mut broadcast::Receiver<()>,
+    rate_limiter: &mut SessionRateLimiter,
+    idle_timeout: Duration,
+) -> anyhow::Result<Option<Authenticated>> {
+    // the user is given a single idle timeout to authenticate, sending pings does not extend it
+    let deadline = Instant::now() + idle_timeout;
+    let mut failed_attempts = 0;
+
+    loop {
+        let cmd = tokio::select! {
+            cmd = commands.next() => cmd,
+            _ = time::sleep_until(deadline) => return Ok(None),
+            Ok(_) = quit_rx.recv() => return Ok(None),
+        };
+
+        if let Some(Ok(cmd)) = &cmd {
+            if let Err(limited) = rate_limiter.check(cmd) {
+                let error = CommandError::rate_limited(limited.retry_after)
+                    .with_request_id(cmd.request_id().map(String::from));
+                event_writer.write(&event::Event::from(error)).await?;
+
+                if limited.disconnect {
+                    return Ok(None);
+                }
+                continue;
+            }
+        }
+
+        let is_attempt = matches!(
+            cmd,
+            Some(Ok(UserCommand::Register(_)
+                | UserCommand::Login(_)
+                | UserCommand::ResumeSession(_)))
+        );
+
+        let result = match cmd {
+            None | Some(Ok(UserCommand::Quit(_))) => return Ok(None),
+            Some(Ok(UserCommand::Ping(cmd))) => {
+                event_writer.write(&pong(cmd)).await?;
+                continue;
+            }
+            Some(Ok(UserCommand::Pong(_))) => continue,
+            // the hello is optional, but it has to come first so that the client knows what to expect afterwards
+            Some(Ok(UserCommand::Hello(cmd))) if event_writer.negotiated().is_none() => {
+                match event_writer.reply_hello(cmd).await? {
+                    Some(_) => continue,
+                    None => return Ok(None),
+                }
+            }
+            Some(Ok(UserCommand::Hello(cmd))) => Err(CommandError::new(
+                CommandErrorCode::InvalidCommand,
+                "the hello has already been sent",
+            )
+            .with_request_id(cmd.request_id)),
+            Some(Err(err)) if err.is::<FrameTooLargeError>() => {
+                event_writer.write(&protocol_error(&err)).await?;
+                return Ok(None);
+            }
+            Some(Ok(UserCommand::Register(cmd))) => account_store
+                .register(&cmd.username, &cmd.password)
+                .await
+                .map(|_| Authenticated::LoggedIn {
+                    user_id: cmd.username,
+                    request_id: cmd.request_id.clone(),
+                })
+                .map_err(|err| err.with_request_id(cmd.request_id)),
+            Some(Ok(UserCommand::Login(cmd))) => account_store
+                .authenticate(&cmd.username, &cmd.password)
+                .await
+                .map(|_| Authenticated::LoggedIn {
+                    user_id: cmd.username,
+                    request_id: cmd.request_id.clone(),
+                })
+                .map_err(|err| err.with_request_id(cmd.request_id)),
+            Some(Ok(UserCommand::ResumeSession(cmd))) => {
+                match suspended_sessions.resume(&cmd.token) {
+                    Some(chat_session) => Ok(Authenticated::Resumed {
+                        chat_session,
+                        request_id: cmd.request_id,
+                    }),
+                    None => Err(CommandError::new(
+                        CommandErrorCode::InvalidResumeToken,
+                        "the session can not be resumed, log in instead",
+                    )
+                    .with_request_id(cmd.request_id)),
+                }
+            }
+            Some(Ok(cmd)) => Err(CommandError::new(
+                CommandErrorCode::NotAuthenticated,
+                "you have to log in first",
+            )
+            .with_request_id(cmd.request_id().map(String::from))),
+            Some(Err(err)) => Err(CommandError::new(
+                CommandErrorCode::InvalidCommand,
+                format!("{:#}", err),
+            )),
+        };
+
+        match result {
+            Ok(authenticated) => return Ok(Some(authenticated)),
+            Err(err) => event_writer.write(&event::Event::from(err)).await?,
+        }
+
+        if is_attempt {
+            failed_attempts += 1;
+            if failed_attempts >= MAX_FAILED_AUTH_ATTEMPTS {
+                println!(
+                    "Closing connection after {} failed attempts to authenticate.",
+                    failed_attempts
+                );
+                return Ok(None);
+            }
+        }
+    }
 }
@@ -0,0 +1,95 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use nanoid::nanoid;
+use tokio::{task::AbortHandle, time};
+
+use super::chat_session::ChatSession;
+
+/// Length of the resume tokens, long enough that they can not be guessed
+const RESUME_TOKEN_LENGTH: usize = 32;
+
+/// [SuspendedSessions] keeps the sessions that have lost their connection for a grace period, by their resume token
+///
+/// A suspended session stays in its rooms and its events keep piling up, so that they are sent to the user once they resume it.
+/// If the session falls too far behind a room meanwhile, the room re-sends the missed messages on resume.
+/// A session that is not resumed within the grace period leaves its rooms.
+pub struct SuspendedSessions {
+    grace_period: Duration,
+    sessions: Mutex<HashMap<String, SuspendedSession>>,
+}
+
+struct SuspendedSession {
+    chat_session: ChatSession,
+    /// The task that ends the session once the grace period is over
+    expiry: AbortHandle,
+}
+
+impl SuspendedSessions {
+    /// Sessions are not kept at all if the grace period is zero
+    pub fn new(grace_period: Duration) -> Self {
+        SuspendedSessions {
+            grace_period,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether the sessions that lose their connection are kept, and resume tokens are handed out
+    pub fn is_enabled(&self) -> bool {
+        !self.grace_period.is_zero()
+    }
+
+    pub(super) fn new_token() -> String {
+        nanoid!(RESUME_TOKEN_LENGTH)
+    }
+
+    /// Keep the session until it is resumed with the token, or the grace period is over
+    pub(super) fn suspend(self: &Arc<Self>, token: String, chat_session: ChatSession) {
+        // the lock is held until the session is stored, so that it can not expire before
+        let mut sessions = self.sessions.lock().unwrap();
+
+        let expiry = tokio::spawn({
+            let suspended_sessions = Arc::clone(self);
+            let token = token.clone();
+
+            async move {
+                time::sleep(suspended_sessions.grace_period).await;
+
+                let suspended = suspended_sessions.sessions.lock().unwrap().remove(&token);
+                if let Some(SuspendedSession {
+                    mut chat_session, ..
+                }) = suspended
+                {
+                    println!(
+                        "Session '{}' of user '{}' has expired.",
+                        chat_session.session_id(),
+                        chat_session.user_id()
+                    );
+                    if let Err(err) = chat_session.leave_all_rooms().await {
+                        println!("could not leave the rooms of an expired session: {:#}", err);
+                    }
+                }
+            }
+        })
+        .abort_handle();
+
+        sessions.insert(
+            token,
+            SuspendedSession {
+                chat_session,
+                expiry,
+            },
+        );
+    }
+
+    /// Take the session suspended with the token, if it has not expired
+    pub(super) fn resume(&self, token: &str) -> Option<ChatSession> {
+        let suspended = self.sessions.lock().unwrap().remove(token)?;
+        suspended.expiry.abort();
+
+        Some(suspended.chat_session)
+    }
+}
@@ -0,0 +1,173 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+};
+
+use comms::event::{CommandErrorCode, DirectMessageEvent, Event};
+use tokio::sync::{mpsc, mpsc::error::TrySendError};
+
+use crate::error::CommandError;
+use crate::room_manager::{now_millis, SessionAndUserId};
+
+/// [SessionRegistry] keeps track of the sessions of every logged in user, regardless of the rooms they are in
+///
+/// It lives next to the [crate::room_manager::RoomManager] and is used to deliver events to users directly,
+/// such as direct messages, to all of their sessions at once.
+#[derive(Debug, Default)]
+pub struct SessionRegistry {
+    /// The event channels of the sessions, by user id and then by session id
+    sessions: RwLock<HashMap<String, HashMap<String, mpsc::Sender<Event>>>>,
+    last_direct_message_id: AtomicU64,
+}
+
+impl SessionRegistry {
+    pub fn new() -> Self {
+        SessionRegistry::default()
+    }
+
+    /// Register a logged in session, events for the user are sent to the given channel from now on
+    pub fn register(&self, session_and_user_id: &SessionAndUserId, tx: mpsc::Sender<Event>) {
+        self.sessions
+            .write()
+            .unwrap()
+            .entry(session_and_user_id.user_id.clone())
+            .or_default()
+            .insert(session_and_user_id.session_id.clone(), tx);
+    }
+
+    /// Remove a session, the user is considered offline once all of their sessions are removed
+    pub fn unregister(&self, session_and_user_id: &SessionAndUserId) {
+        let mut sessions = self.sessions.write().unwrap();
+
+        if let Some(user_sessions) = sessions.get_mut(&session_and_user_id.user_id) {
+            user_sessions.remove(&session_and_user_id.session_id);
+
+            if user_sessions.is_empty() {
+                sessions.remove(&session_and_user_id.user_id);
+            }
+        }
+    }
+
+    /// Send a direct message to all sessions of the recipient, and to all sessions of the sender
+    /// so that every session of both users sees the conversation
+    ///
+    /// Only the copy sent to the session that has sent the message carries the request id.
+    pub fn send_direct_message(
+        &self,
+        sender: &SessionAndUserId,
+        display_name: &str,
+        recipient: &str,
+        content: String,
+        request_id: Option<String>,
+    ) -> Result<(), CommandError> {
+        let sessions = self.sessions.read().unwrap();
+        let Some(recipient_sessions) = sessions.get(recipient) else {
+            return Err(CommandError::new(
+                CommandErrorCode::UserOffline,
+                format!("user '{}' is not online", recipient),
+            ));
+        };
+
+        let event = DirectMessageEvent {
+            id: self.last_direct_message_id.fetch_add(1, Ordering::Relaxed) + 1,
+            timestamp: now_millis(),
+            user_id: sender.user_id.clone(),
+            display_name: String::from(display_name),
+            recipient: String::from(recipient),
+            content,
+            request_id: None,
+        };
+
+        let sender_sessions = sessions.get(&sender.user_id).into_iter().flatten();
+        for (session_id, tx) in recipient_sessions.iter().chain(sender_sessions) {
+            let event = if session_id == &sender.session_id {
+                DirectMessageEvent {
+                    request_id: request_id.clone(),
+                    ..event.clone()
+                }
+            } else {
+                event.clone()
+            };
+
+            // the lock is held while sending, so a session that can not keep up misses the message
+            // instead of blocking the sender
+            if let Err(TrySendError::Full(_)) = tx.try_send(Event::DirectMessage(event)) {
+                println!(
+                    "dropped direct message for session '{}', its channel is full",
+                    session_id
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(session_id: &str, user_id: &str) -> SessionAndUserId {
+        SessionAndUserId {
+            session_id: session_id.into(),
+            user_id: user_id.into(),
+        }
+    }
+
+    fn recv_direct_message(rx: &mut mpsc::Receiver<Event>) -> DirectMessageEvent {
+        match rx.try_recv() {
+            Ok(Event::DirectMessage(event)) => event,
+            other => panic!("expected a direct message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_direct_message_is_delivered_to_all_sessions() {
+        let registry = SessionRegistry::new();
+        let alice = session("s1", "alice");
+        let (alice_tx, mut alice_rx) = mpsc::channel(10);
+        let (bob_tx_1, mut bob_rx_1) = mpsc::channel(10);
+        let (bob_tx_2, mut bob_rx_2) = mpsc::channel(10);
+        registry.register(&alice, alice_tx);
+        registry.register(&session("s2", "bob"), bob_tx_1);
+        registry.register(&session("s3", "bob"), bob_tx_2);
+
+        registry
+            .send_direct_message(&alice, "Alice", "bob", "hi".into(), Some("r1".into()))
+            .unwrap();
+
+        let echoed = recv_direct_message(&mut alice_rx);
+        assert_eq!(echoed.request_id.as_deref(), Some("r1"));
+        for rx in [&mut bob_rx_1, &mut bob_rx_2] {
+            let received = recv_direct_message(rx);
+            assert_eq!(received.user_id, "alice");
+            assert_eq!(received.display_name, "Alice");
+            assert_eq!(received.recipient, "bob");
+            assert_eq!(received.content, "hi");
+            assert_eq!(received.id, echoed.id);
+            assert_eq!(received.request_id, None);
+        }
+    }
+
+    #[test]
+    fn test_direct_message_to_offline_user_is_rejected() {
+        let registry = SessionRegistry::new();
+        let alice = session("s1", "alice");
+        let bob = session("s2", "bob");
+        let (alice_tx, _alice_rx) = mpsc::channel(10);
+        let (bob_tx, _bob_rx) = mpsc::channel(10);
+        registry.register(&alice, alice_tx);
+        registry.register(&bob, bob_tx);
+        assert!(registry
+            .send_direct_message(&alice, "alice", "bob", "hi".into(), None)
+            .is_ok());
+
+        registry.unregister(&bob);
+        assert!(registry
+            .send_direct_message(&alice, "alice", "bob", "hi".into(), None)
+            .is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use std::{net::SocketAddr, time::Duration};
+
+use futures_util::{stream::SplitStream, SinkExt, StreamExt};
+use tokio::{
+    io::{
+        self, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, DuplexStream,
+        WriteHalf,
+    },
+    time,
+};
+use tokio_tungstenite::{
+    tungstenite::{protocol::WebSocketConfig, Message},
+    WebSocketStream,
+};
+
+/// Number of bytes buffered in each direction between a WebSocket connection and its session
+const BRIDGE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Runs the WebSocket handshake of a connection, and bridges it to a stream of JSON lines that the user session can be handled over
+/// Every text frame carries a single command or event, the messages above the maximum frame size are refused
+/// Returns `None` if the handshake fails or does not complete in time
+pub async fn accept<S>(
+    stream: S,
+    addr: SocketAddr,
+    max_frame_size: usize,
+    timeout: Duration,
+) -> Option<DuplexStream>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let config = WebSocketConfig::default()
+        .max_message_size(Some(max_frame_size))
+        .max_frame_size(Some(max_frame_size));
+
+    match time::timeout(
+        timeout,
+        tokio_tungstenite::accept_async_with_config(stream, Some(config)),
+    )
+    .await
+    {
+        Ok(Ok(websocket)) => {
+            let (session_stream, bridge_stream) = io::duplex(BRIDGE_BUFFER_SIZE);
+            tokio::spawn(bridge(websocket, bridge_stream));
+
+            Some(session_stream)
+        }
+        Ok(Err(err)) => {
+            println!("WebSocket handshake with {} failed: {}", addr, err);
+            None
+        }
+        Err(_) => {
+            println!("WebSocket handshake with {} timed out", addr);
+            None
+        }
+    }
+}
+
+/// Forwards the text frames of the user to the session as JSON lines, and the JSON lines of the session to the user as text frames
+/// Runs until the session ends, the events sent after the user has closed their side are still delivered
+async fn bridge<S>(websocket: WebSocketStream<S>, stream: DuplexStream)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut frame_sink, frames) = websocket.split();
+    let (reader, writer) = io::split(stream);
+
+    let events = async move {
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if frame_sink.send(Message::text(line)).await.is_err() {
+                return;
+            }
+        }
+
+        let _ = frame_sink.close().await;
+    };
+    tokio::pin!(events);
+
+    tokio::select! {
+        _ = forward_commands(frames, writer) => events.await,
+        _ = &mut events => {}
+    }
+}
+
+/// Writes the text frames of the user as JSON lines until the user closes the connection
+/// The session only speaks JSON to WebSocket users, a binary frame closes the connection
+async fn forward_commands<S>(
+    mut frames: SplitStream<WebSocketStream<S>>,
+    mut writer: WriteHalf<DuplexStream>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    while let Some(Ok(message)) = frames.next().await {
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Binary(_) | Message::Close(_) => break,
+            // the pings of the user are answered by the WebSocket implementation
+            _ => continue,
+        };
+
+        // line breaks can only be whitespace between the JSON tokens, since they are escaped in strings
+        let mut line = text.as_str().replace(['\r', '\n'], " ");
+        line.push('\n');
+        if writer.write_all(line.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+
+    let _ = writer.shutdown().await;
+}
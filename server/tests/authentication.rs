@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use comms::{
+    command::{self, UserCommand},
+    event::{CommandErrorCode, Event},
+    transport::{
+        self,
+        client::{CommandWriter, EventStream},
+    },
+};
+use tokio::{net::TcpStream, time::Instant};
+use tokio_stream::StreamExt;
+
+mod common;
+
+const PORT: u16 = 8094;
+const SHORT_IDLE_TIMEOUT_PORT: u16 = 8095;
+
+#[tokio::test]
+async fn assert_authentication_attempts_are_rate_limited() {
+    let _server = common::start_server("authentication-rate-limit", PORT, &[], &[]).await;
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        let (mut events, mut commands) = connect(PORT).await;
+
+        // the burst of attempts is answered, the next one has to wait for the budget to refill
+        for _ in 0..5 {
+            commands.write(&resume("not-a-token")).await.unwrap();
+            assert_error(
+                next_event(&mut events).await,
+                CommandErrorCode::InvalidResumeToken,
+            );
+        }
+
+        commands.write(&resume("not-a-token")).await.unwrap();
+        assert_error(next_event(&mut events).await, CommandErrorCode::RateLimited);
+    })
+    .await
+    .expect("the attempts were not answered in time");
+}
+
+#[tokio::test]
+async fn assert_pings_do_not_keep_unauthenticated_connections_open() {
+    let _server = common::start_server(
+        "authentication-deadline",
+        SHORT_IDLE_TIMEOUT_PORT,
+        &["--heartbeat-interval-secs", "1", "--idle-timeout-secs", "2"],
+        &[],
+    )
+    .await;
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        let (mut events, mut commands) = connect(SHORT_IDLE_TIMEOUT_PORT).await;
+        let connected_at = Instant::now();
+
+        let pinger = tokio::spawn(async move {
+            loop {
+                let ping = UserCommand::Ping(command::PingCommand {
+                    timestamp: 0,
+                    request_id: None,
+                });
+                if commands.write(&ping).await.is_err() {
+                    break;
+                }
+
+                tokio::time::sleep(Duration::from_millis(300)).await;
+            }
+        });
+
+        // the pongs keep coming until the connection is closed
+        while let Some(Ok(_)) = events.next().await {}
+
+        assert!(connected_at.elapsed() < Duration::from_secs(4));
+        pinger.abort();
+    })
+    .await
+    .expect("the connection was not closed in time");
+}
+
+async fn connect(port: u16) -> (EventStream, CommandWriter) {
+    let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+
+    transport::client::split_tcp_stream(stream)
+}
+
+fn resume(token: &str) -> UserCommand {
+    UserCommand::ResumeSession(command::ResumeSessionCommand {
+        token: token.into(),
+        request_id: None,
+    })
+}
+
+async fn next_event(events: &mut EventStream) -> Event {
+    match events.next().await {
+        Some(Ok(event)) => event,
+        other => panic!("the server did not send an event: {:?}", other),
+    }
+}
+
+fn assert_error(event: Event, code: CommandErrorCode) {
+    match event {
+        Event::CommandError(err) => assert_eq!(err.code, code),
+        other => panic!("the command was not refused: {:?}", other),
+    }
+}
@@ -0,0 +1,37 @@
+use std::{process::Stdio, time::Duration};
+
+use tokio::{
+    net::TcpStream,
+    process::{Child, Command},
+};
+
+/// Starts the server on the TCP port with a fresh accounts file, and waits until it accepts connections on every given port
+/// The server is killed once the returned child is dropped
+pub async fn start_server(name: &str, tcp_port: u16, args: &[&str], ports: &[u16]) -> Child {
+    let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let server = Command::new(env!("CARGO_BIN_EXE_server"))
+        .args(["--bind-address", "127.0.0.1"])
+        .args(["--port", &tcp_port.to_string()])
+        .arg("--accounts-file")
+        .arg(dir.join("accounts.jsonl"))
+        .args(args)
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("could not start the server");
+
+    for port in std::iter::once(&tcp_port).chain(ports) {
+        let mut attempts = 0;
+        while TcpStream::connect(("127.0.0.1", *port)).await.is_err() {
+            attempts += 1;
+            assert!(attempts < 100, "the server did not start listening");
+
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    server
+}
@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+use comms::{
+    command::{self, UserCommand},
+    event::{CommandErrorCode, Event},
+    transport::{
+        self,
+        client::{CommandWriter, EventStream},
+    },
+};
+use tokio::net::TcpStream;
+use tokio_stream::StreamExt;
+
+mod common;
+
+const PORT: u16 = 8092;
+const SHORT_GRACE_PERIOD_PORT: u16 = 8093;
+const ROOM: &str = "general";
+
+#[tokio::test]
+async fn assert_lost_session_is_resumed_with_missed_events() {
+    let _server = common::start_server("session-resume", PORT, &[], &[]).await;
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        let (mut alice_events, mut alice_commands) = connect(PORT).await;
+        let resume_token = login(&mut alice_events, &mut alice_commands, "alice").await;
+        join_room(&mut alice_events, &mut alice_commands).await;
+
+        // alice loses her connection, while bob keeps chatting in the room
+        drop((alice_events, alice_commands));
+
+        let (mut bob_events, mut bob_commands) = connect(PORT).await;
+        login(&mut bob_events, &mut bob_commands, "bob").await;
+        join_room(&mut bob_events, &mut bob_commands).await;
+        bob_commands
+            .write(&UserCommand::SendMessage(command::SendMessageCommand {
+                room: ROOM.into(),
+                content: "are you still there?".into(),
+                request_id: None,
+            }))
+            .await
+            .unwrap();
+
+        // alice is back in her rooms under the same user and session, and gets the message she has missed
+        let (mut alice_events, mut alice_commands) = connect(PORT).await;
+        alice_commands.write(&resume(&resume_token)).await.unwrap();
+        let resumed = match next_event(&mut alice_events).await {
+            Event::SessionResumed(resumed) => resumed,
+            other => panic!("the session was not resumed: {:?}", other),
+        };
+        assert_eq!(resumed.user_id, "alice");
+        assert_eq!(resumed.joined_rooms, vec![ROOM.to_string()]);
+        assert_ne!(resumed.resume_token, resume_token);
+
+        loop {
+            match next_event(&mut alice_events).await {
+                Event::UserMessage(message) => {
+                    assert_eq!(message.user_id, "bob");
+                    assert_eq!(message.content, "are you still there?");
+                    break;
+                }
+                _ => continue,
+            }
+        }
+
+        // a token can only be used once
+        let (mut events, mut commands) = connect(PORT).await;
+        commands.write(&resume(&resume_token)).await.unwrap();
+        assert_invalid_resume_token(next_event(&mut events).await);
+    })
+    .await
+    .expect("the session was not resumed in time");
+}
+
+#[tokio::test]
+async fn assert_session_expires_after_the_grace_period() {
+    let _server = common::start_server(
+        "session-expiry",
+        SHORT_GRACE_PERIOD_PORT,
+        &["--resume-grace-period-secs", "1"],
+        &[],
+    )
+    .await;
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        let (mut events, mut commands) = connect(SHORT_GRACE_PERIOD_PORT).await;
+        let resume_token = login(&mut events, &mut commands, "alice").await;
+        drop((events, commands));
+
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+
+        let (mut events, mut commands) = connect(SHORT_GRACE_PERIOD_PORT).await;
+        commands.write(&resume(&resume_token)).await.unwrap();
+        assert_invalid_resume_token(next_event(&mut events).await);
+    })
+    .await
+    .expect("the session did not expire in time");
+}
+
+async fn connect(port: u16) -> (EventStream, CommandWriter) {
+    let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+
+    transport::client::split_tcp_stream(stream)
+}
+
+fn resume(token: &str) -> UserCommand {
+    UserCommand::ResumeSession(command::ResumeSessionCommand {
+        token: token.into(),
+        request_id: None,
+    })
+}
+
+/// Registers the user, and returns the resume token of the session
+async fn login(events: &mut EventStream, commands: &mut CommandWriter, username: &str) -> String {
+    commands
+        .write(&UserCommand::Register(command::RegisterCommand {
+            username: username.into(),
+            password: "correct horse battery staple".into(),
+            request_id: None,
+        }))
+        .await
+        .unwrap();
+
+    match next_event(events).await {
+        Event::LoginSuccessful(login) => login.resume_token.expect("no resume token"),
+        other => panic!("the user could not register: {:?}", other),
+    }
+}
+
+async fn join_room(events: &mut EventStream, commands: &mut CommandWriter) {
+    commands
+        .write(&UserCommand::JoinRoom(command::JoinRoomCommand {
+            room: ROOM.into(),
+            request_id: None,
+        }))
+        .await
+        .unwrap();
+
+    loop {
+        match next_event(events).await {
+            Event::UserJoinedRoom(_) => break,
+            Event::CommandError(err) => panic!("{:?}", err),
+            _ => continue,
+        }
+    }
+}
+
+async fn next_event(events: &mut EventStream) -> Event {
+    match events.next().await {
+        Some(Ok(event)) => event,
+        other => panic!("the server did not send an event: {:?}", other),
+    }
+}
+
+fn assert_invalid_resume_token(event: Event) {
+    match event {
+        Event::CommandError(err) => assert_eq!(err.code, CommandErrorCode::InvalidResumeToken),
+        other => panic!("the session was resumed: {:?}", other),
+    }
+}
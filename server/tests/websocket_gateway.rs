@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+use comms::{
+    command::{self, UserCommand},
+    event::Event,
+    transport::{
+        self,
+        client::{CommandWriter, EventStream},
+    },
+};
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+mod common;
+
+const TCP_PORT: u16 = 8090;
+const WEBSOCKET_PORT: u16 = 8091;
+const ROOM: &str = "general";
+
+type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[tokio::test]
+async fn assert_websocket_and_tcp_clients_share_rooms() {
+    let _server = common::start_server(
+        "websocket-gateway",
+        TCP_PORT,
+        &["--websocket-port", &WEBSOCKET_PORT.to_string()],
+        &[WEBSOCKET_PORT],
+    )
+    .await;
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        // a terminal client over TCP and a browser client over WebSocket join the same room
+        let (mut events, mut commands) = connect_tcp_client("alice").await;
+        let mut websocket = connect_websocket_client("bob").await;
+
+        send_over_websocket(
+            &mut websocket,
+            &UserCommand::SendMessage(command::SendMessageCommand {
+                room: ROOM.into(),
+                content: "hello from the browser".into(),
+                request_id: None,
+            }),
+        )
+        .await;
+        loop {
+            match events.next().await {
+                Some(Ok(Event::UserMessage(message))) => {
+                    assert_eq!(message.user_id, "bob");
+                    assert_eq!(message.content, "hello from the browser");
+                    break;
+                }
+                Some(Ok(_)) => continue,
+                other => panic!("the TCP client did not receive the message: {:?}", other),
+            }
+        }
+
+        commands
+            .write(&UserCommand::SendMessage(command::SendMessageCommand {
+                room: ROOM.into(),
+                content: "hello from the terminal".into(),
+                request_id: None,
+            }))
+            .await
+            .unwrap();
+        loop {
+            match next_websocket_event(&mut websocket).await {
+                Event::UserMessage(message) if message.user_id == "alice" => {
+                    assert_eq!(message.content, "hello from the terminal");
+                    break;
+                }
+                _ => continue,
+            }
+        }
+    })
+    .await
+    .expect("the clients did not exchange the messages in time");
+}
+
+fn register(username: &str) -> UserCommand {
+    UserCommand::Register(command::RegisterCommand {
+        username: username.into(),
+        password: "correct horse battery staple".into(),
+        request_id: None,
+    })
+}
+
+fn join_room() -> UserCommand {
+    UserCommand::JoinRoom(command::JoinRoomCommand {
+        room: ROOM.into(),
+        request_id: None,
+    })
+}
+
+async fn connect_tcp_client(username: &str) -> (EventStream, CommandWriter) {
+    let stream = TcpStream::connect(("127.0.0.1", TCP_PORT)).await.unwrap();
+    let (mut events, mut commands) = transport::client::split_tcp_stream(stream);
+
+    commands.write(&register(username)).await.unwrap();
+    commands.write(&join_room()).await.unwrap();
+    loop {
+        match events.next().await {
+            Some(Ok(Event::UserJoinedRoom(_))) => break,
+            Some(Ok(Event::CommandError(err))) => panic!("{:?}", err),
+            Some(Ok(_)) => continue,
+            other => panic!("the TCP client could not join the room: {:?}", other),
+        }
+    }
+
+    (events, commands)
+}
+
+async fn connect_websocket_client(username: &str) -> WebSocket {
+    let (mut websocket, _response) =
+        tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{}", WEBSOCKET_PORT))
+            .await
+            .unwrap();
+
+    send_over_websocket(&mut websocket, &register(username)).await;
+    send_over_websocket(&mut websocket, &join_room()).await;
+    loop {
+        match next_websocket_event(&mut websocket).await {
+            Event::UserJoinedRoom(_) => break,
+            Event::CommandError(err) => panic!("{:?}", err),
+            _ => continue,
+        }
+    }
+
+    websocket
+}
+
+async fn send_over_websocket(websocket: &mut WebSocket, command: &UserCommand) {
+    websocket
+        .send(Message::text(serde_json::to_string(command).unwrap()))
+        .await
+        .unwrap();
+}
+
+/// Reads the next event sent by the server, every text frame holds a single event
+async fn next_websocket_event(websocket: &mut WebSocket) -> Event {
+    loop {
+        match websocket.next().await {
+            Some(Ok(Message::Text(text))) => return serde_json::from_str(text.as_str()).unwrap(),
+            Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+            other => panic!("the WebSocket client did not receive an event: {:?}", other),
+        }
+    }
+}
@@ -1,7 +1,38 @@
 #[derive(Debug, Clone)]
 pub enum Action {
-    ConnectToServerRequest { addr: String },
-    SendMessage { content: String },
-    SelectRoom { room: String },
+    ConnectToServerRequest {
+        addr: String,
+        username: String,
+        password: String,
+        /// Create a new account with the credentials instead of logging in
+        register: bool,
+        /// CA file or certificate fingerprint to trust the server with, the public CAs are trusted if it is empty
+        tls_trust: String,
+    },
+    SendMessage {
+        content: String,
+    },
+    SendDirectMessage {
+        user_id: String,
+        content: String,
+    },
+    SelectRoom {
+        room: String,
+    },
+    LoadOlderMessages,
+    /// The user has started typing a message to the active room, or keeps typing it
+    TypingStarted,
+    /// The user has stopped typing without sending the message
+    TypingStopped,
+    SetNickname {
+        nickname: String,
+    },
+    CreateRoom {
+        room: String,
+        description: String,
+    },
+    DeleteRoom {
+        room: String,
+    },
     Exit,
 }
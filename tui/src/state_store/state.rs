@@ -1,15 +1,44 @@
-use std::collections::{HashMap, HashSet};
+use chrono::Utc;
+use comms::event;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
 use std::fmt::Formatter;
-use circular_queue::CircularQueue;
-use comms::event;
 
 #[derive(Debug, Clone)]
 pub enum MessageBoxItem {
-    Message { user_id: String, content: String },
+    Message {
+        /// The id of the message, none for a message which is not recorded in the history
+        id: Option<u64>,
+        /// Server time of the message in milliseconds since the Unix epoch
+        timestamp: u64,
+        user_id: String,
+        content: String,
+    },
     Notification(String),
 }
 
+impl From<&event::UserMessageBroadcastEvent> for MessageBoxItem {
+    fn from(event: &event::UserMessageBroadcastEvent) -> Self {
+        MessageBoxItem::Message {
+            id: (!event.unrecorded).then_some(event.id),
+            timestamp: event.timestamp,
+            user_id: event.user_id.clone(),
+            content: event.content.clone(),
+        }
+    }
+}
+
+impl From<&event::DirectMessageEvent> for MessageBoxItem {
+    fn from(event: &event::DirectMessageEvent) -> Self {
+        MessageBoxItem::Message {
+            id: Some(event.id),
+            timestamp: event.timestamp,
+            user_id: event.user_id.clone(),
+            content: event.content.clone(),
+        }
+    }
+}
+
 const MAX_MESSAGES_TO_STORE_PER_ROOM: usize = 100;
 
 /// RoomData holds the data for a room
@@ -21,12 +50,18 @@ pub struct RoomData {
     pub description: String,
     /// List of users in the room
     pub users: HashSet<String>,
-    /// History of recorded messages
-    pub messages: CircularQueue<MessageBoxItem>,
+    /// Users typing a message to the room, other than the user
+    pub typing_users: HashSet<String>,
+    /// History of recorded messages, ordered oldest to newest
+    pub messages: VecDeque<MessageBoxItem>,
+    /// Cursor to load the older messages of the room with, if there are any
+    pub history_cursor: Option<u64>,
     /// Has joined the room
     pub has_joined: bool,
     /// Has unread messages
     pub has_unread: bool,
+    /// The user the conversation is with, if this is a direct message conversation instead of a room
+    pub direct_message_user_id: Option<String>,
 }
 
 impl Default for RoomData {
@@ -35,9 +70,12 @@ impl Default for RoomData {
             name: String::new(),
             description: String::new(),
             users: HashSet::new(),
-            messages: CircularQueue::with_capacity(MAX_MESSAGES_TO_STORE_PER_ROOM),
+            typing_users: HashSet::new(),
+            messages: VecDeque::with_capacity(MAX_MESSAGES_TO_STORE_PER_ROOM),
+            history_cursor: None,
             has_joined: false,
             has_unread: false,
+            direct_message_user_id: None,
         }
     }
 }
@@ -50,14 +88,83 @@ impl RoomData {
             ..Default::default()
         }
     }
+
+    /// Creates the conversation of direct messages between the user and the given other user
+    /// It is listed along with the rooms, under a name that can not clash with a room name
+    pub fn direct_message(own_user_id: &str, user_id: &str) -> Self {
+        RoomData {
+            name: direct_message_room_name(user_id),
+            description: format!("Direct messages with @{}", user_id),
+            users: HashSet::from([String::from(own_user_id), String::from(user_id)]),
+            has_joined: true,
+            direct_message_user_id: Some(String::from(user_id)),
+            ..Default::default()
+        }
+    }
+
+    /// Push a new item to the end of the message list, dropping the oldest item if the list is full
+    pub fn push_message(&mut self, item: MessageBoxItem) {
+        self.messages.push_back(item);
+
+        if self.messages.len() > MAX_MESSAGES_TO_STORE_PER_ROOM {
+            self.messages.pop_front();
+            // the dropped message can not be re-fetched with the cursor anymore
+            self.history_cursor = None;
+        }
+    }
+
+    /// Push older items to the front of the message list, as long as the list is not full
+    /// Returns whether all of the items fit, the rest of the older messages can not be loaded then
+    fn prepend_messages(&mut self, items: impl DoubleEndedIterator<Item = MessageBoxItem>) -> bool {
+        for item in items.rev() {
+            if self.messages.len() >= MAX_MESSAGES_TO_STORE_PER_ROOM {
+                return false;
+            }
+
+            self.messages.push_front(item);
+        }
+
+        true
+    }
+
+    /// The id of the latest message that is stored for the room
+    fn last_message_id(&self) -> Option<u64> {
+        self.messages.iter().rev().find_map(|item| match item {
+            MessageBoxItem::Message { id, .. } => *id,
+            MessageBoxItem::Notification(_) => None,
+        })
+    }
+}
+
+/// The name of the conversation of direct messages with the given user
+/// Room names can not contain '@', so it never clashes with a room name
+pub fn direct_message_room_name(user_id: &str) -> String {
+    format!("@{}", user_id)
+}
+
+/// Current local time in milliseconds since the Unix epoch
+pub fn now_millis() -> u64 {
+    Utc::now().timestamp_millis() as u64
 }
 
 #[derive(Debug, Clone)]
 pub enum ServerConnectionStatus {
     Uninitialized,
     Connecting,
-    Connected { addr: String },
-    Errored { err: String },
+    Authenticating {
+        addr: String,
+    },
+    Connected {
+        addr: String,
+    },
+    /// The connection was lost, the rooms and messages are kept while reconnecting
+    Reconnecting {
+        addr: String,
+        attempt: u32,
+    },
+    Errored {
+        err: String,
+    },
 }
 
 impl fmt::Display for ServerConnectionStatus {
@@ -65,7 +172,13 @@ impl fmt::Display for ServerConnectionStatus {
         match self {
             ServerConnectionStatus::Uninitialized => write!(f, "Uninitialized"),
             ServerConnectionStatus::Connecting => write!(f, "Connecting"),
+            ServerConnectionStatus::Authenticating { addr } => {
+                write!(f, "Authenticating with {}", addr)
+            }
             ServerConnectionStatus::Connected { addr } => write!(f, "Connected to {}", addr),
+            ServerConnectionStatus::Reconnecting { addr, attempt } => {
+                write!(f, "Reconnecting… to {} (attempt {})", addr, attempt)
+            }
             ServerConnectionStatus::Errored { err } => write!(f, "Errored: {}", err),
         }
     }
@@ -79,8 +192,14 @@ pub struct State {
     pub active_room: Option<String>,
     /// The id of the user
     pub user_id: String,
+    /// The token to resume the session with if the connection is lost, if the server has handed out one
+    pub resume_token: Option<String>,
+    /// Names to display for the users seen so far, by user id
+    pub display_names: HashMap<String, String>,
     /// Storage of room data
     pub room_data_map: HashMap<String, RoomData>,
+    /// Round-trip time of the latest ping to the server, in milliseconds
+    pub latency_ms: Option<u64>,
     /// Timer since app was opened
     pub timer: usize,
 }
@@ -91,7 +210,10 @@ impl Default for State {
             server_connection_status: ServerConnectionStatus::Uninitialized,
             active_room: None,
             user_id: String::new(),
+            resume_token: None,
+            display_names: HashMap::new(),
             room_data_map: HashMap::new(),
+            latency_ms: None,
             timer: 0,
         }
     }
@@ -101,31 +223,115 @@ impl State {
     pub fn handle_server_event(&mut self, event: &event::Event) {
         match event {
             event::Event::LoginSuccessful(event) => {
+                let reconnected = matches!(
+                    self.server_connection_status,
+                    ServerConnectionStatus::Reconnecting { .. }
+                );
+                self.mark_authenticated();
+
                 self.user_id = event.user_id.clone();
-                self.room_data_map = event
-                    .rooms
-                    .clone()
-                    .into_iter()
-                    .map(|r| (r.name.clone(), RoomData::new(r.name, r.description)))
-                    .collect();
-            }
+                self.resume_token = event.resume_token.clone();
+                self.display_names
+                    .insert(event.user_id.clone(), event.display_name.clone());
 
-            event::Event::RoomHistory(history_event) => {
-            if let Some(room_data) = self.room_data_map.get_mut(&history_event.room) {
-                // ล้างของเก่าก่อน
-                room_data.messages.clear();
-
-                // เติม history เข้า CircularQueue
-                for msg in &history_event.messages {
-                    room_data.messages.push(MessageBoxItem::Message {
-                        user_id: msg.user_id.clone(),
-                        content: msg.content.clone(),
+                if reconnected {
+                    // the messages are kept until the history of the rooms joined again replaces them
+                    self.room_data_map.retain(|name, room_data| {
+                        room_data.direct_message_user_id.is_some()
+                            || event.rooms.iter().any(|room| room.name.eq(name))
                     });
+                    for room in event.rooms.iter() {
+                        let room_data =
+                            self.room_data_map
+                                .entry(room.name.clone())
+                                .or_insert_with(|| {
+                                    RoomData::new(room.name.clone(), room.description.clone())
+                                });
+                        room_data.has_joined = false;
+                        room_data.typing_users.clear();
+                    }
+                    // the room the user was looking at may have been deleted in the meantime
+                    if self
+                        .active_room
+                        .as_ref()
+                        .is_some_and(|active_room| !self.room_data_map.contains_key(active_room))
+                    {
+                        self.active_room = None;
+                    }
+                } else {
+                    self.room_data_map = event
+                        .rooms
+                        .clone()
+                        .into_iter()
+                        .map(|r| (r.name.clone(), RoomData::new(r.name, r.description)))
+                        .collect();
                 }
+            }
+            event::Event::SessionResumed(event) => {
+                self.mark_authenticated();
+
+                self.user_id = event.user_id.clone();
+                self.resume_token = Some(event.resume_token.clone());
+                self.display_names
+                    .insert(event.user_id.clone(), event.display_name.clone());
+
+                // the messages received before the connection was lost are kept, the missed events follow
+                for room in event.rooms.iter() {
+                    self.room_data_map
+                        .entry(room.name.clone())
+                        .or_insert_with(|| {
+                            RoomData::new(room.name.clone(), room.description.clone())
+                        });
+                }
+                for (name, room_data) in self.room_data_map.iter_mut() {
+                    if room_data.direct_message_user_id.is_none() {
+                        room_data.has_joined = event.joined_rooms.contains(name);
+                    }
+                    // the users still typing announce it again within a few seconds
+                    room_data.typing_users.clear();
                 }
             }
 
+            event::Event::RoomHistory(history_event) => {
+                if let Some(room_data) = self.room_data_map.get_mut(&history_event.room) {
+                    let messages = history_event.messages.iter().map(MessageBoxItem::from);
+
+                    if history_event.before.is_none() {
+                        // the most recent page replaces whatever was stored before
+                        room_data.messages.clear();
+                    }
+
+                    // an older page is prepended in front of the loaded messages, up to the stored limit
+                    let has_fit = room_data.prepend_messages(messages);
+                    room_data.history_cursor = history_event.next_cursor.filter(|_| has_fit);
+                }
+            }
+
+            event::Event::RoomResync(event) => {
+                if let Some(room_data) = self.room_data_map.get_mut(&event.room) {
+                    for message in event.messages.iter() {
+                        if room_data
+                            .last_message_id()
+                            .is_some_and(|last_message_id| last_message_id >= message.id)
+                        {
+                            continue;
+                        }
+
+                        room_data.push_message(MessageBoxItem::from(message));
+                    }
+
+                    // other events such as users joining or leaving can not be recovered
+                    room_data.push_message(MessageBoxItem::Notification(format!(
+                        "Fell behind the room and missed {} events, {} messages were recovered",
+                        event.skipped,
+                        event.messages.len()
+                    )));
+                }
+            }
             event::Event::RoomParticipation(event) => {
+                self.display_names
+                    .insert(event.user_id.clone(), event.display_name.clone());
+
                 if let Some(room_data) = self.room_data_map.get_mut(&event.room) {
                     match event.status {
                         event::RoomParticipationStatus::Joined => {
@@ -136,35 +342,60 @@ impl State {
                         }
                         event::RoomParticipationStatus::Left => {
                             room_data.users.remove(&event.user_id);
+                            room_data.typing_users.remove(&event.user_id);
                             if event.user_id == self.user_id {
                                 room_data.has_joined = false;
                             }
                         }
                     }
 
-                    room_data
-                        .messages
-                        .push(MessageBoxItem::Notification(format!(
-                            "{} has {} the room",
-                            event.user_id,
-                            match event.status {
-                                event::RoomParticipationStatus::Joined => "joined",
-                                event::RoomParticipationStatus::Left => "left",
-                            }
-                        )));
+                    room_data.push_message(MessageBoxItem::Notification(format!(
+                        "{} has {} the room",
+                        event.display_name,
+                        match event.status {
+                            event::RoomParticipationStatus::Joined => "joined",
+                            event::RoomParticipationStatus::Left => "left",
+                        }
+                    )));
                 }
             }
             event::Event::UserJoinedRoom(event) => {
-                self.room_data_map.get_mut(&event.room).unwrap().users =
-                    event.users.clone().into_iter().collect();
+                for user in event.users.iter() {
+                    self.display_names
+                        .insert(user.user_id.clone(), user.display_name.clone());
+                }
+
+                // the room may have been deleted meanwhile
+                let Some(room_data) = self.room_data_map.get_mut(&event.room) else {
+                    return;
+                };
+                room_data.users = event
+                    .users
+                    .iter()
+                    .map(|user| user.user_id.clone())
+                    .collect();
+                // the participation event is only broadcast for the first session of a user,
+                // so the reply is what tells this session that it has joined
+                room_data.has_joined = true;
             }
             event::Event::UserMessage(event) => {
-                let room_data = self.room_data_map.get_mut(&event.room).unwrap();
+                let Some(room_data) = self.room_data_map.get_mut(&event.room) else {
+                    return;
+                };
+
+                // a message may arrive both as part of the history and as a broadcast right after joining,
+                // a message only shown to its sender is not recorded, its id is not its own
+                if !event.unrecorded
+                    && room_data
+                        .last_message_id()
+                        .is_some_and(|last_message_id| last_message_id >= event.id)
+                {
+                    return;
+                }
 
-                room_data.messages.push(MessageBoxItem::Message {
-                    user_id: event.user_id.clone(),
-                    content: event.content.clone(),
-                });
+                room_data.push_message(MessageBoxItem::from(event));
+                // the message ends the typing of its sender
+                room_data.typing_users.remove(&event.user_id);
 
                 if let Some(active_room) = self.active_room.as_ref() {
                     if !active_room.eq(&event.room) {
@@ -172,19 +403,217 @@ impl State {
                     }
                 }
             }
+            event::Event::DirectMessage(event) => {
+                self.display_names
+                    .insert(event.user_id.clone(), event.display_name.clone());
+
+                // the messages sent by the user are echoed back, those belong to the recipient's conversation
+                let other_user_id = if event.user_id == self.user_id {
+                    &event.recipient
+                } else {
+                    &event.user_id
+                };
+                let room = direct_message_room_name(other_user_id);
+                let room_data = self
+                    .room_data_map
+                    .entry(room.clone())
+                    .or_insert_with(|| RoomData::direct_message(&self.user_id, other_user_id));
+
+                room_data.push_message(MessageBoxItem::from(event));
+
+                if self.active_room.as_ref() != Some(&room) {
+                    room_data.has_unread = true;
+                }
+            }
+            event::Event::NicknameChanged(event) => {
+                self.display_names
+                    .insert(event.user_id.clone(), event.nickname.clone());
+
+                // the reply without a room only updates the name, the rooms get their own broadcast
+                let room_data = event
+                    .room
+                    .as_ref()
+                    .and_then(|room| self.room_data_map.get_mut(room));
+                if let Some(room_data) = room_data {
+                    room_data.push_message(MessageBoxItem::Notification(format!(
+                        "@{} is now known as {}",
+                        event.user_id, event.nickname
+                    )));
+                }
+            }
+            event::Event::RoomCreated(event) => {
+                self.room_data_map
+                    .entry(event.room.name.clone())
+                    .or_insert_with(|| {
+                        RoomData::new(event.room.name.clone(), event.room.description.clone())
+                    });
+            }
+            event::Event::RoomDeleted(event) => {
+                self.room_data_map.remove(&event.room);
+
+                if self.active_room.as_ref() == Some(&event.room) {
+                    self.active_room = None;
+                }
+            }
+            event::Event::UserModerated(event) => {
+                if let Some(room_data) = self.room_data_map.get_mut(&event.room) {
+                    // the sessions of a kicked or banned user are removed from the room by the server
+                    if matches!(
+                        event.action,
+                        event::ModerationAction::Kicked | event::ModerationAction::Banned
+                    ) {
+                        room_data.users.remove(&event.user_id);
+                        room_data.typing_users.remove(&event.user_id);
+                        if event.user_id == self.user_id {
+                            room_data.has_joined = false;
+                        }
+                    }
+
+                    let display_name = |user_id: &String| {
+                        self.display_names
+                            .get(user_id)
+                            .cloned()
+                            .unwrap_or_else(|| user_id.clone())
+                    };
+                    let action = match event.action {
+                        event::ModerationAction::Kicked => "kicked",
+                        event::ModerationAction::Banned => "banned",
+                        event::ModerationAction::Unbanned => "unbanned",
+                        event::ModerationAction::Muted => "muted",
+                        event::ModerationAction::Unmuted => "unmuted",
+                    };
+                    let until = event
+                        .expires_at
+                        .map(|expires_at| {
+                            format!(
+                                " for {} secs",
+                                expires_at.saturating_sub(now_millis()).div_ceil(1000)
+                            )
+                        })
+                        .unwrap_or_default();
+
+                    room_data.push_message(MessageBoxItem::Notification(format!(
+                        "{} was {} by {}{}",
+                        display_name(&event.user_id),
+                        action,
+                        display_name(&event.moderator_id),
+                        until
+                    )));
+                }
+            }
+            event::Event::UserTyping(event) => {
+                let room_data = self.room_data_map.get_mut(&event.room);
+
+                if let Some(room_data) = room_data.filter(|_| event.user_id != self.user_id) {
+                    if event.typing {
+                        room_data.typing_users.insert(event.user_id.clone());
+                    } else {
+                        room_data.typing_users.remove(&event.user_id);
+                    }
+                }
+            }
+            // the ping was sent with the local time, see [now_millis]
+            event::Event::Pong(event) => {
+                self.latency_ms = Some(now_millis().saturating_sub(event.timestamp));
+            }
+            // the state store answers the pings of the server, and waits for the hello reply before logging in
+            event::Event::Ping(_) | event::Event::Hello(_) => {}
+            // the server has forgotten the session, the state store logs in again instead
+            event::Event::CommandError(event)
+                if event.code == event::CommandErrorCode::InvalidResumeToken =>
+            {
+                self.resume_token = None;
+            }
+            // the server refused the credentials, the error is shown on the connect page
+            event::Event::CommandError(event)
+                if matches!(
+                    self.server_connection_status,
+                    ServerConnectionStatus::Authenticating { .. }
+                        | ServerConnectionStatus::Reconnecting { .. }
+                ) =>
+            {
+                self.server_connection_status = ServerConnectionStatus::Errored {
+                    err: event.message.clone(),
+                };
+            }
+            event::Event::CommandError(event) => {
+                // errors without a room are shown in the room the user is looking at
+                let room = event.room.as_ref().or(self.active_room.as_ref());
+
+                if let Some(room_data) = room.and_then(|room| self.room_data_map.get_mut(room)) {
+                    room_data.push_message(MessageBoxItem::Notification(format!(
+                        "Error: {}",
+                        event.message
+                    )));
+                }
+            }
+        }
+    }
+
+    /// Marks the connection as connected once the server has accepted the credentials or the resume token
+    fn mark_authenticated(&mut self) {
+        if let ServerConnectionStatus::Authenticating { addr }
+        | ServerConnectionStatus::Reconnecting { addr, .. } = &self.server_connection_status
+        {
+            self.server_connection_status =
+                ServerConnectionStatus::Connected { addr: addr.clone() };
         }
     }
 
+    /// Marks the connection to the given address as lost. The rooms and messages are kept while reconnecting,
+    /// unless the user was not logged in yet or too many attempts have failed, then the state is reset.
+    /// Returns the number of the reconnection attempt to make, if any
+    pub fn mark_connection_lost(&mut self, addr: &str, max_attempts: u32) -> Option<u32> {
+        let attempt = match &self.server_connection_status {
+            ServerConnectionStatus::Connected { .. } => 1,
+            ServerConnectionStatus::Reconnecting { attempt, .. } if *attempt < max_attempts => {
+                attempt + 1
+            }
+            ServerConnectionStatus::Reconnecting { .. } => {
+                *self = State {
+                    server_connection_status: ServerConnectionStatus::Errored {
+                        err: format!("could not reconnect to {}", addr),
+                    },
+                    ..State::default()
+                };
+
+                return None;
+            }
+            _ => {
+                *self = State::default();
+
+                return None;
+            }
+        };
+
+        self.server_connection_status = ServerConnectionStatus::Reconnecting {
+            addr: String::from(addr),
+            attempt,
+        };
+
+        Some(attempt)
+    }
+
+    /// Returns the rooms the user has joined, without the direct message conversations
+    pub fn joined_rooms(&self) -> Vec<String> {
+        self.room_data_map
+            .values()
+            .filter(|room_data| room_data.has_joined && room_data.direct_message_user_id.is_none())
+            .map(|room_data| room_data.name.clone())
+            .collect()
+    }
+
     pub fn mark_connection_request_start(&mut self) {
         self.server_connection_status = ServerConnectionStatus::Connecting;
     }
 
     /// Processes the result of a connection request to change the state of the application
+    /// The user is authenticated once the server accepts the credentials sent after connecting
     pub fn process_connection_request_result(&mut self, result: anyhow::Result<String>) {
         self.server_connection_status = match result {
-            Ok(addr) => ServerConnectionStatus::Connected { addr: addr.clone() },
+            Ok(addr) => ServerConnectionStatus::Authenticating { addr: addr.clone() },
             Err(err) => ServerConnectionStatus::Errored {
-                err: err.to_string(),
+                err: format!("{:#}", err),
             },
         }
     }
@@ -199,6 +628,28 @@ impl State {
         Some(room_data)
     }
 
+    /// Returns the active room if the user has joined it, direct message conversations are not rooms on the server
+    pub fn active_joined_room(&self) -> Option<&str> {
+        let active_room = self.active_room.as_ref()?;
+
+        self.room_data_map
+            .get(active_room)
+            .filter(|room_data| room_data.has_joined && room_data.direct_message_user_id.is_none())
+            .map(|_| active_room.as_str())
+    }
+
+    /// Takes the cursor for loading older messages of the active room, if there is one.
+    /// Returns the room name and the cursor, the cursor is given back with the next history page.
+    pub fn take_active_room_history_cursor(&mut self) -> Option<(String, u64)> {
+        let active_room = self.active_room.as_ref()?;
+        let room_data = self.room_data_map.get_mut(active_room)?;
+
+        room_data
+            .history_cursor
+            .take()
+            .map(|cursor| (active_room.clone(), cursor))
+    }
+
     pub fn tick_timer(&mut self) {
         self.timer += 1;
     }
@@ -2,10 +2,12 @@ use std::time::Duration;
 
 use anyhow::Context;
 use comms::{
-    command,
+    command, event, protocol,
     transport::{
         self,
         client::{CommandWriter, EventStream},
+        tls::{self, ServerTrust},
+        Codec, DEFAULT_MAX_FRAME_SIZE,
     },
 };
 use tokio::{
@@ -14,12 +16,28 @@ use tokio::{
         broadcast,
         mpsc::{self, UnboundedReceiver, UnboundedSender},
     },
+    time::Instant,
 };
 use tokio_stream::StreamExt;
 
 use crate::{Interrupted, Terminator};
 
-use super::{action::Action, State};
+use super::{action::Action, state::now_millis, ServerConnectionStatus, State};
+
+/// How often the server is pinged to measure the round-trip latency
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+/// Environment variable to pick the codec of the connection with, JSON lines are used unless it is set
+const CODEC_ENV_VAR: &str = "CHAT_CODEC";
+/// Prefix of the server addresses to connect to with TLS
+const TLS_ADDR_PREFIX: &str = "tls://";
+/// Delay before the first attempt to reconnect, doubled after every failed attempt
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+/// Upper bound of the delay between two attempts to reconnect
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+/// How many attempts to reconnect are made before giving up and going back to the connect page
+const MAX_RECONNECT_ATTEMPTS: u32 = 10;
+/// How long a single attempt to reconnect may take
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct StateStore {
     state_tx: UnboundedSender<State>,
@@ -35,9 +53,99 @@ impl StateStore {
 
 type ServerHandle = (EventStream, CommandWriter);
 
-async fn create_server_handle(addr: &str) -> anyhow::Result<ServerHandle> {
+/// The parameters of the latest connection request, kept to reconnect with once the connection is lost
+#[derive(Clone)]
+struct ConnectionRequest {
+    addr: String,
+    tls_trust: String,
+    username: String,
+    password: String,
+}
+
+impl ConnectionRequest {
+    /// The account exists once the first connection succeeded, so reconnecting always logs in
+    fn login_command(&self) -> command::UserCommand {
+        command::UserCommand::Login(command::LoginCommand {
+            username: self.username.clone(),
+            password: self.password.clone(),
+            request_id: None,
+        })
+    }
+}
+
+/// The delay before the given attempt to reconnect, growing exponentially up to a bound
+fn reconnect_delay(attempt: u32) -> Duration {
+    INITIAL_RECONNECT_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(MAX_RECONNECT_DELAY)
+}
+
+/// Connect to the server, with TLS if the address has the TLS prefix or a trust is given
+async fn connect(addr: &str, tls_trust: &str, codec: Codec) -> anyhow::Result<ServerHandle> {
+    let tls_addr = addr.strip_prefix(TLS_ADDR_PREFIX);
+    if tls_addr.is_none() && tls_trust.trim().is_empty() {
+        let stream = TcpStream::connect(addr).await?;
+
+        return Ok(transport::client::split_tcp_stream_with_codec(
+            stream,
+            codec,
+            DEFAULT_MAX_FRAME_SIZE,
+        ));
+    }
+
+    let addr = tls_addr.unwrap_or(addr);
+    let trust = tls_trust
+        .parse::<ServerTrust>()
+        .context("invalid TLS trust")?;
+    let connector = tls::connector(&trust)?;
+    // the certificate is verified against the host name, without the port and the brackets of IPv6 addresses
+    let host = addr
+        .rsplit_once(':')
+        .map_or(addr, |(host, _port)| host)
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let server_name = tls::ServerName::try_from(host.to_string())
+        .with_context(|| format!("invalid server name '{}'", host))?;
+
     let stream = TcpStream::connect(addr).await?;
-    let (event_stream, command_writer) = transport::client::split_tcp_stream(stream);
+    let stream = connector
+        .connect(server_name, stream)
+        .await
+        .context("TLS handshake failed")?;
+
+    Ok(transport::client::split_stream_with_codec(
+        stream,
+        codec,
+        DEFAULT_MAX_FRAME_SIZE,
+    ))
+}
+
+/// Connects to the server and sends the credentials, either a register, a login or a resume session command
+async fn create_server_handle(
+    addr: &str,
+    tls_trust: &str,
+    credentials: &command::UserCommand,
+) -> anyhow::Result<ServerHandle> {
+    let codec = match std::env::var(CODEC_ENV_VAR) {
+        Ok(codec) => codec.parse::<Codec>()?,
+        Err(_) => Codec::default(),
+    };
+    let (mut event_stream, mut command_writer) = connect(addr, tls_trust, codec).await?;
+
+    // an incompatible server is refused before the credentials are sent to it
+    transport::client::hello(
+        &mut event_stream,
+        &mut command_writer,
+        protocol::CAPABILITIES,
+    )
+    .await
+    .context("could not agree on the protocol with the server")?;
+
+    // the server only replies with a login successful event once the credentials are accepted
+    command_writer
+        .write(credentials)
+        .await
+        .context("could not send credentials")?;
 
     Ok((event_stream, command_writer))
 }
@@ -50,54 +158,164 @@ impl StateStore {
         mut interrupt_rx: broadcast::Receiver<Interrupted>,
     ) -> anyhow::Result<Interrupted> {
         let mut opt_server_handle: Option<ServerHandle> = None;
+        let mut opt_connection_request: Option<ConnectionRequest> = None;
         let mut state = State::default();
+        let mut reconnect_at = Instant::now();
 
         // the initial state once
         self.state_tx.send(state.clone())?;
 
         let mut ticker = tokio::time::interval(Duration::from_secs(1));
+        let mut ping_ticker = tokio::time::interval(PING_INTERVAL);
 
         let result = loop {
-            if let Some((event_stream, command_writer)) = opt_server_handle.as_mut() {
+            if let Some((event_stream, _)) = opt_server_handle.as_mut() {
+                // the commands are written once the select is done, a failed write means the connection is lost
+                let mut commands = Vec::new();
+                let mut connection_lost = false;
+
                 tokio::select! {
                     // Handle the server events as they come in
                     maybe_event = event_stream.next() => match maybe_event {
                         Some(Ok(event)) => {
+                            // answer the server, which pings idle connections to find out whether they are alive
+                            if let event::Event::Ping(ping) = &event {
+                                commands.push(command::UserCommand::Pong(command::PongCommand {
+                                    timestamp: ping.timestamp,
+                                    request_id: None,
+                                }));
+                            }
+
+                            // the rooms joined before the connection was lost have to be joined again after logging in
+                            let rooms_to_rejoin = match (&event, &state.server_connection_status) {
+                                (event::Event::LoginSuccessful(_), ServerConnectionStatus::Reconnecting { .. }) => state.joined_rooms(),
+                                _ => Vec::new(),
+                            };
+
                             state.handle_server_event(&event);
+
+                            match &event {
+                                // the server has forgotten the session while reconnecting, log in again instead
+                                event::Event::CommandError(err) if err.code == event::CommandErrorCode::InvalidResumeToken => {
+                                    if let Some(connection_request) = opt_connection_request.as_ref() {
+                                        commands.push(connection_request.login_command());
+                                    }
+                                },
+                                // joining a room replies with its most recent messages, which reloads its history
+                                event::Event::LoginSuccessful(_) => {
+                                    commands.extend(rooms_to_rejoin.into_iter().map(|room| {
+                                        command::UserCommand::JoinRoom(command::JoinRoomCommand {
+                                            room,
+                                            request_id: None,
+                                        })
+                                    }));
+                                },
+                                _ => (),
+                            }
+
+                            // the credentials were refused, the connection can not be used anymore
+                            if let ServerConnectionStatus::Errored { .. } = state.server_connection_status {
+                                opt_server_handle = None;
+                            }
                         },
-                        // server disconnected, we need to reset the state
-                        None => {
-                            opt_server_handle = None;
-                            state = State::default();
+                        // server disconnected or sent something that can not be read, reconnect if the user was logged in,
+                        // otherwise reset the state
+                        None | Some(Err(_)) => {
+                            connection_lost = true;
                         },
-                        _ => (),
                     },
                     // Handle the actions coming from the UI
                     // and process them to do async operations
                     Some(action) = action_rx.recv() => match action {
+                        // the commands would be refused until the server has accepted the credentials again
+                        action if !matches!(action, Action::Exit)
+                            && matches!(state.server_connection_status, ServerConnectionStatus::Reconnecting { .. }) => (),
                         Action::SendMessage { content } => {
-                            if let Some(active_room) = state.active_room.as_ref() {
-                                command_writer
-                                    .write(&command::UserCommand::SendMessage(
-                                        command::SendMessageCommand {
-                                            room: active_room.clone(),
-                                            content,
-                                        },
-                                    ))
-                                    .await
-                                    .context("could not send message")?;
+                            let direct_message_user_id = state
+                                .active_room
+                                .as_ref()
+                                .and_then(|active_room| state.room_data_map.get(active_room))
+                                .and_then(|room_data| room_data.direct_message_user_id.clone());
+
+                            // messages typed into a direct message conversation go to the other user
+                            if let Some(user_id) = direct_message_user_id {
+                                commands.push(command::UserCommand::SendDirectMessage(
+                                    command::SendDirectMessageCommand {
+                                        user_id,
+                                        content,
+                                        request_id: None,
+                                    },
+                                ));
+                            } else if let Some(active_room) = state.active_room.as_ref() {
+                                commands.push(command::UserCommand::SendMessage(
+                                    command::SendMessageCommand {
+                                        room: active_room.clone(),
+                                        content,
+                                        request_id: None,
+                                    },
+                                ));
                             }
                         },
+                        Action::SendDirectMessage { user_id, content } => {
+                            commands.push(command::UserCommand::SendDirectMessage(command::SendDirectMessageCommand {
+                                user_id,
+                                content,
+                                request_id: None,
+                            }));
+                        },
                         Action::SelectRoom { room } => {
                             if let Some(false) = state.try_set_active_room(room.as_str()).map(|room_data| room_data.has_joined) {
-                                command_writer
-                                    .write(&command::UserCommand::JoinRoom(command::JoinRoomCommand {
-                                        room,
-                                    }))
-                                    .await
-                                    .context("could not join room")?;
+                                commands.push(command::UserCommand::JoinRoom(command::JoinRoomCommand {
+                                    room,
+                                    request_id: None,
+                                }));
                             }
                         },
+                        Action::LoadOlderMessages => {
+                            if let Some((room, before)) = state.take_active_room_history_cursor() {
+                                commands.push(command::UserCommand::GetHistory(command::GetHistoryCommand {
+                                    room,
+                                    before: Some(before),
+                                    limit: None,
+                                    request_id: None,
+                                }));
+                            }
+                        },
+                        Action::TypingStarted => {
+                            if let Some(room) = state.active_joined_room() {
+                                commands.push(command::UserCommand::TypingStarted(command::TypingStartedCommand {
+                                    room: String::from(room),
+                                    request_id: None,
+                                }));
+                            }
+                        },
+                        Action::TypingStopped => {
+                            if let Some(room) = state.active_joined_room() {
+                                commands.push(command::UserCommand::TypingStopped(command::TypingStoppedCommand {
+                                    room: String::from(room),
+                                    request_id: None,
+                                }));
+                            }
+                        },
+                        Action::SetNickname { nickname } => {
+                            commands.push(command::UserCommand::SetNickname(command::SetNicknameCommand {
+                                nickname,
+                                request_id: None,
+                            }));
+                        },
+                        Action::CreateRoom { room, description } => {
+                            commands.push(command::UserCommand::CreateRoom(command::CreateRoomCommand {
+                                room,
+                                description,
+                                request_id: None,
+                            }));
+                        },
+                        Action::DeleteRoom { room } => {
+                            commands.push(command::UserCommand::DeleteRoom(command::DeleteRoomCommand {
+                                room,
+                                request_id: None,
+                            }));
+                        },
                         Action::Exit => {
                             let _ = terminator.terminate(Interrupted::UserInt);
 
@@ -109,6 +327,75 @@ impl StateStore {
                     _ = ticker.tick() => {
                         state.tick_timer();
                     },
+                    // Ping the server to measure the latency, the pong is handled as a server event
+                    _ = ping_ticker.tick() => {
+                        commands.push(command::UserCommand::Ping(command::PingCommand {
+                            timestamp: now_millis(),
+                            request_id: None,
+                        }));
+                    },
+                    // Catch and handle interrupt signal to gracefully shutdown
+                    Ok(interrupted) = interrupt_rx.recv() => {
+                        break interrupted;
+                    }
+                }
+
+                if let Some((_, command_writer)) = opt_server_handle.as_mut() {
+                    for command in commands.iter() {
+                        if command_writer.write(command).await.is_err() {
+                            connection_lost = true;
+                            break;
+                        }
+                    }
+                }
+
+                if connection_lost {
+                    opt_server_handle = None;
+
+                    let addr = opt_connection_request.as_ref().map(|connection_request| connection_request.addr.as_str()).unwrap_or_default();
+                    if let Some(attempt) = state.mark_connection_lost(addr, MAX_RECONNECT_ATTEMPTS) {
+                        reconnect_at = Instant::now() + reconnect_delay(attempt);
+                    }
+                }
+            } else if let (Some(connection_request), ServerConnectionStatus::Reconnecting { .. }) = (
+                opt_connection_request.clone(),
+                &state.server_connection_status,
+            ) {
+                tokio::select! {
+                    // Try to reconnect once the delay of the attempt has passed
+                    _ = tokio::time::sleep_until(reconnect_at) => {
+                        // resuming the session keeps the rooms joined, the events missed in the meantime follow
+                        let credentials = match state.resume_token.clone() {
+                            Some(token) => command::UserCommand::ResumeSession(command::ResumeSessionCommand {
+                                token,
+                                request_id: None,
+                            }),
+                            None => connection_request.login_command(),
+                        };
+
+                        let result = tokio::time::timeout(
+                            RECONNECT_TIMEOUT,
+                            create_server_handle(&connection_request.addr, &connection_request.tls_trust, &credentials),
+                        )
+                        .await;
+                        match result {
+                            // the status stays reconnecting until the server accepts the credentials
+                            Ok(Ok(server_handle)) => {
+                                let _ = opt_server_handle.insert(server_handle);
+                            },
+                            _ => {
+                                if let Some(attempt) = state.mark_connection_lost(&connection_request.addr, MAX_RECONNECT_ATTEMPTS) {
+                                    reconnect_at = Instant::now() + reconnect_delay(attempt);
+                                }
+                            },
+                        }
+                    },
+                    // the other actions need a connection, they are dropped while reconnecting
+                    Some(action) = action_rx.recv() => if let Action::Exit = action {
+                        let _ = terminator.terminate(Interrupted::UserInt);
+
+                        break Interrupted::UserInt;
+                    },
                     // Catch and handle interrupt signal to gracefully shutdown
                     Ok(interrupted) = interrupt_rx.recv() => {
                         break interrupted;
@@ -117,16 +404,33 @@ impl StateStore {
             } else {
                 tokio::select! {
                     Some(action) = action_rx.recv() => match action {
-                        Action::ConnectToServerRequest { addr } => {
+                        Action::ConnectToServerRequest { addr, username, password, register, tls_trust } => {
                             state.mark_connection_request_start();
                             // emit event to re-render any part depending on the connection status
                             self.state_tx.send(state.clone())?;
 
-                            match create_server_handle(&addr).await {
+                            let connection_request = ConnectionRequest {
+                                addr,
+                                tls_trust,
+                                username,
+                                password,
+                            };
+                            let credentials = if register {
+                                command::UserCommand::Register(command::RegisterCommand {
+                                    username: connection_request.username.clone(),
+                                    password: connection_request.password.clone(),
+                                    request_id: None,
+                                })
+                            } else {
+                                connection_request.login_command()
+                            };
+
+                            match create_server_handle(&connection_request.addr, &connection_request.tls_trust, &credentials).await {
                                 Ok(server_handle) => {
                                     // set the server handle and change status for further processing
                                     let _ = opt_server_handle.insert(server_handle);
-                                    state.process_connection_request_result(Ok(addr));
+                                    state.process_connection_request_result(Ok(connection_request.addr.clone()));
+                                    opt_connection_request = Some(connection_request);
                                     // ticker needs to be reset to avoid showing time spent inputting and connecting to the server address
                                     ticker.reset();
                                 },
@@ -17,6 +17,8 @@ pub struct InputBox {
     text: String,
     /// Position of cursor in the editor area.
     cursor_position: usize,
+    /// Render every character as `*`, e.g. for passwords
+    masked: bool,
 }
 
 impl InputBox {
@@ -38,6 +40,10 @@ impl InputBox {
         self.text.is_empty()
     }
 
+    pub fn set_masked(&mut self, masked: bool) {
+        self.masked = masked;
+    }
+
     fn move_cursor_left(&mut self) {
         let cursor_moved_left = self.cursor_position.saturating_sub(1);
         self.cursor_position = self.clamp_cursor(cursor_moved_left);
@@ -87,6 +93,7 @@ impl Component for InputBox {
             //
             text: String::new(),
             cursor_position: 0,
+            masked: false,
         }
     }
 
@@ -133,7 +140,13 @@ pub struct RenderProps {
 
 impl ComponentRender<RenderProps> for InputBox {
     fn render(&self, frame: &mut Frame, props: RenderProps) {
-        let input = Paragraph::new(self.text.as_str())
+        let text = if self.masked {
+            "*".repeat(self.text.chars().count())
+        } else {
+            self.text.clone()
+        };
+
+        let input = Paragraph::new(text)
             .style(Style::default().fg(Color::Yellow))
             .block(
                 Block::default()
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 
+use chrono::{DateTime, Local};
 use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
 use ratatui::{prelude::*, widgets::*, Frame};
 use tokio::sync::mpsc::UnboundedSender;
@@ -50,6 +51,8 @@ impl TryFrom<usize> for Section {
 struct Props {
     /// The logged-in user
     user_id: String,
+    /// Names to display for the users, by user id
+    display_names: HashMap<String, String>,
     /// The currently active room
     active_room: Option<String>,
     /// The timer for the chat page
@@ -58,21 +61,29 @@ struct Props {
     room_data_map: HashMap<String, RoomData>,
     /// Connection status for the current connection
     connection_status: ServerConnectionStatus,
+    /// Round-trip time to the server in milliseconds, once it is measured
+    latency_ms: Option<u64>,
 }
 
 impl From<&State> for Props {
     fn from(state: &State) -> Self {
         Props {
             user_id: state.user_id.clone(),
+            display_names: state.display_names.clone(),
             active_room: state.active_room.clone(),
             timer: state.timer,
             room_data_map: state.room_data_map.clone(),
             connection_status: state.server_connection_status.clone(),
+            latency_ms: state.latency_ms,
         }
     }
 }
 
 const DEFAULT_HOVERED_SECTION: Section = Section::MessageInput;
+/// How many lines a single page up / down moves the message list
+const MESSAGE_SCROLL_STEP: usize = 5;
+/// Older messages are requested once the scroll position gets this close to the oldest loaded message
+const LOAD_OLDER_MESSAGES_THRESHOLD: usize = 20;
 
 /// ChatPage handles the UI and the state of the chat page
 pub struct ChatPage {
@@ -85,6 +96,8 @@ pub struct ChatPage {
     pub active_section: Option<Section>,
     /// Section that is currently hovered
     pub last_hovered_section: Section,
+    /// Number of lines the message list is scrolled up from the latest message
+    pub message_scroll: usize,
     // Child Components
     /// The room list widget that handles the listing of the rooms
     pub room_list: RoomList,
@@ -93,6 +106,15 @@ pub struct ChatPage {
 }
 
 impl ChatPage {
+    /// The name to display for the user, falls back to the user id for users that have not been seen
+    fn display_name<'a>(&'a self, user_id: &'a str) -> &'a str {
+        self.props
+            .display_names
+            .get(user_id)
+            .map(String::as_str)
+            .unwrap_or(user_id)
+    }
+
     fn get_room_data(&self, name: &str) -> Option<&RoomData> {
         self.props.room_data_map.get(name)
     }
@@ -145,6 +167,29 @@ impl ChatPage {
         }
     }
 
+    fn active_room_messages_len(&self) -> usize {
+        self.props
+            .active_room
+            .as_ref()
+            .and_then(|active_room| self.get_room_data(active_room))
+            .map(|room_data| room_data.messages.len())
+            .unwrap_or(0)
+    }
+
+    fn scroll_messages_up(&mut self) {
+        let messages_len = self.active_room_messages_len();
+        self.message_scroll = (self.message_scroll + MESSAGE_SCROLL_STEP).min(messages_len);
+
+        // ask for an older page before the user reaches the top of the loaded messages
+        if self.message_scroll + LOAD_OLDER_MESSAGES_THRESHOLD >= messages_len {
+            let _ = self.action_tx.send(Action::LoadOlderMessages);
+        }
+    }
+
+    fn scroll_messages_down(&mut self) {
+        self.message_scroll = self.message_scroll.saturating_sub(MESSAGE_SCROLL_STEP);
+    }
+
     fn disable_section(&mut self, section: &Section) {
         self.get_section_activation_for_section(section)
             .deactivate();
@@ -165,6 +210,7 @@ impl Component for ChatPage {
             // internal component state
             active_section: None,
             last_hovered_section: DEFAULT_HOVERED_SECTION,
+            message_scroll: 0,
             // child components
             room_list: RoomList::new(state, action_tx.clone()),
             message_input_box: MessageInputBox::new(state, action_tx),
@@ -176,8 +222,17 @@ impl Component for ChatPage {
     where
         Self: Sized,
     {
+        let props = Props::from(state);
+        // scrolling is kept per page, so it is reset when the user switches rooms
+        let message_scroll = if props.active_room == self.props.active_room {
+            self.message_scroll
+        } else {
+            0
+        };
+
         ChatPage {
-            props: Props::from(state),
+            props,
+            message_scroll,
             // propagate the update to the child components
             room_list: self.room_list.move_with_state(state),
             message_input_box: self.message_input_box.move_with_state(state),
@@ -194,6 +249,19 @@ impl Component for ChatPage {
             return;
         }
 
+        // the message list can be scrolled regardless of the active section
+        match key.code {
+            KeyCode::PageUp => {
+                self.scroll_messages_up();
+                return;
+            }
+            KeyCode::PageDown => {
+                self.scroll_messages_down();
+                return;
+            }
+            _ => (),
+        }
+
         let active_section = self.active_section.clone();
 
         match active_section {
@@ -241,6 +309,25 @@ fn calculate_list_offset(height: u16, items_len: usize) -> usize {
     items_len.saturating_sub(height as usize - 2)
 }
 
+/// Describes who is typing in a room given their names, empty if nobody is
+fn typing_text(mut names: Vec<&str>) -> String {
+    names.sort();
+
+    match names.as_slice() {
+        [] => String::new(),
+        [name] => format!("{} is typing…", name),
+        [first, second] => format!("{} and {} are typing…", first, second),
+        _ => String::from("Several people are typing…"),
+    }
+}
+
+/// Formats the server timestamp of a message as local time
+fn format_message_time(timestamp: u64) -> String {
+    DateTime::from_timestamp_millis(timestamp as i64)
+        .map(|date_time| date_time.with_timezone(&Local).format("%H:%M").to_string())
+        .unwrap_or_default()
+}
+
 impl ComponentRender<()> for ChatPage {
     fn render(&self, frame: &mut Frame, _props: ()) {
         let [left, middle, right] = *Layout::default()
@@ -260,7 +347,7 @@ impl ComponentRender<()> for ChatPage {
 
         let [container_room_list, container_user_info] = *Layout::default()
             .direction(Direction::Vertical)
-            .constraints([Constraint::Min(1), Constraint::Length(6)].as_ref())
+            .constraints([Constraint::Min(1), Constraint::Length(7)].as_ref())
             .split(left)
         else {
             panic!("The left layout should have 2 chunks")
@@ -275,9 +362,17 @@ impl ComponentRender<()> for ChatPage {
         );
 
         let user_info = Paragraph::new(Text::from(vec![
-            Line::from(format!("User: @{}", self.props.user_id)),
+            Line::from(format!(
+                "User: {} (@{})",
+                self.display_name(&self.props.user_id),
+                self.props.user_id
+            )),
             Line::from(format!("Chatting for: {} secs", self.props.timer)),
             Line::from(format!("Server: {}", self.props.connection_status)),
+            Line::from(match self.props.latency_ms {
+                Some(latency_ms) => format!("Latency: {} ms", latency_ms),
+                None => String::from("Latency: measuring..."),
+            }),
         ])).wrap(Wrap { trim: false })
         .block(
             Block::default()
@@ -286,19 +381,21 @@ impl ComponentRender<()> for ChatPage {
         );
         frame.render_widget(user_info, container_user_info);
 
-        let [container_highlight, container_messages, container_input] = *Layout::default()
-            .direction(Direction::Vertical)
-            .constraints(
-                [
-                    Constraint::Length(3),
-                    Constraint::Min(1),
-                    Constraint::Length(3),
-                ]
-                .as_ref(),
-            )
-            .split(middle)
+        let [container_highlight, container_messages, container_typing, container_input] =
+            *Layout::default()
+                .direction(Direction::Vertical)
+                .constraints(
+                    [
+                        Constraint::Length(3),
+                        Constraint::Min(1),
+                        Constraint::Length(1),
+                        Constraint::Length(3),
+                    ]
+                    .as_ref(),
+                )
+                .split(middle)
         else {
-            panic!("The middle layout should have 3 chunks")
+            panic!("The middle layout should have 4 chunks")
         };
 
         let top_line = if let Some(room_data) = self
@@ -307,9 +404,15 @@ impl ComponentRender<()> for ChatPage {
             .as_ref()
             .and_then(|active_room| self.get_room_data(active_room))
         {
+            let room_tag = if room_data.direct_message_user_id.is_some() {
+                room_data.name.clone()
+            } else {
+                format!("#{}", room_data.name)
+            };
+
             Line::from(vec![
                 "on ".into(),
-                Span::from(format!("#{}", room_data.name)).bold(),
+                Span::from(room_tag).bold(),
                 " for ".into(),
                 Span::from(format!(r#""{}""#, room_data.description)).italic(),
             ])
@@ -329,17 +432,28 @@ impl ComponentRender<()> for ChatPage {
             self.get_room_data(active_room)
                 .map(|room_data| {
                     let message_offset =
-                        calculate_list_offset(container_messages.height, room_data.messages.len());
+                        calculate_list_offset(container_messages.height, room_data.messages.len())
+                            .saturating_sub(self.message_scroll);
 
                     room_data
                         .messages
-                        .asc_iter()
+                        .iter()
                         .skip(message_offset)
                         .map(|mbi| {
                             let line = match mbi {
-                                MessageBoxItem::Message { user_id, content } => {
-                                    Line::from(Span::raw(format!("@{}: {}", user_id, content)))
-                                }
+                                MessageBoxItem::Message {
+                                    timestamp,
+                                    user_id,
+                                    content,
+                                    ..
+                                } => Line::from(vec![
+                                    Span::raw(format_message_time(*timestamp)).dim(),
+                                    Span::raw(format!(
+                                        " {}: {}",
+                                        self.display_name(user_id),
+                                        content
+                                    )),
+                                ]),
                                 MessageBoxItem::Notification(content) => {
                                     Line::from(Span::raw(content.clone()).italic())
                                 }
@@ -358,6 +472,26 @@ impl ComponentRender<()> for ChatPage {
             List::new(messages).block(Block::default().borders(Borders::ALL).title("Messages"));
         frame.render_widget(messages, container_messages);
 
+        let typing = self
+            .props
+            .active_room
+            .as_ref()
+            .and_then(|active_room| self.get_room_data(active_room))
+            .map(|room_data| {
+                typing_text(
+                    room_data
+                        .typing_users
+                        .iter()
+                        .map(|user_id| self.display_name(user_id))
+                        .collect(),
+                )
+            })
+            .unwrap_or_default();
+        frame.render_widget(
+            Paragraph::new(Span::raw(typing).italic().dim()),
+            container_typing,
+        );
+
         self.message_input_box.render(
             frame,
             message_input_box::RenderProps {
@@ -395,7 +529,10 @@ impl ComponentRender<()> for ChatPage {
                             .iter()
                             .skip(users_offset)
                             .map(|user_id| {
-                                ListItem::new(Line::from(Span::raw(format!("@{user_id}"))))
+                                ListItem::new(Line::from(vec![
+                                    Span::raw(self.display_name(user_id)),
+                                    Span::raw(format!(" @{user_id}")).dim(),
+                                ]))
                             })
                             .collect::<Vec<ListItem<'_>>>(),
                         room_users_len,
@@ -442,6 +579,10 @@ impl HasUsageInfo for ChatPage {
                         keys: vec!["←".into(), "→".into()],
                         description: "to hover widgets".into(),
                     },
+                    UsageInfoLine {
+                        keys: vec!["PgUp".into(), "PgDn".into()],
+                        description: "to scroll messages".into(),
+                    },
                     UsageInfoLine {
                         keys: vec!["Enter".into()],
                         description: format!(
@@ -1,3 +1,5 @@
+use std::time::{Duration, Instant};
+
 use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
 use ratatui::{
     prelude::Rect,
@@ -29,12 +31,22 @@ impl From<&State> for Props {
     }
 }
 
+/// Messages starting with these prefixes are turned into commands instead of being sent
+const SET_NICKNAME_PREFIX: &str = "/nick ";
+const CREATE_ROOM_PREFIX: &str = "/create ";
+const DELETE_ROOM_PREFIX: &str = "/delete ";
+const DIRECT_MESSAGE_PREFIX: &str = "/dm ";
+/// Typing is sent again this often while the user keeps typing, the server ends it after a few seconds without it
+const TYPING_REFRESH_INTERVAL: Duration = Duration::from_secs(3);
+
 pub struct MessageInputBox {
     action_tx: UnboundedSender<Action>,
     /// State Mapped MessageInputBox Props
     props: Props,
     // Internal State for the Component
     pub input_box: InputBox,
+    /// When the typing was last sent, if the user is typing
+    typing_sent_at: Option<Instant>,
 }
 
 impl MessageInputBox {
@@ -43,13 +55,72 @@ impl MessageInputBox {
             return;
         }
 
+        let action = parse_input(self.input_box.text());
+
+        // a message ends the typing on its own
+        if let Action::SendMessage { .. } = action {
+            self.typing_sent_at = None;
+        } else {
+            self.stop_typing();
+        }
+
         // TODO: handle the error scenario
-        let _ = self.action_tx.send(Action::SendMessage {
-            content: String::from(self.input_box.text()),
-        });
+        let _ = self.action_tx.send(action);
 
         self.input_box.reset();
     }
+
+    /// Tells the room whether the user is typing a message, commands do not count as typing
+    fn update_typing(&mut self) {
+        let typing = !self.input_box.is_empty() && !self.input_box.text().starts_with('/');
+
+        if !typing {
+            self.stop_typing();
+        } else if self
+            .typing_sent_at
+            .is_none_or(|sent_at| sent_at.elapsed() >= TYPING_REFRESH_INTERVAL)
+        {
+            let _ = self.action_tx.send(Action::TypingStarted);
+            self.typing_sent_at = Some(Instant::now());
+        }
+    }
+
+    fn stop_typing(&mut self) {
+        if self.typing_sent_at.take().is_some() {
+            let _ = self.action_tx.send(Action::TypingStopped);
+        }
+    }
+}
+
+/// Turns the text of the input box into the action it stands for
+fn parse_input(text: &str) -> Action {
+    if let Some(nickname) = text.strip_prefix(SET_NICKNAME_PREFIX) {
+        Action::SetNickname {
+            nickname: String::from(nickname.trim()),
+        }
+    } else if let Some(args) = text.strip_prefix(CREATE_ROOM_PREFIX) {
+        let (room, description) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+
+        Action::CreateRoom {
+            room: String::from(room),
+            description: String::from(description.trim()),
+        }
+    } else if let Some(room) = text.strip_prefix(DELETE_ROOM_PREFIX) {
+        Action::DeleteRoom {
+            room: String::from(room.trim()),
+        }
+    } else if let Some(args) = text.strip_prefix(DIRECT_MESSAGE_PREFIX) {
+        let (user_id, content) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+
+        Action::SendDirectMessage {
+            user_id: String::from(user_id.trim_start_matches('@')),
+            content: String::from(content.trim()),
+        }
+    } else {
+        Action::SendMessage {
+            content: String::from(text),
+        }
+    }
 }
 
 impl Component for MessageInputBox {
@@ -59,6 +130,7 @@ impl Component for MessageInputBox {
             props: Props::from(state),
             //
             input_box: InputBox::new(state, action_tx),
+            typing_sent_at: None,
         }
     }
 
@@ -86,6 +158,8 @@ impl Component for MessageInputBox {
 
             if key.code == KeyCode::Enter {
                 self.submit_message();
+            } else {
+                self.update_typing();
             }
         }
     }
@@ -96,6 +170,7 @@ impl SectionActivation for MessageInputBox {
 
     fn deactivate(&mut self) {
         self.input_box.reset();
+        self.stop_typing();
     }
 }
 
@@ -131,7 +206,13 @@ impl HasUsageInfo for MessageInputBox {
             }
         } else {
             UsageInfo {
-                description: Some("Type your message to send a message to the active room".into()),
+                description: Some(
+                    "Type your message to send a message to the active room. \
+                    Use /nick <name> to change your nickname, \
+                    /create <room> <description> or /delete <room> to manage rooms, \
+                    /dm <user> <message> to message a user directly"
+                        .into(),
+                ),
                 lines: vec![
                     UsageInfoLine {
                         keys: vec!["Esc".into()],
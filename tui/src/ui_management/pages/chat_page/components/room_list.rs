@@ -18,9 +18,8 @@ use crate::ui_management::components::{Component, ComponentRender};
 
 pub struct RoomState {
     pub name: String,
-    pub description: String,
-    pub has_joined: bool,
     pub has_unread: bool,
+    pub is_direct_message: bool,
 }
 
 struct Props {
@@ -37,13 +36,15 @@ impl From<&State> for Props {
             .iter()
             .map(|(name, room_data)| RoomState {
                 name: name.clone(),
-                description: room_data.description.clone(),
-                has_joined: room_data.has_joined,
                 has_unread: room_data.has_unread,
+                is_direct_message: room_data.direct_message_user_id.is_some(),
             })
             .collect::<Vec<RoomState>>();
 
-        rooms.sort_by(|room_a, room_b| room_a.name.cmp(&room_b.name));
+        // direct message conversations are listed after the rooms
+        rooms.sort_by(|room_a, room_b| {
+            (room_a.is_direct_message, &room_a.name).cmp(&(room_b.is_direct_message, &room_b.name))
+        });
 
         Self {
             rooms,
@@ -64,6 +65,10 @@ pub struct RoomList {
 
 impl RoomList {
     fn next(&mut self) {
+        if self.props.rooms.is_empty() {
+            return;
+        }
+
         let i = match self.list_state.selected() {
             Some(i) => {
                 if i >= self.props.rooms.len() - 1 {
@@ -78,6 +83,10 @@ impl RoomList {
     }
 
     fn previous(&mut self) {
+        if self.props.rooms.is_empty() {
+            return;
+        }
+
         let i = match self.list_state.selected() {
             Some(i) => {
                 if i == 0 {
@@ -125,8 +134,20 @@ impl Component for RoomList {
     where
         Self: Sized,
     {
+        let props = Props::from(state);
+        let mut list_state = self.list_state;
+
+        // rooms can be deleted while one of them is selected
+        if list_state
+            .selected()
+            .is_some_and(|selected| selected >= props.rooms.len())
+        {
+            list_state.select(props.rooms.len().checked_sub(1));
+        }
+
         Self {
-            props: Props::from(state),
+            props,
+            list_state,
             ..self
         }
     }
@@ -194,8 +215,15 @@ impl ComponentRender<RenderProps> for RoomList {
             .rooms()
             .iter()
             .map(|room_state| {
+                // the name of a direct message conversation already starts with '@'
+                let prefix = if room_state.is_direct_message {
+                    ""
+                } else {
+                    "#"
+                };
                 let room_tag = format!(
-                    "#{}{}",
+                    "{}{}{}",
+                    prefix,
                     room_state.name,
                     if room_state.has_unread { "*" } else { "" }
                 );
@@ -251,7 +279,7 @@ impl HasUsageInfo for RoomList {
                 },
                 UsageInfoLine {
                     keys: vec!["Enter".into()],
-                    description: "to join room".into(),
+                    description: "to join room or open conversation".into(),
                 },
             ],
         }
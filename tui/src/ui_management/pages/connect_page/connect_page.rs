@@ -26,26 +26,84 @@ impl From<&State> for Props {
     }
 }
 
-/// ConnectPage handles the connection to the server
+/// Input box of the connect page which receives the key events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Address,
+    TlsTrust,
+    Username,
+    Password,
+}
+
+impl Field {
+    fn next(self) -> Self {
+        match self {
+            Field::Address => Field::TlsTrust,
+            Field::TlsTrust => Field::Username,
+            Field::Username => Field::Password,
+            Field::Password => Field::Address,
+        }
+    }
+
+    fn previous(self) -> Self {
+        match self {
+            Field::Address => Field::Password,
+            Field::TlsTrust => Field::Address,
+            Field::Username => Field::TlsTrust,
+            Field::Password => Field::Username,
+        }
+    }
+}
+
+/// ConnectPage handles the connection to the server and the authentication of the user
 pub struct ConnectPage {
     /// Action sender
     pub action_tx: UnboundedSender<Action>,
     // Mapped Props from State
     props: Props,
+    /// Currently focused input box
+    focused_field: Field,
     // Internal Components
     input_box: InputBox,
+    tls_trust_input_box: InputBox,
+    username_input_box: InputBox,
+    password_input_box: InputBox,
 }
 
 impl ConnectPage {
-    fn connect_to_server(&mut self) {
-        if self.input_box.is_empty() {
+    fn connect_to_server(&mut self, register: bool) {
+        if self.input_box.is_empty()
+            || self.username_input_box.is_empty()
+            || self.password_input_box.is_empty()
+        {
             return;
         }
 
         let _ = self.action_tx.send(Action::ConnectToServerRequest {
             addr: self.input_box.text().to_string(),
+            username: self.username_input_box.text().to_string(),
+            password: self.password_input_box.text().to_string(),
+            register,
+            tls_trust: self.tls_trust_input_box.text().to_string(),
         });
     }
+
+    fn focused_input_box_mut(&mut self) -> &mut InputBox {
+        match self.focused_field {
+            Field::Address => &mut self.input_box,
+            Field::TlsTrust => &mut self.tls_trust_input_box,
+            Field::Username => &mut self.username_input_box,
+            Field::Password => &mut self.password_input_box,
+        }
+    }
+
+    fn border_color(&self, field: Field) -> Color {
+        if self.focused_field == field {
+            Color::Yellow
+        } else {
+            Color::Reset
+        }
+    }
 }
 
 const DEFAULT_SERVER_ADDR: &str = "localhost:8080";
@@ -57,13 +115,19 @@ impl Component for ConnectPage {
     {
         let mut input_box = InputBox::new(state, action_tx.clone());
         input_box.set_text(DEFAULT_SERVER_ADDR);
+        let mut password_input_box = InputBox::new(state, action_tx.clone());
+        password_input_box.set_masked(true);
 
         ConnectPage {
             action_tx: action_tx.clone(),
             //
             props: Props::from(state),
+            focused_field: Field::Username,
             //
             input_box,
+            tls_trust_input_box: InputBox::new(state, action_tx.clone()),
+            username_input_box: InputBox::new(state, action_tx.clone()),
+            password_input_box,
         }
         .move_with_state(state)
     }
@@ -83,23 +147,32 @@ impl Component for ConnectPage {
     }
 
     fn handle_key_event(&mut self, key: KeyEvent) {
-        self.input_box.handle_key_event(key);
-
         if key.kind != KeyEventKind::Press {
             return;
         }
 
         match key.code {
             KeyCode::Enter => {
-                self.connect_to_server();
+                self.connect_to_server(false);
+            }
+            KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.connect_to_server(true);
             }
-            KeyCode::Char('q') => {
+            KeyCode::Tab | KeyCode::Down => {
+                self.focused_field = self.focused_field.next();
+            }
+            KeyCode::BackTab | KeyCode::Up => {
+                self.focused_field = self.focused_field.previous();
+            }
+            KeyCode::Esc => {
                 let _ = self.action_tx.send(Action::Exit);
             }
             KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
                 let _ = self.action_tx.send(Action::Exit);
             }
-            _ => {}
+            _ => {
+                self.focused_input_box_mut().handle_key_event(key);
+            }
         }
     }
 }
@@ -136,11 +209,14 @@ impl ComponentRender<()> for ConnectPage {
             panic!("The horizontal layout should have 3 chunks")
         };
 
-        let [container_addr_input, container_help_text, container_error_message] =
+        let [container_addr_input, container_tls_trust_input, container_username_input, container_password_input, container_help_text, container_error_message] =
             *Layout::default()
                 .direction(Direction::Vertical)
                 .constraints(
                     [
+                        Constraint::Length(3),
+                        Constraint::Length(3),
+                        Constraint::Length(3),
                         Constraint::Length(3),
                         Constraint::Length(3),
                         Constraint::Min(1),
@@ -149,24 +225,61 @@ impl ComponentRender<()> for ConnectPage {
                 )
                 .split(both_centered)
         else {
-            panic!("The left layout should have 3 chunks")
+            panic!("The left layout should have 6 chunks")
         };
 
         self.input_box.render(
             frame,
             input_box::RenderProps {
-                title: "Server Host and Port".into(),
+                title: "Server Host and Port, tls://host:port for TLS".into(),
                 area: container_addr_input,
-                border_color: Color::Yellow,
-                show_cursor: true,
+                border_color: self.border_color(Field::Address),
+                show_cursor: self.focused_field == Field::Address,
+            },
+        );
+        self.tls_trust_input_box.render(
+            frame,
+            input_box::RenderProps {
+                title: "TLS CA File or sha256: Pin (optional)".into(),
+                area: container_tls_trust_input,
+                border_color: self.border_color(Field::TlsTrust),
+                show_cursor: self.focused_field == Field::TlsTrust,
+            },
+        );
+        self.username_input_box.render(
+            frame,
+            input_box::RenderProps {
+                title: "Username".into(),
+                area: container_username_input,
+                border_color: self.border_color(Field::Username),
+                show_cursor: self.focused_field == Field::Username,
+            },
+        );
+        self.password_input_box.render(
+            frame,
+            input_box::RenderProps {
+                title: "Password".into(),
+                area: container_password_input,
+                border_color: self.border_color(Field::Password),
+                show_cursor: self.focused_field == Field::Password,
             },
         );
 
-        let help_text = Paragraph::new(Text::from(Line::from(vec![
-            "Press ".into(),
-            "<Enter>".bold(),
-            " to connect".into(),
-        ])));
+        let help_text = Paragraph::new(Text::from(vec![
+            Line::from(vec![
+                "Press ".into(),
+                "<Enter>".bold(),
+                " to log in, ".into(),
+                "<Ctrl+R>".bold(),
+                " to register".into(),
+            ]),
+            Line::from(vec![
+                "<Tab>".bold(),
+                " to switch fields, ".into(),
+                "<Esc>".bold(),
+                " to quit".into(),
+            ]),
+        ]));
         frame.render_widget(help_text, container_help_text);
 
         let error_message = Paragraph::new(if let Some(err) = self.props.error_message.as_ref() {
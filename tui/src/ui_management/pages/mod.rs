@@ -24,7 +24,8 @@ impl From<&State> for Props {
     fn from(state: &State) -> Self {
         Props {
             active_page: match state.server_connection_status {
-                ServerConnectionStatus::Connected { .. } => ActivePage::ChatPage,
+                ServerConnectionStatus::Connected { .. }
+                | ServerConnectionStatus::Reconnecting { .. } => ActivePage::ChatPage,
                 _ => ActivePage::ConnectPage,
             },
         }